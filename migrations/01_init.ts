@@ -1,12 +1,20 @@
 /* eslint-disable @typescript-eslint/no-floating-promises */
 import * as anchor from "@coral-xyz/anchor";
 import { Program, web3, Idl } from "@coral-xyz/anchor";
+import { createMint } from "@solana/spl-token";
 import idlJson from "../target/idl/cipherpay_anchor.json";
 
+const CONFIG_SEED = Buffer.from("config");
 const ROOT_CACHE_SEED = Buffer.from("root_cache");
 const TREE_SEED = Buffer.from("tree");
+// Pools start at tree_id 0 (u32 LE in the seeds); `rolloverTree` opens the next one when full.
+const GENESIS_TREE_ID = Buffer.alloc(4);
 const DEFAULT_DEPTH = Number(process.env.CP_TREE_DEPTH ?? 16);
+// Roots kept per tree (<= 212 at init; grow later with `resizeRootCache`).
+const ROOT_CAPACITY = Number(process.env.CP_ROOT_CAPACITY ?? 64);
 const GENESIS_ROOT_OVERRIDE = process.env.CP_GENESIS_ROOT ?? "";
+// Pools are per mint; reuse CP_TOKEN_MINT or create a fresh 0-decimals test mint.
+const TOKEN_MINT_OVERRIDE = process.env.CP_TOKEN_MINT ?? "";
 
 // ---- helpers (no BigInt literals; avoid ES2020 requirement) ----
 function toLeBytes32(n: bigint): Uint8Array {
@@ -56,10 +64,54 @@ export default async function run(provider: anchor.AnchorProvider) {
   console.log("⚙️  network:", provider.connection.rpcEndpoint);
   console.log("🧭 programId:", program.programId.toBase58());
 
-  const [treePda] = web3.PublicKey.findProgramAddressSync([TREE_SEED], program.programId);
-  const [rootCachePda] = web3.PublicKey.findProgramAddressSync([ROOT_CACHE_SEED], program.programId);
+  // ---- Initialize ProgramConfig (upgrade authority only; admin = CP_ADMIN or wallet) ----
+  const [configPda] = web3.PublicKey.findProgramAddressSync([CONFIG_SEED], program.programId);
+  const [programDataPda] = web3.PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+  );
+  const admin = process.env.CP_ADMIN ? new web3.PublicKey(process.env.CP_ADMIN) : provider.wallet.publicKey;
+  try {
+    console.log("📦 initializeConfig...");
+    await program.methods
+      .initializeConfig(admin)
+      .accountsPartial({
+        config: configPda,
+        authority: provider.wallet.publicKey,
+        program: program.programId,
+        programData: programDataPda,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    console.log("✅ config =", configPda.toBase58(), "admin =", admin.toBase58());
+  } catch (e: any) {
+    const msg = String(e?.message ?? e);
+    if (!/already.*in use/i.test(msg)) throw e;
+    console.log("ℹ️ config already exists:", configPda.toBase58());
+  }
+
+  // ---- pick the pool mint ----
+  let tokenMint: web3.PublicKey;
+  if (TOKEN_MINT_OVERRIDE) {
+    tokenMint = new web3.PublicKey(TOKEN_MINT_OVERRIDE);
+    console.log("🪙 using CP_TOKEN_MINT:", tokenMint.toBase58());
+  } else {
+    const payer = (provider.wallet as any).payer as web3.Keypair;
+    tokenMint = await createMint(provider.connection, payer, payer.publicKey, null, 0);
+    console.log("🪙 created pool mint:", tokenMint.toBase58());
+  }
+
+  const [treePda] = web3.PublicKey.findProgramAddressSync(
+    [TREE_SEED, tokenMint.toBuffer(), GENESIS_TREE_ID],
+    program.programId
+  );
+  const [rootCachePda] = web3.PublicKey.findProgramAddressSync(
+    [ROOT_CACHE_SEED, tokenMint.toBuffer(), GENESIS_TREE_ID],
+    program.programId
+  );
 
   // ---- compute genesis root ----
+  // The program rebuilds the empty-tree frontier on-chain and rejects any other root.
   const depth = DEFAULT_DEPTH;
   let genesisRoot: Uint8Array;
   if (GENESIS_ROOT_OVERRIDE) {
@@ -71,44 +123,29 @@ export default async function run(provider: anchor.AnchorProvider) {
     console.log("🌱 computed zero-tree root:", "0x" + Buffer.from(genesisRoot).reverse().toString("hex"));
   }
 
-  // ---- Initialize TreeState (idempotent-ish) ----
+  // ---- Initialize the per-mint pool: TreeState + Root Cache (idempotent-ish) ----
   try {
-    console.log("📦 initializeTreeState...");
+    console.log("📦 initializePool...");
     await program.methods
-      .initializeTreeState(depth, Array.from(genesisRoot))
+      .initializePool(depth, Array.from(genesisRoot), ROOT_CAPACITY)
       .accountsPartial({
+        config: configPda,
         tree: treePda,
-        authority: provider.wallet.publicKey,
-        systemProgram: web3.SystemProgram.programId,
-      })
-      .rpc();
-    console.log("✅ tree =", treePda.toBase58());
-  } catch (e: any) {
-    const msg = String(e?.message ?? e);
-    if (!/already.*in use/i.test(msg)) throw e;
-    console.log("ℹ️ tree already exists:", treePda.toBase58());
-  }
-
-  // ---- Initialize Root Cache (PDA; zero-copy) ----
-  // This uses `#[account(init, space=8+...)]` on an AccountLoader in Rust.
-  try {
-    console.log("📦 initializeRootCache (PDA)...");
-    await program.methods
-      .initializeRootCache()
-      .accountsPartial({
         rootCache: rootCachePda,
+        tokenMint,
         authority: provider.wallet.publicKey,
         systemProgram: web3.SystemProgram.programId,
       })
       .rpc();
+    console.log("✅ tree =", treePda.toBase58());
     console.log("✅ root_cache =", rootCachePda.toBase58());
   } catch (e: any) {
     const msg = String(e?.message ?? e);
-    // If you prefer strict "first time only", drop this branch.
     if (!/already.*in use/i.test(msg)) throw e;
-    console.log("ℹ️ root_cache already exists:", rootCachePda.toBase58());
+    console.log("ℹ️ pool already exists for mint:", tokenMint.toBase58());
   }
 
+  console.log(`👉 export CP_TOKEN_MINT=${tokenMint.toBase58()}`);
   console.log("🎉 init complete");
 }
 
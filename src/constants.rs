@@ -7,11 +7,29 @@ use anchor_lang::pubkey;            // brings the `pubkey!` macro into scope
 // PDA seeds (yours, kept)
 // =======================
 
-pub const PROGRAM_ADMIN: Pubkey = pubkey!("56nPWpjBLbh1n8vvUdCYGmg3dS5zNwLW9UhCg4MMpBmN");
+/// Program config PDA (admin / governance): seeds = [b"config"]
+pub const CONFIG_SEED: &[u8] = b"config";
 
 /// Deposit marker PDA: seeds = [b"deposit", deposit_hash]
 pub const DEPOSIT_MARKER_SEED: &[u8] = b"deposit";
 
+/// Two-phase deposit escrow PDA: seeds = [b"pending_deposit", deposit_hash]
+pub const PENDING_DEPOSIT_SEED: &[u8] = b"pending_deposit";
+
+/// Slots a pending deposit must wait before it can be refunded (~10 minutes).
+pub const PENDING_DEPOSIT_TIMEOUT_SLOTS: u64 = 1_500;
+
+/// Per-mint pool limits PDA (deposit / withdraw size limits, epoch outflow cap):
+/// seeds = [b"pool_config", mint]
+pub const POOL_CONFIG_SEED: &[u8] = b"pool_config";
+
+/// Time-locked large withdrawal PDA: seeds = [b"pending_withdrawal", nullifier]
+pub const PENDING_WITHDRAWAL_SEED: &[u8] = b"pending_withdrawal";
+
+/// Audit attestation PDA (optional result of `verify_audit_proof`):
+/// seeds = [b"audit", auditor, audit_nonce]
+pub const AUDIT_ATTESTATION_SEED: &[u8] = b"audit";
+
 /// Vault authority PDA (canonical owner of the vault ATA):
 /// seeds = [b"vault", mint]
 pub const VAULT_SEED: &[u8] = b"vault";
@@ -20,14 +38,54 @@ pub const VAULT_SEED: &[u8] = b"vault";
 /// seeds = [b"nullifier", nullifier_bytes]
 pub const NULLIFIER_SEED: &[u8] = b"nullifier";
 
-/// Root cache PDA (ring buffer or single root, depending on your state design):
-/// seeds = [b"root_cache", mint] (or global, if you prefer)
+/// Compact nullifier set shard PDA (sorted nullifiers sharing a first byte):
+/// seeds = [b"nf_shard", [prefix]]
+pub const NULLIFIER_SHARD_SEED: &[u8] = b"nf_shard";
+
+/// Nullifier slots a shard is created with / grows by when full (paid by the spender).
+pub const NULLIFIER_SHARD_GROWTH: u32 = 64;
+
+/// Processed deposit-hash shard PDA (sorted hashes of closed deposit markers, same
+/// layout as a nullifier shard): seeds = [b"dep_shard", [prefix]]
+pub const PROCESSED_DEPOSITS_SEED: &[u8] = b"dep_shard";
+
+/// Root cache PDA (ring buffer of recent roots for one tree of a pool):
+/// seeds = [b"root_cache", mint, tree_id_le_u32]
 pub const ROOT_CACHE_SEED: &[u8] = b"root_cache";
 
+/// Tree state PDA (current root + next leaf index for one tree of a pool):
+/// seeds = [b"tree", mint, tree_id_le_u32]
 pub const TREE_SEED: &[u8] = b"tree";
 
-/// How many historical roots to store if you keep a ring-buffer cache.
-pub const MAX_ROOTS: usize = 64;
+/// Pending-commitment queue PDA of a tree in sequencer mode:
+/// seeds = [b"queue", mint, tree_id_le_u32]
+pub const COMMITMENT_QUEUE_SEED: &[u8] = b"queue";
+
+/// Largest commitment queue (created by CPI, so it must fit in 10 KiB).
+pub const MAX_COMMITMENT_QUEUE_CAPACITY: u32 = 256;
+
+/// On-chain verifying key PDA (upgradable circuits):
+/// seeds = [b"vk", [circuit_id], version_le_u16]
+pub const VK_SEED: &[u8] = b"vk";
+
+/// Largest root cache `rollover_tree` creates (by CPI, so it must fit in 10 KiB);
+/// a tree whose cache was grown past it gets this many, the rest via `resize_root_cache`.
+pub const MAX_ROLLOVER_ROOT_CAPACITY: u32 = 212;
+
+/// Largest root history a cache may be grown to (40 bytes per root).
+pub const MAX_ROOT_CAPACITY: u32 = 4096;
+
+/// Deepest supported Merkle tree (leaf indices are u32).
+/// A full tree is rolled over to `tree_id + 1` (see `rollover_tree`).
+pub const MAX_TREE_DEPTH: u8 = 32;
+
+/// Domain tag hashed with a mint to get its `tokenId` field element
+/// (see `utils::token_id_for`).
+pub const TOKEN_ID_DOMAIN: &[u8] = b"cipherpay:token_id";
+
+/// Domain tag hashed with the recipient token account to get a withdraw's
+/// `recipientWalletPubKey` field element (see `utils::recipient_wallet_field`).
+pub const RECIPIENT_WALLET_DOMAIN: &[u8] = b"cipherpay:recipient_ata";
 
 // ==================================
 // Groth16 / BN254 byte-size helpers
@@ -47,16 +105,17 @@ pub const PROOF_BYTES_LEN: usize = G1_BYTES + G2_BYTES + G1_BYTES; // 256
 // Public-signal counts (must match your Circom circuits)
 // =====================================================
 
-/// deposit.circom publicSignals count:
-/// [newCommitment, ownerCipherPayPubKey, newMerkleRoot, newNextLeafIndex, amount, depositHash, oldMerkleRoot]
-pub const NPUB_DEPOSIT: usize = 7;
+/// deposit.circom publicSignals count (the commitment is appended on-chain):
+/// [newCommitment, ownerCipherPayPubKey, amount, depositHash, tokenId]
+pub const NPUB_DEPOSIT: usize = 5;
 
 /// withdraw.circom publicSignals count:
-/// [nullifier, merkleRoot, recipientWalletPubKey, amount, tokenId]
-pub const NPUB_WITHDRAW: usize = 5;
+/// [nullifier, merkleRoot, recipientOwnerLo, recipientOwnerHi, recipientWalletPubKey,
+///  amount, tokenId, fee, relayerLo, relayerHi]
+pub const NPUB_WITHDRAW: usize = 10;
 
-/// transfer.circom publicSignals count:
-/// [outC1, outC2, nullifier, merkleRoot, newRoot1, newRoot2, newNextIdx, encNote1Hash, encNote2Hash]
+/// transfer.circom publicSignals count (outputs are appended on-chain):
+/// [outC1, outC2, nullifier, merkleRoot, encNote1Hash, encNote2Hash, fee, relayerLo, relayerHi]
 pub const NPUB_TRANSFER: usize = 9;
 
 // =====================================
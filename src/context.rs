@@ -3,71 +3,476 @@
 
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{Mint,Token, TokenAccount};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
-use crate::constants::{DEPOSIT_MARKER_SEED, NULLIFIER_SEED, VAULT_SEED, TREE_SEED, ROOT_CACHE_SEED};
+use crate::constants::{AUDIT_ATTESTATION_SEED, MAX_ROLLOVER_ROOT_CAPACITY, COMMITMENT_QUEUE_SEED, MAX_COMMITMENT_QUEUE_CAPACITY, CONFIG_SEED, VK_SEED, DEPOSIT_MARKER_SEED, PENDING_DEPOSIT_SEED, PENDING_WITHDRAWAL_SEED, NULLIFIER_SEED, POOL_CONFIG_SEED, VAULT_SEED, TREE_SEED, ROOT_CACHE_SEED};
+use crate::error::CipherPayError;
+use crate::program::CipherpayAnchor;
 use crate::state::*;
 
-/// Initialize the global Merkle tree state (one per deployment/cluster)
+/// One-time creation of the program config. Only the program's upgrade authority
+/// may call it, so a third party cannot front-run the deployment and claim admin.
 #[derive(Accounts)]
-pub struct InitializeTreeState<'info> {
+pub struct InitializeConfig<'info> {
     #[account(
         init,
         payer = authority,
-        space = 8 + TreeState::INIT_SPACE,   // keep whichever constant your state defines
-        seeds = [TREE_SEED],
+        space = ProgramConfig::SPACE,
+        seeds = [CONFIG_SEED],
         bump
     )]
-    pub tree: Account<'info, TreeState>,
+    pub config: Account<'info, ProgramConfig>,
 
     #[account(mut)]
     pub authority: Signer<'info>,
 
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ CipherPayError::Unauthorized)]
+    pub program: Program<'info, CipherpayAnchor>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @ CipherPayError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
     pub system_program: Program<'info, System>,
 }
 
-// ---------------- Init vault PDA (authority-held mint authority elsewhere) ---------------
+/// Current admin proposes a new admin (step 1 of 2).
 #[derive(Accounts)]
-pub struct InitializeVault<'info> {
-    /// PDA to be derived with VAULT_SEED; created off-chain or here if you prefer.
+pub struct ProposeAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Admin toggles the per-operation pause flags.
+#[derive(Accounts)]
+pub struct SetPauseState<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Admin sets the maximum (or minimum) age of membership roots.
+#[derive(Accounts)]
+pub struct SetRootMaxAge<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Admin sets the deposit / withdraw limits of a pool (creates its `PoolConfig`).
+#[derive(Accounts)]
+pub struct SetPoolLimits<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = PoolConfig::SPACE,
+        seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub pool_config: Account<'info, PoolConfig>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
     #[account(mut)]
-    pub vault: Signer<'info>,
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Admin sets the large-withdrawal time lock and guardian of a pool
+/// (its `PoolConfig` must exist, see `set_pool_limits`).
+#[derive(Accounts)]
+pub struct SetWithdrawTimelock<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut, seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()], bump = pool_config.bump)]
+    pub pool_config: Account<'info, PoolConfig>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Admin moves spends from legacy nullifier PDAs to shards (PDA → SHARDED).
+#[derive(Accounts)]
+pub struct SetNullifierSet<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Move one legacy `NullifierRecord` into its shard and close it.
+/// Permissionless: `payer` funds shard growth and receives the record's rent.
+#[derive(Accounts)]
+#[instruction(nullifier: [u8; 32])]
+pub struct MigrateNullifierRecord<'info> {
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub payer: Signer<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [NULLIFIER_SEED, nullifier.as_ref()],
+        bump = nullifier_record.bump,
+        close = payer
+    )]
+    pub nullifier_record: Account<'info, NullifierRecord>,
+
+    /// CHECK: PDA, owner and layout verified in `spend_nullifier_shard`.
+    #[account(mut)]
+    pub nullifier_shard: UncheckedAccount<'info>,
+
     pub system_program: Program<'info, System>,
 }
 
-/// One-time init of the root cache account.
+/// Admin allocates a verifying-key slot for (circuit, version) and pins its hash.
+#[derive(Accounts)]
+#[instruction(circuit: u8, version: u16, vk_len: u32)]
+pub struct InitVerifyingKey<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = VerifyingKeyAccount::space(vk_len as usize),
+        seeds = [VK_SEED, &[circuit], &version.to_le_bytes()],
+        bump
+    )]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Admin writes a chunk of VK bytes, or finalizes the key (same accounts).
+#[derive(Accounts)]
+pub struct UpdateVerifyingKey<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Admin sets the lowest verifying-key version a circuit accepts.
 #[derive(Accounts)]
-pub struct InitializeRootCache<'info> {
+pub struct SetMinVkVersion<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Proposed admin accepts the role (step 2 of 2).
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.pending_admin == Some(new_admin.key()) @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub new_admin: Signer<'info>,
+}
+
+/// Initialize a shielded pool for one SPL mint: its first Merkle tree (tree_id 0) and root cache.
+/// Both PDAs are derived from the mint, so every mint gets an isolated pool. Admin only.
+#[derive(Accounts)]
+#[instruction(depth: u8, genesis_root: [u8; 32], root_capacity: u32)]
+pub struct InitializePool<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = TreeState::space(depth),
+        seeds = [TREE_SEED, token_mint.key().as_ref(), &0u32.to_le_bytes()],
+        bump
+    )]
+    pub tree: Account<'info, TreeState>,
+
     #[account(
         init,
         payer = authority,
-        space = 8 + MerkleRootCache::SIZE,
-        seeds = [ROOT_CACHE_SEED],
+        space = MerkleRootCache::space(root_capacity),
+        seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &0u32.to_le_bytes()],
         bump
     )]
     pub root_cache: AccountLoader<'info, MerkleRootCache>,
 
+    /// Mint this pool shields (classic Token or Token-2022).
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
+/// Open tree `tree_id + 1` (with its own root cache) once the current tree of a pool is full.
+/// Permissionless so a full pool never waits on the admin; `payer` funds the rent.
+#[derive(Accounts)]
+pub struct RolloverTree<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Current (full) tree of the pool.
+    #[account(
+        seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()],
+        bump,
+        constraint = tree.is_full() @ CipherPayError::TreeNotFull
+    )]
+    pub tree: Account<'info, TreeState>,
+
+    /// Root cache of the current tree; the new one gets the same capacity.
+    #[account(seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = TreeState::space(tree.depth),
+        seeds = [TREE_SEED, token_mint.key().as_ref(), &(tree.tree_id + 1).to_le_bytes()],
+        bump
+    )]
+    pub new_tree: Account<'info, TreeState>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = MerkleRootCache::space(root_cache.load()?.capacity.min(MAX_ROLLOVER_ROOT_CAPACITY)),
+        seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &(tree.tree_id + 1).to_le_bytes()],
+        bump
+    )]
+    pub new_root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Admin grows the root cache of tree `tree_id` (realloc; the program re-lays the ring).
+#[derive(Accounts)]
+#[instruction(tree_id: u32, new_capacity: u32)]
+pub struct ResizeRootCache<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree_id.to_le_bytes()],
+        bump,
+        realloc = MerkleRootCache::space(new_capacity),
+        realloc::payer = admin,
+        realloc::zero = false
+    )]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Admin creates the commitment queue of tree `tree_id` (needed for sequencer mode).
+#[derive(Accounts)]
+#[instruction(tree_id: u32, capacity: u32)]
+pub struct InitializeCommitmentQueue<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(seeds = [TREE_SEED, token_mint.key().as_ref(), &tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = CommitmentQueue::space(capacity),
+        seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree_id.to_le_bytes()],
+        bump,
+        constraint = capacity > 0 && capacity <= MAX_COMMITMENT_QUEUE_CAPACITY @ CipherPayError::InvalidInput
+    )]
+    pub commitment_queue: AccountLoader<'info, CommitmentQueue>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Admin switches tree `tree_id` in or out of sequencer mode.
+#[derive(Accounts)]
+#[instruction(tree_id: u32)]
+pub struct SetSequencerMode<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == admin.key() @ CipherPayError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    /// Must exist to enable the mode, and be empty to leave it.
+    #[account(seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: AccountLoader<'info, CommitmentQueue>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub admin: Signer<'info>,
+}
+
+/// Permissionless batcher: insert the oldest queued commitments of `tree` with one
+/// batch-insert proof (registry VK of `circuit_id::batch_insert(N)`).
+///
+/// `remaining_accounts`: the (writable) `DepositMarker`s of deposits whose leaves
+/// the batch inserts, so their roots get set and they can later be closed.
+#[derive(Accounts)]
+pub struct InsertQueuedCommitments<'info> {
+    pub batcher: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: AccountLoader<'info, CommitmentQueue>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Registry VK of the N-leaf batch-insert circuit.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+}
+
+/// Permissionless: restore the frontier of a tree after batch inserts
+/// (checked against its current root, so any caller can supply it).
+#[derive(Accounts)]
+pub struct SyncTreeFrontier<'info> {
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    /// Mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+}
+
+// ---------------- Init vault PDA (authority-held mint authority elsewhere) ---------------
+#[derive(Accounts)]
+pub struct InitializeVault<'info> {
+    /// PDA to be derived with VAULT_SEED; created off-chain or here if you prefer.
+    #[account(mut)]
+    pub vault: Signer<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 #[instruction(deposit_hash: Vec<u8>, proof_bytes: Vec<u8>, public_inputs_bytes: Vec<u8>)]
 pub struct ShieldedDepositAtomic<'info> {
     #[account(mut, signer)]
     pub payer: Signer<'info>,
 
-    // global tree
-    #[account(mut, seeds = [TREE_SEED], bump)]
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    // per-mint tree (the current, non-full one)
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
     pub tree: Account<'info, TreeState>,
 
-    #[account(mut)]
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
     pub root_cache: AccountLoader<'info, MerkleRootCache>,
 
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
     #[account(
         init,
         payer = payer,
@@ -77,26 +482,294 @@ pub struct ShieldedDepositAtomic<'info> {
     )]
     pub deposit_marker: Account<'info, DepositMarker>,
 
-    /// CHECK: program vault PDA (authority)
+    /// CHECK: processed-deposits shard for deposit_hash[0] (may not exist yet);
+    /// rejects hashes whose marker was already closed. Verified in the handler.
+    pub processed_deposits: UncheckedAccount<'info>,
+
+    /// CHECK: pool limits PDA of this mint (may not exist yet: no limits). Read in the handler.
+    #[account(seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()], bump)]
+    pub pool_config: UncheckedAccount<'info>,
+
+    /// CHECK: program vault PDA (authority) for this mint
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
     pub vault_pda: UncheckedAccount<'info>,
 
-    /// CHECK: program’s vault ATA for this mint
-    #[account(mut)]
-    pub vault_token_account: UncheckedAccount<'info>,
+    /// Program vault ATA for this mint (destination of the SPL transfer).
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    /// CHECK: SPL mint
-    pub token_mint: UncheckedAccount<'info>,
+    /// SPL mint (classic Token or Token-2022; transfer-fee config is read from it)
+    pub token_mint: InterfaceAccount<'info, Mint>,
 
     /// CHECK: sysvar instructions
     pub instructions: UncheckedAccount<'info>,
 
+    /// Optional on-chain VK (selects the circuit version); embedded VK if omitted.
+    pub verifying_key: Option<Account<'info, VerifyingKeyAccount>>,
+
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, anchor_spl::token::Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, anchor_spl::associated_token::AssociatedToken>,
 }
 
+/// Batched deposit: K deposits proven by one aggregated proof (registry VK of
+/// `circuit_id::deposit_batch(K)`), funded by one SPL transfer to the vault per deposit.
+/// `remaining_accounts` hold the K `DepositMarker` PDAs (created here), followed by
+/// the K processed-deposits shards, both in public-signal order.
+#[derive(Accounts)]
+pub struct ShieldedDepositBatch<'info> {
+    #[account(mut, signer)]
+    pub payer: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    // per-mint tree (the current, non-full one)
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    /// CHECK: pool limits PDA of this mint (may not exist yet: no limits). Read in the handler.
+    #[account(seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()], bump)]
+    pub pool_config: UncheckedAccount<'info>,
+
+    /// CHECK: program vault PDA (authority) for this mint
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint (destination of the SPL transfer).
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// SPL mint (classic Token or Token-2022; transfer-fee config is read from it)
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: sysvar instructions
+    pub instructions: UncheckedAccount<'info>,
+
+    /// Registry VK of the K-deposit circuit (no embedded VK exists).
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Two-phase deposit, phase one: the depositor moves `amount` into the pool vault,
+/// escrowed under a `PendingDeposit` keyed by `deposit_hash` (no proof, no memo).
+#[derive(Accounts)]
+#[instruction(deposit_hash: [u8; 32])]
+pub struct CreatePendingDeposit<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = depositor,
+        space = PendingDeposit::SPACE,
+        seeds = [PENDING_DEPOSIT_SEED, deposit_hash.as_ref()],
+        bump
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    /// CHECK: processed-deposits shard for deposit_hash[0] (may not exist yet);
+    /// an already-used hash could never be finalized. Verified in the handler.
+    pub processed_deposits: UncheckedAccount<'info>,
+
+    /// Depositor's token account for this mint (source of the escrow).
+    #[account(mut, token::mint = token_mint, token::authority = depositor)]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: pool limits PDA of this mint (may not exist yet: no limits). Read in the handler.
+    #[account(seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()], bump)]
+    pub pool_config: UncheckedAccount<'info>,
+
+    /// CHECK: program vault PDA (authority) for this mint
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// SPL mint (classic Token or Token-2022; transfer-fee config is read from it)
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Two-phase deposit, phase two: a deposit proof for the escrowed amount turns it
+/// into a note. Anyone may submit it (the proof binds `deposit_hash`); the escrow
+/// rent goes back to the depositor.
+#[derive(Accounts)]
+#[instruction(deposit_hash: [u8; 32])]
+pub struct FinalizePendingDeposit<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [PENDING_DEPOSIT_SEED, deposit_hash.as_ref()],
+        bump = pending_deposit.bump,
+        has_one = depositor @ CipherPayError::Unauthorized,
+        constraint = pending_deposit.mint == token_mint.key() @ CipherPayError::VaultMismatch,
+        close = depositor
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    /// CHECK: original depositor (checked against `pending_deposit`); receives the rent.
+    #[account(mut)]
+    pub depositor: UncheckedAccount<'info>,
+
+    // per-mint tree (the current, non-full one)
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = DepositMarker::SPACE,
+        seeds = [DEPOSIT_MARKER_SEED, deposit_hash.as_ref()],
+        bump
+    )]
+    pub deposit_marker: Account<'info, DepositMarker>,
+
+    /// CHECK: processed-deposits shard for deposit_hash[0] (may not exist yet);
+    /// rejects hashes whose marker was already closed. Verified in the handler.
+    pub processed_deposits: UncheckedAccount<'info>,
+
+    /// SPL mint of the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Optional on-chain VK (selects the circuit version); embedded VK if omitted.
+    pub verifying_key: Option<Account<'info, VerifyingKeyAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Depositor takes a pending deposit back out of the vault once it timed out
+/// without being finalized.
+#[derive(Accounts)]
+#[instruction(deposit_hash: [u8; 32])]
+pub struct RefundPendingDeposit<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [PENDING_DEPOSIT_SEED, deposit_hash.as_ref()],
+        bump = pending_deposit.bump,
+        has_one = depositor @ CipherPayError::Unauthorized,
+        constraint = pending_deposit.mint == token_mint.key() @ CipherPayError::VaultMismatch,
+        close = depositor
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    /// Depositor's token account for this mint (refund destination).
+    #[account(mut, token::mint = token_mint, token::authority = depositor)]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: program vault PDA (signs the refund CPI with seeds)
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// SPL mint of the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Original payer reclaims a processed deposit marker's rent once the deposit's
+/// root is no longer accepted by its root cache (evicted or older than the max age).
+/// Queued deposits need their root set by `insert_queued_commitments` first.
+#[derive(Accounts)]
+#[instruction(deposit_hash: [u8; 32])]
+pub struct CloseDepositMarker<'info> {
+    /// Original depositor; funds processed-deposits shard growth, receives the rent.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Program config (root max age).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [DEPOSIT_MARKER_SEED, deposit_hash.as_ref()],
+        bump = deposit_marker.bump,
+        constraint = deposit_marker.processed @ CipherPayError::InvalidInput,
+        constraint = deposit_marker.payer == payer.key() @ CipherPayError::Unauthorized,
+        close = payer
+    )]
+    pub deposit_marker: Account<'info, DepositMarker>,
+
+    /// Root cache of the tree the deposit was appended to.
+    #[account(
+        seeds = [ROOT_CACHE_SEED, deposit_marker.mint.as_ref(), &deposit_marker.tree_id.to_le_bytes()],
+        bump
+    )]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// CHECK: PDA, owner and layout verified in `record_processed_deposit`.
+    #[account(mut)]
+    pub processed_deposits: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
 /// Spend one input (nullifier) and append two outputs.
-/// Only `payer` signs (covers rent for the nullifier record).
+/// Only `payer` signs (covers rent for the nullifier record). `payer` may be a relayer,
+/// compensated out of the vault with the fee committed to by the proof.
 #[derive(Accounts)]
 #[instruction(nullifier: Vec<u8>, _proof: Vec<u8>, _publics: Vec<u8>)]
 pub struct ShieldedTransfer<'info> {
@@ -104,15 +777,27 @@ pub struct ShieldedTransfer<'info> {
     #[account(mut, signer)]
     pub payer: Signer<'info>,
 
-    /// Global Merkle tree (strict sync with proof’s spent root).
-    #[account(mut, seeds = [TREE_SEED], bump)]
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// Current Merkle tree of this mint's pool (outputs are appended here).
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
     pub tree: Account<'info, TreeState>,
 
-    /// Rolling cache of recent roots (zero-copy account).
-    #[account(mut, seeds = [ROOT_CACHE_SEED], bump)]
+    /// Rolling cache of recent roots of `tree` (zero-copy account).
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
     pub root_cache: AccountLoader<'info, MerkleRootCache>,
 
-    /// Per-nullifier one-shot PDA; prevents double-spends.
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    /// Root cache of an older (rolled-over) tree of this mint holding the spent root;
+    /// `root_cache` is used when omitted.
+    pub membership_root_cache: Option<AccountLoader<'info, MerkleRootCache>>,
+
+    /// Per-nullifier one-shot PDA; prevents double-spends (`nullifier_set` == PDA only).
     #[account(
         init_if_needed,
         payer = payer,
@@ -120,15 +805,109 @@ pub struct ShieldedTransfer<'info> {
         seeds = [NULLIFIER_SEED, nullifier.as_ref()],  // <- use the *instruction arg* bytes
         bump
     )]
-    pub nullifier_record: Account<'info, NullifierRecord>,
+    pub nullifier_record: Option<Account<'info, NullifierRecord>>,
+
+    /// Nullifier shard of `nullifier` (`nullifier_set` != PDA); replaces `nullifier_record`.
+    /// CHECK: PDA, owner and layout verified in `spend_nullifier_sharded`.
+    #[account(mut)]
+    pub nullifier_shard: Option<UncheckedAccount<'info>>,
+
+    /// Legacy record address of `nullifier`, required when `nullifier_set` == SHARDED.
+    /// CHECK: address and contents verified in `spend_nullifier_sharded`; never created.
+    pub legacy_nullifier_record: Option<UncheckedAccount<'info>>,
+
+    /// Program vault authority PDA for this mint (signs the relayer-fee CPI).
+    /// CHECK: PDA only used as a signer for token CPI via seeds.
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Relayer’s token account for the same mint; required when the proof charges a fee.
+    #[account(mut)]
+    pub relayer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// SPL mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Optional on-chain VK (selects the circuit version); embedded VK if omitted.
+    pub verifying_key: Option<Account<'info, VerifyingKeyAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Join-split transfer: spend N inputs and append M outputs (see `joinsplit_idx`).
+/// `remaining_accounts`, in the same order as the `nullifiers` argument, hold one
+/// `NullifierRecord` PDA per input (`nullifier_set` == PDA), or one shard per input
+/// followed by each input's legacy record address. The (N, M) circuit has no embedded VK,
+/// so a finalized registry VK is mandatory.
+#[derive(Accounts)]
+pub struct ShieldedTransferJoinSplit<'info> {
+    /// Fee payer / only signer (may be a relayer).
+    #[account(mut, signer)]
+    pub payer: Signer<'info>,
+
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// Current Merkle tree of this mint's pool (outputs are appended here).
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    /// Rolling cache of recent roots of `tree` (zero-copy account).
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    /// Root cache of an older (rolled-over) tree of this mint holding the spent root;
+    /// `root_cache` is used when omitted.
+    pub membership_root_cache: Option<AccountLoader<'info, MerkleRootCache>>,
+
+    /// Program vault authority PDA for this mint (signs the relayer-fee CPI).
+    /// CHECK: PDA only used as a signer for token CPI via seeds.
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Relayer’s token account for the same mint; required when the proof charges a fee.
+    #[account(mut)]
+    pub relayer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// SPL mint selecting the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Registry VK of the (N, M) join-split circuit.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
 
     pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 /// Shielded withdraw:
 /// - Only `payer` signs
-/// - We **do not** mutate the TreeState here
-/// - We check the spent root against the rolling root cache
+/// - We check the spent root against the rolling root cache (of any tree of the pool)
+/// - Full withdraw does **not** touch the TreeState; change mode (12 publics)
+///   appends the change note, so `tree` must be supplied
 #[derive(Accounts)]
 #[instruction(nullifier: Vec<u8>, _proof: Vec<u8>, _publics: Vec<u8>)]
 pub struct ShieldedWithdraw<'info> {
@@ -136,11 +915,29 @@ pub struct ShieldedWithdraw<'info> {
     #[account(mut, signer)]
     pub payer: Signer<'info>,
 
-    /// Rolling Merkle roots cache (PDA, zero-copy).
-    #[account(mut, seeds = [ROOT_CACHE_SEED], bump)]
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// Current Merkle tree of this mint's pool; required only in change mode.
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Option<Account<'info, TreeState>>,
+
+    /// Rolling Merkle roots cache holding the spent root: any tree of this mint's
+    /// pool, including rolled-over ones (checked by its `mint` field, zero-copy).
+    #[account(mut)]
     pub root_cache: AccountLoader<'info, MerkleRootCache>,
 
-    /// Per-withdraw nullifier record: prevents replay (idempotent).
+    /// Root cache of `tree`, when `root_cache` belongs to an older tree (change mode only).
+    #[account(mut)]
+    pub tree_root_cache: Option<AccountLoader<'info, MerkleRootCache>>,
+
+    /// Commitment queue of `tree` (change mode on a tree in sequencer mode);
+    /// matched against `tree` by its mint / tree id fields.
+    #[account(mut)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    /// Per-withdraw nullifier record: prevents replay (`nullifier_set` == PDA only).
     #[account(
         init_if_needed,
         payer = payer,
@@ -148,20 +945,35 @@ pub struct ShieldedWithdraw<'info> {
         seeds = [NULLIFIER_SEED, nullifier.as_ref()],
         bump
     )]
-    pub nullifier_record: Account<'info, NullifierRecord>,
+    pub nullifier_record: Option<Account<'info, NullifierRecord>>,
 
-    /// Program vault authority PDA (signs CPIs with seeds).
+    /// Nullifier shard of `nullifier` (`nullifier_set` != PDA); replaces `nullifier_record`.
+    /// CHECK: PDA, owner and layout verified in `spend_nullifier_sharded`.
+    #[account(mut)]
+    pub nullifier_shard: Option<UncheckedAccount<'info>>,
+
+    /// Legacy record address of `nullifier`, required when `nullifier_set` == SHARDED.
+    /// CHECK: address and contents verified in `spend_nullifier_sharded`; never created.
+    pub legacy_nullifier_record: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: pool limits PDA of this mint (may not exist yet: no limits); its
+    /// epoch outflow is updated in the handler.
+    #[account(mut, seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()], bump)]
+    pub pool_config: UncheckedAccount<'info>,
+
+    /// Program vault authority PDA for this mint (signs CPIs with seeds).
     /// CHECK: PDA only used as a signer for token CPI via seeds.
-    #[account(seeds = [VAULT_SEED], bump)]
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
     pub vault_pda: UncheckedAccount<'info>,
 
     /// Program vault ATA for the selected mint.
     #[account(
         mut,
         associated_token::mint = token_mint,
-        associated_token::authority = vault_pda
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
     )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
 
     /// Recipient’s wallet (ATA authority). **Not a signer**.
     /// CHECK: Used only as the ATA authority public key.
@@ -171,15 +983,170 @@ pub struct ShieldedWithdraw<'info> {
     #[account(
         mut,
         associated_token::mint = token_mint,
-        associated_token::authority = recipient_owner
+        associated_token::authority = recipient_owner,
+        associated_token::token_program = token_program
+    )]
+    pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Relayer’s token account for the same mint; required when the proof charges a fee.
+    #[account(mut)]
+    pub relayer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Time-locked payout record; required exactly when the amount is above the
+    /// pool's `large_withdraw_threshold` (the tokens then wait in the vault).
+    #[account(
+        init,
+        payer = payer,
+        space = PendingWithdrawal::SPACE,
+        seeds = [PENDING_WITHDRAWAL_SEED, nullifier.as_ref()],
+        bump
     )]
-    pub recipient_token_account: Account<'info, TokenAccount>,
+    pub pending_withdrawal: Option<Account<'info, PendingWithdrawal>>,
+
+    /// Mint being withdrawn (classic Token or Token-2022).
+    pub token_mint: InterfaceAccount<'info, Mint>,
 
-    /// Mint being withdrawn.
-    pub token_mint: Account<'info, Mint>,
+    /// Optional on-chain VK (selects the circuit version); embedded VK if omitted.
+    pub verifying_key: Option<Account<'info, VerifyingKeyAccount>>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+/// Pay out a time-locked withdrawal once its delay is over. Permissionless: the
+/// destinations are fixed in the `PendingWithdrawal`; its rent goes back to its payer.
+#[derive(Accounts)]
+#[instruction(nullifier: [u8; 32])]
+pub struct ClaimPendingWithdrawal<'info> {
+    /// Program config (pause flags).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [PENDING_WITHDRAWAL_SEED, nullifier.as_ref()],
+        bump = pending_withdrawal.bump,
+        has_one = payer @ CipherPayError::Unauthorized,
+        constraint = pending_withdrawal.mint == token_mint.key() @ CipherPayError::VaultMismatch,
+        close = payer
+    )]
+    pub pending_withdrawal: Account<'info, PendingWithdrawal>,
+
+    /// CHECK: payer of the pending withdrawal (checked against it); receives the rent.
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+
+    /// CHECK: program vault PDA (signs the payout CPIs with seeds)
+    #[account(seeds = [VAULT_SEED, token_mint.key().as_ref()], bump)]
+    pub vault_pda: UncheckedAccount<'info>,
+
+    /// Program vault ATA for this mint.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_pda,
+        associated_token::token_program = token_program
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Recipient token account bound by the withdraw proof.
+    #[account(mut, address = pending_withdrawal.recipient_token_account @ CipherPayError::RecipientWalletMismatch)]
+    pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Relayer token account; required when the withdrawal carries a fee.
+    #[account(mut)]
+    pub relayer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// SPL mint of the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Guardian cancels a time-locked withdrawal during its delay: the tokens stay in the
+/// vault and the refund note bound by the withdraw proof is appended to the tree.
+#[derive(Accounts)]
+#[instruction(nullifier: [u8; 32])]
+pub struct CancelPendingWithdrawal<'info> {
+    pub guardian: Signer<'info>,
+
+    /// Program config (root cache window).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [POOL_CONFIG_SEED, token_mint.key().as_ref()],
+        bump = pool_config.bump,
+        has_one = guardian @ CipherPayError::Unauthorized
+    )]
+    pub pool_config: Account<'info, PoolConfig>,
+
+    #[account(
+        mut,
+        seeds = [PENDING_WITHDRAWAL_SEED, nullifier.as_ref()],
+        bump = pending_withdrawal.bump,
+        has_one = payer @ CipherPayError::Unauthorized,
+        constraint = pending_withdrawal.mint == token_mint.key() @ CipherPayError::VaultMismatch,
+        close = payer
+    )]
+    pub pending_withdrawal: Account<'info, PendingWithdrawal>,
+
+    /// CHECK: payer of the pending withdrawal (checked against it); receives the rent.
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+
+    // per-mint tree (the current, non-full one)
+    #[account(mut, seeds = [TREE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub tree: Account<'info, TreeState>,
+
+    #[account(mut, seeds = [ROOT_CACHE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// Commitment queue of `tree`; required while the tree is in sequencer mode.
+    #[account(mut, seeds = [COMMITMENT_QUEUE_SEED, token_mint.key().as_ref(), &tree.tree_id.to_le_bytes()], bump)]
+    pub commitment_queue: Option<AccountLoader<'info, CommitmentQueue>>,
+
+    /// SPL mint of the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+}
+
+/// Verify a selective-disclosure audit proof (registry VK of `circuit_id::AUDIT`)
+/// against a cached root of this mint's pool. With `attestation`, the result is also
+/// stored for the auditor to read.
+#[derive(Accounts)]
+#[instruction(audit_nonce: [u8; 32])]
+pub struct VerifyAuditProof<'info> {
+    /// Prover bound by the proof's prover limbs; pays the attestation rent.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Program config (root max age).
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// Root cache holding the proven root: any tree of this mint's pool
+    /// (checked by its `mint` field, zero-copy).
+    pub root_cache: AccountLoader<'info, MerkleRootCache>,
+
+    /// CHECK: auditor wallet, bound by the proof's auditor limbs. Not a signer.
+    pub auditor: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = AuditAttestation::SPACE,
+        seeds = [AUDIT_ATTESTATION_SEED, auditor.key().as_ref(), audit_nonce.as_ref()],
+        bump
+    )]
+    pub attestation: Option<Account<'info, AuditAttestation>>,
+
+    /// SPL mint of the pool.
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Registry VK of the audit circuit (no embedded VK exists).
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -8,7 +8,31 @@ pub enum CipherPayError {
     #[msg("Deposit hash already used.")]
     DepositAlreadyUsed,
 
-    /// The provided Merkle root was not found in the on-chain root cache.
+    /// close_deposit_marker called while the deposit's root is still accepted by its root cache.
+    #[msg("Deposit root has not aged out yet.")]
+    DepositRootStillLive,
+
+    /// close_deposit_marker called for a queued deposit whose batch insert has not set its root.
+    #[msg("Deposit root not set yet (commitment still queued).")]
+    DepositRootPending,
+
+    /// finalize_pending_deposit proof amount differs from the escrowed amount.
+    #[msg("Deposit amount does not match the escrowed amount.")]
+    DepositAmountMismatch,
+
+    /// Deposit amount is below the pool's `min_deposit`.
+    #[msg("Deposit amount is below the pool minimum.")]
+    DepositBelowMinimum,
+
+    /// Deposit amount is above the pool's `max_deposit`.
+    #[msg("Deposit amount is above the pool maximum.")]
+    DepositAboveMaximum,
+
+    /// refund_pending_deposit called before the pending deposit timed out.
+    #[msg("Pending deposit has not timed out yet.")]
+    PendingDepositNotExpired,
+
+    /// The provided Merkle root was not found in the on-chain root cache (or is older than the max root age).
     #[msg("Merkle root not found in root cache.")]
     UnknownMerkleRoot,
 
@@ -33,10 +57,36 @@ pub enum CipherPayError {
     #[msg("Invalid or truncated verifying key bytes.")]
     InvalidVerifyingKey,
 
+    /// The on-chain verifying key has not been finalized yet.
+    #[msg("Verifying key is not finalized.")]
+    VerifyingKeyNotFinalized,
+
+    /// The on-chain verifying key is finalized and can no longer be written.
+    #[msg("Verifying key is already finalized.")]
+    VerifyingKeyAlreadyFinalized,
+
+    /// The supplied verifying key belongs to another circuit.
+    #[msg("Verifying key does not belong to this circuit.")]
+    VerifyingKeyCircuitMismatch,
+
+    /// keccak256 of the uploaded verifying key differs from the pinned hash.
+    #[msg("Verifying key hash mismatch.")]
+    VerifyingKeyHashMismatch,
+
+    /// The verifying key's version is below the circuit's `min_vk_version`
+    /// (the embedded VK counts as version 0).
+    #[msg("Verifying key version has been retired.")]
+    VerifyingKeyRetired,
+
     /// The number of public inputs supplied does not match the circuit's expected count/order.
     #[msg("Mismatched number of public inputs for this circuit.")]
     PublicInputCountMismatch,
 
+    /// The instruction only attests to a verified proof, and this build verifies none
+    /// (neither `real-crypto` nor `mock-verifier`).
+    #[msg("Proof verification is not compiled into this build.")]
+    ProofVerificationDisabled,
+
     /// When you bind opaque payload tags (e.g., encNote tags) to outputs/recipients and they don't match.
     #[msg("Public input payload binding mismatch.")]
     PayloadBindingMismatch,
@@ -46,15 +96,63 @@ pub enum CipherPayError {
     #[msg("Nullifier already used.")]
     NullifierAlreadyUsed,
 
+    /// The nullifier accounts passed do not match the configured nullifier set
+    /// (legacy PDA vs shard), or the requested nullifier-set change is not allowed.
+    #[msg("Nullifier accounts do not match the configured nullifier set.")]
+    NullifierSetMismatch,
+
     /// Nullifier reconstructed from private inputs doesn't equal the one asserted (if you expose it).
     #[msg("Nullifier provided does not match one in proof.")]
     NullifierMismatch,
 
     // ========== Withdraw Errors ==========
-    /// Sanity checks on amounts (e.g., zero/overflow) failed.
+    /// Sanity checks on amounts (e.g., zero/overflow, above the pool's `max_withdraw`) failed.
     #[msg("Invalid withdrawal amount.")]
     InvalidWithdrawAmount,
 
+    /// The withdrawal would take the pool's outflow this epoch above `epoch_outflow_cap`.
+    #[msg("Pool withdrawal cap for this epoch reached.")]
+    EpochOutflowCapExceeded,
+
+    /// A withdrawal above `large_withdraw_threshold` was sent without its
+    /// `pending_withdrawal` account (or one was sent for a withdrawal below it).
+    #[msg("Pending withdrawal account missing or not expected.")]
+    PendingWithdrawalMismatch,
+
+    /// A time-locked withdrawal was proven without a refund commitment
+    /// (use a `WITHDRAW_REFUNDABLE` / `WITHDRAW_CHANGE_REFUNDABLE` proof).
+    #[msg("Time-locked withdrawal requires a refundable proof.")]
+    RefundCommitmentRequired,
+
+    /// claim_pending_withdrawal called before `claimable_at`.
+    #[msg("Pending withdrawal is still time-locked.")]
+    WithdrawalStillLocked,
+
+    /// cancel_pending_withdrawal called once the delay is over (the recipient may claim).
+    #[msg("Pending withdrawal can no longer be cancelled.")]
+    WithdrawalUnlocked,
+
+    /// The proof charges a relayer fee but no relayer token account was supplied.
+    #[msg("Relayer token account required when a relayer fee is charged.")]
+    RelayerAccountMissing,
+
+    /// The relayer token account is not owned by the relayer bound in the proof.
+    #[msg("Relayer account does not match the relayer bound in the proof.")]
+    RelayerMismatch,
+
+    /// The proof's recipientWalletPubKey is not `recipient_wallet_field(recipient_token_account)`.
+    #[msg("Proof recipient wallet does not match the recipient token account.")]
+    RecipientWalletMismatch,
+
+    // ========== Audit Errors ==========
+    /// The auditor account is not the auditor bound in the audit proof.
+    #[msg("Auditor does not match the auditor bound in the proof.")]
+    AuditorMismatch,
+
+    /// The submitting wallet is not the prover bound in the audit proof.
+    #[msg("Signer does not match the prover bound in the proof.")]
+    ProverMismatch,
+
     // ========== Token / Vault Errors ==========
     /// A token transfer CPI returned an error status.
     #[msg("Token transfer failed.")]
@@ -68,6 +166,10 @@ pub enum CipherPayError {
     #[msg("Vault authority PDA does not match.")]
     VaultAuthorityMismatch,
 
+    /// The proof's tokenId public signal is not `token_id_for(token_mint)`.
+    #[msg("Proof token id does not match the pool mint.")]
+    TokenIdMismatch,
+
     // ========== Transaction Validation Errors ==========
     /// Required Memo instruction not found in the same transaction.
     #[msg("Required Memo instruction not found in transaction.")]
@@ -77,6 +179,65 @@ pub enum CipherPayError {
     #[msg("Required SPL Token transfer not found in transaction.")]
     RequiredSplTransferMissing,
 
+    // ========== Merkle Tree Errors ==========
+    /// Tree depth is 0 or exceeds MAX_TREE_DEPTH.
+    #[msg("Invalid Merkle tree depth.")]
+    InvalidTreeDepth,
+
+    /// initialize_pool genesis root is not the empty-tree root for `depth`.
+    #[msg("Genesis root does not match the empty tree root.")]
+    InvalidGenesisRoot,
+
+    /// Tree has 2^depth leaves; roll over to the next tree id.
+    #[msg("Merkle tree is full.")]
+    TreeFull,
+
+    /// Rollover requested while the current tree still has room.
+    #[msg("Merkle tree is not full yet.")]
+    TreeNotFull,
+
+    /// Tree is in sequencer mode: commitments must go through its queue (or the queue is
+    /// missing / not empty when the mode is switched off).
+    #[msg("Merkle tree is in sequencer mode.")]
+    TreeSequenced,
+
+    /// A batch insert left `filled_subtrees` behind the root; call sync_tree_frontier.
+    #[msg("Merkle tree frontier is stale.")]
+    FrontierStale,
+
+    /// Supplied frontier does not reproduce the tree's current root.
+    #[msg("Frontier does not match the current root.")]
+    FrontierMismatch,
+
+    /// Commitment queue has no room left.
+    #[msg("Commitment queue is full.")]
+    CommitmentQueueFull,
+
+    /// A batch insert or pop asks for more commitments than are queued.
+    #[msg("Not enough commitments queued.")]
+    CommitmentQueueUnderflow,
+
+    /// Root cache belongs to a different mint or tree.
+    #[msg("Root cache does not match the pool or tree.")]
+    RootCacheMismatch,
+
+    /// Root cache capacity is zero, above MAX_ROOT_CAPACITY, not a growth, or the data is too small.
+    #[msg("Invalid root cache capacity.")]
+    InvalidRootCapacity,
+
+    /// The root cache is full of roots younger than `root_min_age_slots`; grow it.
+    #[msg("Root cache busy: oldest root is below the minimum age.")]
+    RootCacheBusy,
+
+    /// Poseidon syscall failed (input not a canonical field element).
+    #[msg("Merkle hash (Poseidon) failed.")]
+    MerkleHashFailed,
+
+    // ========== Governance Errors ==========
+    /// The admin has paused this operation (emergency circuit breaker).
+    #[msg("This operation is paused by the program admin.")]
+    OperationPaused,
+
     // ========== General Errors ==========
     /// Signer/authority or account ownership checks failed.
     #[msg("You are not authorized to perform this action.")]
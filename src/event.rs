@@ -3,7 +3,8 @@ use anchor_lang::prelude::*;
 /// Emitted after a successful shielded_deposit:
 /// - `deposit_hash` was marked processed
 /// - `commitment` inserted at `next_leaf_index`
-/// - root cache updated with `new_merkle_root`
+/// - root cache updated with `new_merkle_root` (zero if the tree is in sequencer
+///   mode: the commitment was queued and `QueuedCommitmentsInserted` carries its root)
 #[event]
 pub struct DepositCompleted {
     pub deposit_hash: [u8; 32],
@@ -13,6 +14,25 @@ pub struct DepositCompleted {
     pub new_merkle_root: [u8; 32],
     pub next_leaf_index: u32,
     pub mint: Pubkey,
+    /// Tree the commitment was appended to (leaf index is relative to it)
+    pub tree_id: u32,
+}
+
+/// Emitted after a successful shielded_deposit_batch (K deposits, one transfer each):
+/// - commitments appended in order at `next_leaf_index - K ..`
+/// - `new_merkle_roots[i]` is the root after commitment i (all cached; zero when queued)
+#[event]
+pub struct DepositBatchCompleted {
+    pub deposit_hashes: Vec<[u8; 32]>,
+    pub owner_cipherpay_pubkeys: Vec<[u8; 32]>,
+    pub commitments: Vec<[u8; 32]>,
+    pub old_merkle_root: [u8; 32],
+    pub new_merkle_roots: Vec<[u8; 32]>,
+    pub next_leaf_index: u32,
+    /// Sum of the deposit amounts (what the K transfers landed in the vault)
+    pub total_amount: u64,
+    pub mint: Pubkey,
+    pub tree_id: u32,
 }
 
 /// Emitted after a successful shielded_transfer:
@@ -28,7 +48,7 @@ pub struct TransferCompleted {
     pub enc_note2_hash: [u8; 32],
     /// Root before appends (from membership proof)
     pub merkle_root_before: [u8; 32],
-    /// Root after inserting out1
+    /// Root after inserting out1 (zero when the outputs were queued)
     pub new_merkle_root1: [u8; 32],
     /// Root after inserting out2
     pub new_merkle_root2: [u8; 32],
@@ -36,10 +56,46 @@ pub struct TransferCompleted {
     pub next_leaf_index: u32,
     /// SPL mint that identifies the vault this applies to
     pub mint: Pubkey,
+    /// Tree the outputs were appended to (leaf indices are relative to it)
+    pub tree_id: u32,
+    /// Tree `merkle_root_before` belongs to (older than `tree_id` after a rollover)
+    pub root_tree_id: u32,
+    /// Relayer fee paid out of the vault (0 = self-submitted)
+    pub fee: u64,
+    /// Relayer wallet paid `fee` (default pubkey when `fee == 0`)
+    pub relayer: Pubkey,
+}
+
+/// Emitted after a successful shielded_transfer_joinsplit (N inputs, M outputs):
+/// - proves membership of every input note (root = `merkle_root_before`)
+/// - appends `out_commitments[j]`, producing `new_merkle_roots[j]` (zero when queued)
+#[event]
+pub struct JoinSplitCompleted {
+    pub nullifiers: Vec<[u8; 32]>,
+    pub out_commitments: Vec<[u8; 32]>,
+    pub enc_note_hashes: Vec<[u8; 32]>,
+    /// Root before appends (from membership proof)
+    pub merkle_root_before: [u8; 32],
+    /// Root after each output append
+    pub new_merkle_roots: Vec<[u8; 32]>,
+    /// Tree next_index after all M appends
+    pub next_leaf_index: u32,
+    /// SPL mint that identifies the vault this applies to
+    pub mint: Pubkey,
+    /// Tree the outputs were appended to (leaf indices are relative to it)
+    pub tree_id: u32,
+    /// Tree `merkle_root_before` belongs to (older than `tree_id` after a rollover)
+    pub root_tree_id: u32,
+    /// Relayer fee paid out of the vault (0 = self-submitted)
+    pub fee: u64,
+    /// Relayer wallet paid `fee` (default pubkey when `fee == 0`)
+    pub relayer: Pubkey,
 }
 
 /// Emitted after a successful shielded_withdraw:
 /// - proves inclusion, nullifies the note, and performs SPL transfer to `recipient`
+/// - `amount` is the public amount; `recipient` got `amount - fee`, `relayer` got `fee`
+/// - in change mode, the remainder is re-shielded as `change_commitment`
 #[event]
 pub struct WithdrawCompleted {
     pub nullifier: [u8; 32],
@@ -47,4 +103,261 @@ pub struct WithdrawCompleted {
     pub amount: u64,
     pub mint: Pubkey,
     pub recipient: Pubkey,
-}
\ No newline at end of file
+    /// Relayer fee taken out of `amount` (0 = self-submitted)
+    pub fee: u64,
+    /// Relayer wallet paid `fee` (default pubkey when `fee == 0`)
+    pub relayer: Pubkey,
+    /// Change note appended to the tree (zero when the note was spent fully)
+    pub change_commitment: [u8; 32],
+    /// Encrypted change-note hash (zero when no change)
+    pub enc_change_note_hash: [u8; 32],
+    /// Tree next_index after the change append (unchanged when no change)
+    pub next_leaf_index: u32,
+    /// Tree `merkle_root_used` belongs to (may be an older, rolled-over tree)
+    pub root_tree_id: u32,
+    /// Tree the change note was appended to (`root_tree_id` when no tree was passed)
+    pub tree_id: u32,
+}
+
+/// Emitted when the current admin proposes a successor (two-step rotation).
+#[event]
+pub struct AdminProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+/// Emitted when the config admin changes (initial set or accepted rotation).
+#[event]
+pub struct AdminChanged {
+    pub old_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+/// Emitted when the admin flips any of the per-operation pause flags.
+#[event]
+pub struct PauseStateChanged {
+    pub deposit_paused: bool,
+    pub transfer_paused: bool,
+    pub withdraw_paused: bool,
+    pub admin: Pubkey,
+}
+
+/// Emitted when a full tree is closed for appends and tree `new_tree_id` takes over.
+/// Roots of the old tree stay valid for spends through its root cache.
+#[event]
+pub struct TreeRolledOver {
+    pub mint: Pubkey,
+    pub old_tree_id: u32,
+    pub new_tree_id: u32,
+    /// Last root of the full tree
+    pub final_root: [u8; 32],
+    /// Empty-tree root the new tree starts from
+    pub genesis_root: [u8; 32],
+    pub depth: u8,
+}
+
+/// Emitted when the admin grows the root history of one tree.
+#[event]
+pub struct RootCacheResized {
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    pub old_capacity: u32,
+    pub new_capacity: u32,
+}
+
+/// Emitted when the admin changes how long membership roots stay valid.
+#[event]
+pub struct RootMaxAgeChanged {
+    pub max_age_slots: u64,
+    pub admin: Pubkey,
+}
+
+/// Emitted when the admin changes the minimum time roots stay in their cache.
+#[event]
+pub struct RootMinAgeChanged {
+    pub min_age_slots: u64,
+    pub admin: Pubkey,
+}
+
+/// Emitted when the admin sets the deposit / withdraw limits of a pool.
+#[event]
+pub struct PoolLimitsUpdated {
+    pub mint: Pubkey,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub max_withdraw: u64,
+    pub epoch_outflow_cap: u64,
+    pub admin: Pubkey,
+}
+
+/// Emitted when a withdrawal uses up a pool's epoch outflow cap (less than one
+/// maximum-size withdrawal left); later withdrawals that do not fit are rejected.
+#[event]
+pub struct EpochOutflowCapReached {
+    pub mint: Pubkey,
+    pub epoch: u64,
+    pub epoch_outflow: u64,
+    pub epoch_outflow_cap: u64,
+}
+
+/// Emitted when the admin sets a pool's large-withdrawal time lock and guardian.
+#[event]
+pub struct WithdrawTimelockUpdated {
+    pub mint: Pubkey,
+    pub large_withdraw_threshold: u64,
+    pub withdraw_delay_slots: u64,
+    pub guardian: Pubkey,
+    pub admin: Pubkey,
+}
+
+/// Emitted when the admin moves spends to another nullifier-set mode.
+#[event]
+pub struct NullifierSetChanged {
+    pub old_mode: u8,
+    pub new_mode: u8,
+    pub admin: Pubkey,
+}
+
+/// Emitted when a legacy nullifier record is folded into its shard and closed.
+#[event]
+pub struct NullifierRecordMigrated {
+    pub nullifier: [u8; 32],
+    pub shard: Pubkey,
+}
+
+/// Emitted when new commitments are queued for a tree in sequencer mode
+/// (leaf indices `first_leaf_index ..`; the root moves at the next batch insert).
+#[event]
+pub struct CommitmentsQueued {
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    pub commitments: Vec<[u8; 32]>,
+    pub first_leaf_index: u32,
+}
+
+/// Emitted when a batcher inserts queued commitments with one batch-insert proof.
+#[event]
+pub struct QueuedCommitmentsInserted {
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    pub old_merkle_root: [u8; 32],
+    pub new_merkle_root: [u8; 32],
+    pub start_index: u32,
+    pub count: u32,
+    pub batcher: Pubkey,
+}
+
+/// Emitted when the admin switches a tree in or out of sequencer mode.
+#[event]
+pub struct SequencerModeChanged {
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    pub enabled: bool,
+    pub admin: Pubkey,
+}
+
+/// Emitted when a stale frontier is replaced by one matching the current root.
+#[event]
+pub struct TreeFrontierSynced {
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    pub root: [u8; 32],
+    pub next_index: u32,
+}
+
+/// Emitted when phase one of a two-phase deposit escrows tokens in the vault.
+#[event]
+pub struct PendingDepositCreated {
+    pub deposit_hash: [u8; 32],
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    /// Net amount that landed in the vault (what the proof must commit to)
+    pub amount: u64,
+    /// First slot `refund_pending_deposit` is accepted
+    pub refundable_at: u64,
+}
+
+/// Emitted when a timed-out pending deposit is paid back to its depositor.
+#[event]
+pub struct PendingDepositRefunded {
+    pub deposit_hash: [u8; 32],
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+/// Emitted when a deposit marker is closed and its rent refunded to the original payer.
+#[event]
+pub struct DepositMarkerClosed {
+    pub deposit_hash: [u8; 32],
+    pub payer: Pubkey,
+    pub lamports: u64,
+}
+
+/// Emitted when an on-chain verifying key passes its hash check and becomes usable.
+#[event]
+pub struct VerifyingKeyFinalized {
+    pub circuit: u8,
+    pub version: u16,
+    pub vk_hash: [u8; 32],
+}
+
+/// Emitted when the admin changes the lowest VK version a circuit accepts.
+#[event]
+pub struct MinVkVersionChanged {
+    pub circuit: u8,
+    pub old_min_version: u16,
+    pub new_min_version: u16,
+    pub admin: Pubkey,
+}
+
+/// Emitted when a withdrawal above the pool threshold is time-locked instead of paid.
+#[event]
+pub struct PendingWithdrawalCreated {
+    pub nullifier: [u8; 32],
+    pub mint: Pubkey,
+    pub recipient_token_account: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub claimable_at: u64,
+    pub refund_commitment: [u8; 32],
+}
+
+/// Emitted when a time-locked withdrawal is paid out after its delay.
+#[event]
+pub struct PendingWithdrawalClaimed {
+    pub nullifier: [u8; 32],
+    pub mint: Pubkey,
+    pub recipient_token_account: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+/// Emitted when the guardian cancels a time-locked withdrawal; its value goes back
+/// into the tree as the proof-bound `refund_commitment`.
+#[event]
+pub struct PendingWithdrawalCancelled {
+    pub nullifier: [u8; 32],
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub refund_commitment: [u8; 32],
+    pub new_merkle_root: [u8; 32],
+    pub next_leaf_index: u32,
+    pub tree_id: u32,
+    pub guardian: Pubkey,
+}
+
+/// Emitted when an audit proof verifies: the prover holds notes under `merkle_root`
+/// summing to `total_amount`, disclosed to `auditor`.
+#[event]
+pub struct AuditAttested {
+    pub auditor: Pubkey,
+    pub prover: Pubkey,
+    pub mint: Pubkey,
+    pub merkle_root: [u8; 32],
+    pub total_amount: u64,
+    pub note_set_hash: [u8; 32],
+    pub disclosure_hash: [u8; 32],
+    pub audit_nonce: [u8; 32],
+    pub slot: u64,
+}
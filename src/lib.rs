@@ -8,23 +8,45 @@
 #![allow(deprecated)]
 
 use anchor_lang::prelude::*;
-#[cfg(feature = "real-crypto")]
-use anchor_spl::token::{self, Transfer as SplTransfer};
+use anchor_spl::token_interface::{self, TransferChecked};
 
-use crate::constants::{DEPOSIT_MARKER_SEED, VAULT_SEED, TREE_SEED};
+use crate::constants::{le_bytes_32_to_u64, MAX_ROLLOVER_ROOT_CAPACITY, DEPOSIT_MARKER_SEED, MAX_TREE_DEPTH, VAULT_SEED, TREE_SEED};
 use crate::context::*;
 use crate::error::CipherPayError;
 use crate::event::*;
+use crate::state::{DepositMarker, PendingCommitments, ProgramConfig};
 use crate::utils::{
+    add_commitments,
+    assert_deposit_hash_unused,
+    assert_deposit_within_limits,
     assert_memo_in_same_tx,
     assert_transfer_checked_in_same_tx,
+    assert_withdraw_destination,
+    commitment_queue_mut,
+    create_deposit_marker,
+    grow_root_cache,
+    init_root_cache,
+    init_tree_frontier,
     insert_merkle_root,
     insert_many_roots,
     is_valid_root,
+    joinsplit_nullifier_accounts,
+    load_pool_config,
+    record_pool_withdraw,
+    record_processed_deposit,
+    root_cache_tree_id,
+    spend_nullifier_pda,
+    spend_nullifier_shard,
+    spend_nullifier_sharded,
+    sync_frontier,
+    token_id_for,
+    transfer_fee_for,
+    vault_payout,
+    verify_circuit_proof,
 };
 
-#[cfg(feature = "real-crypto")]
 use crate::zk_verifier::solana_verifier;
+use crate::zk_verifier::solana_verifier::{audit_idx, batch_insert_idx, deposit_batch_idx, joinsplit_idx};
 
 declare_id!("56nPWpjBLbh1n8vvUdCYGmg3dS5zNwLW9UhCg4MMpBmN");
 
@@ -62,35 +84,34 @@ fn pubkey_from_limbs(lo32: &[u8; 32], hi32: &[u8; 32]) -> Pubkey {
 pub mod cipherpay_anchor {
     use super::*;
 
-    #[cfg(feature = "real-crypto")]
+    #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
     use crate::zk_verifier::solana_verifier::{deposit_idx, transfer_idx, withdraw_idx};
 
-    // For builds without `real-crypto`, provide indices so the code compiles.
-    #[cfg(not(feature = "real-crypto"))]
+    // For builds without `real-crypto` / `mock-verifier`, provide indices so the code compiles.
+    #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
     mod stub_idx {
         pub mod deposit_idx {
             pub const NEW_COMMITMENT: usize = 0;
             pub const OWNER_CIPHERPAY_PUBKEY: usize = 1;
-            pub const NEW_MERKLE_ROOT: usize = 2;
-            pub const NEW_NEXT_LEAF_INDEX: usize = 3;
-            pub const AMOUNT: usize = 4;
-            pub const DEPOSIT_HASH: usize = 5;
-            pub const OLD_MERKLE_ROOT: usize = 6;
+            pub const AMOUNT: usize = 2;
+            pub const DEPOSIT_HASH: usize = 3;
+            pub const TOKEN_ID: usize = 4;
         }
         pub mod transfer_idx {
             pub const OUT_COMMITMENT_1: usize = 0;
             pub const OUT_COMMITMENT_2: usize = 1;
             pub const NULLIFIER: usize = 2;
             pub const MERKLE_ROOT: usize = 3;
-            pub const NEW_MERKLE_ROOT_1: usize = 4;
-            pub const NEW_MERKLE_ROOT_2: usize = 5;
-            pub const NEW_NEXT_LEAF_INDEX: usize = 6;
-            pub const ENC_NOTE1_HASH: usize = 7;
-            pub const ENC_NOTE2_HASH: usize = 8;
+            pub const ENC_NOTE1_HASH: usize = 4;
+            pub const ENC_NOTE2_HASH: usize = 5;
+            pub const FEE: usize = 6;
+            pub const RELAYER_LO: usize = 7;
+            pub const RELAYER_HI: usize = 8;
         }
         // UPDATED: withdraw publics now include recipient_owner limbs (slots 2,3)
+        // and the relayer fee + relayer limbs (slots 7,8,9)
         // [ NULLIFIER(0), MERKLE_ROOT(1), RECIPIENT_OWNER_LO(2), RECIPIENT_OWNER_HI(3),
-        //   RECIPIENT_WALLET_PUBKEY(4), AMOUNT(5), TOKEN_ID(6) ]
+        //   RECIPIENT_WALLET_PUBKEY(4), AMOUNT(5), TOKEN_ID(6), FEE(7), RELAYER_LO(8), RELAYER_HI(9) ]
         pub mod withdraw_idx {
             pub const NULLIFIER: usize = 0;
             pub const MERKLE_ROOT: usize = 1;
@@ -99,30 +120,479 @@ pub mod cipherpay_anchor {
             pub const RECIPIENT_WALLET_PUBKEY: usize = 4;
             pub const AMOUNT: usize = 5;
             pub const TOKEN_ID: usize = 6;
+            pub const FEE: usize = 7;
+            pub const RELAYER_LO: usize = 8;
+            pub const RELAYER_HI: usize = 9;
+            // change mode (12 publics)
+            pub const CHANGE_COMMITMENT: usize = 10;
+            pub const ENC_CHANGE_NOTE_HASH: usize = 11;
+            // refundable modes (11 / 13 publics)
+            pub const fn refund_commitment(with_change: bool) -> usize {
+                if with_change { 12 } else { 10 }
+            }
         }
     }
-    #[cfg(not(feature = "real-crypto"))]
+    #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
     use stub_idx::{deposit_idx, transfer_idx, withdraw_idx};
 
 
-    pub fn initialize_vault(_ctx: Context<InitializeVault>) -> Result<()> {
+    /// Create the program config and set its first admin (upgrade authority only).
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        cfg.admin         = admin;
+        cfg.pending_admin = None;
+        cfg.bump          = ctx.bumps.config;
+        emit!(AdminChanged { old_admin: Pubkey::default(), new_admin: admin });
         Ok(())
     }
 
-    pub fn initialize_root_cache(ctx: Context<InitializeRootCache>) -> Result<()> {
-        let mut cache = ctx.accounts.root_cache.load_init()?;
-        cache.clear();
-        msg!("root_cache initialized: next_slot={}, count={}", cache.next_slot, cache.count);
+    /// Step 1 of admin rotation: current admin nominates `new_admin`.
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        cfg.pending_admin = Some(new_admin);
+        emit!(AdminProposed { admin: cfg.admin, pending_admin: new_admin });
+        Ok(())
+    }
+
+    /// Step 2 of admin rotation: the nominated key signs to take over.
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        let old_admin = cfg.admin;
+        cfg.admin         = ctx.accounts.new_admin.key();
+        cfg.pending_admin = None;
+        emit!(AdminChanged { old_admin, new_admin: cfg.admin });
+        Ok(())
+    }
+
+    /// Emergency circuit breaker: pause/unpause deposit, transfer and withdraw independently.
+    pub fn set_pause_state(
+        ctx: Context<SetPauseState>,
+        deposit_paused: bool,
+        transfer_paused: bool,
+        withdraw_paused: bool,
+    ) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        let mut flags = 0u8;
+        if deposit_paused  { flags |= ProgramConfig::PAUSE_DEPOSIT; }
+        if transfer_paused { flags |= ProgramConfig::PAUSE_TRANSFER; }
+        if withdraw_paused { flags |= ProgramConfig::PAUSE_WITHDRAW; }
+        cfg.pause_flags = flags;
+        emit!(PauseStateChanged {
+            deposit_paused,
+            transfer_paused,
+            withdraw_paused,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Expire membership roots older than `max_age_slots` (0 disables expiry).
+    /// Ring eviction still applies; see `set_root_min_age` for busy pools.
+    pub fn set_root_max_age(ctx: Context<SetRootMaxAge>, max_age_slots: u64) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        cfg.root_max_age_slots = max_age_slots;
+        emit!(RootMaxAgeChanged {
+            max_age_slots,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Keep every cached root for at least `min_age_slots` (0 disables the window):
+    /// appends that would evict a younger root fail until `resize_root_cache` grows the cache.
+    pub fn set_root_min_age(ctx: Context<SetRootMaxAge>, min_age_slots: u64) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        cfg.root_min_age_slots = min_age_slots;
+        emit!(RootMinAgeChanged {
+            min_age_slots,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Set a pool's deposit / withdraw limits (0 = no limit). The epoch outflow cap
+    /// counts gross withdrawals per epoch; changing it keeps this epoch's outflow.
+    pub fn set_pool_limits(
+        ctx: Context<SetPoolLimits>,
+        min_deposit: u64,
+        max_deposit: u64,
+        max_withdraw: u64,
+        epoch_outflow_cap: u64,
+    ) -> Result<()> {
+        require!(max_deposit == 0 || min_deposit <= max_deposit, CipherPayError::InvalidInput);
+        let limits = &mut ctx.accounts.pool_config;
+        limits.mint              = ctx.accounts.token_mint.key();
+        limits.bump              = ctx.bumps.pool_config;
+        limits.min_deposit       = min_deposit;
+        limits.max_deposit       = max_deposit;
+        limits.max_withdraw      = max_withdraw;
+        limits.epoch_outflow_cap = epoch_outflow_cap;
+        emit!(PoolLimitsUpdated {
+            mint: limits.mint,
+            min_deposit,
+            max_deposit,
+            max_withdraw,
+            epoch_outflow_cap,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Time-lock withdrawals above `large_withdraw_threshold` (0 = off) for
+    /// `withdraw_delay_slots`, during which `guardian` may cancel them.
+    pub fn set_withdraw_timelock(
+        ctx: Context<SetWithdrawTimelock>,
+        large_withdraw_threshold: u64,
+        withdraw_delay_slots: u64,
+        guardian: Pubkey,
+    ) -> Result<()> {
+        require!(
+            large_withdraw_threshold == 0 || withdraw_delay_slots > 0,
+            CipherPayError::InvalidInput
+        );
+        let limits = &mut ctx.accounts.pool_config;
+        limits.large_withdraw_threshold = large_withdraw_threshold;
+        limits.withdraw_delay_slots     = withdraw_delay_slots;
+        limits.guardian                 = guardian;
+        emit!(WithdrawTimelockUpdated {
+            mint: limits.mint,
+            large_withdraw_threshold,
+            withdraw_delay_slots,
+            guardian,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Move spends from legacy PDAs to nullifier shards: PDA → SHARDED (no way back).
+    pub fn set_nullifier_set(ctx: Context<SetNullifierSet>, mode: u8) -> Result<()> {
+        let cfg = &mut ctx.accounts.config;
+        require!(
+            cfg.nullifier_set == ProgramConfig::NULLIFIER_SET_PDA
+                && mode == ProgramConfig::NULLIFIER_SET_SHARDED,
+            CipherPayError::NullifierSetMismatch
+        );
+        let old_mode = cfg.nullifier_set;
+        cfg.nullifier_set = mode;
+        emit!(NullifierSetChanged { old_mode, new_mode: mode, admin: ctx.accounts.admin.key() });
+        Ok(())
+    }
+
+    /// Move a legacy `NullifierRecord` into its shard and close it, refunding its rent to `payer`.
+    pub fn migrate_nullifier_record(ctx: Context<MigrateNullifierRecord>, nullifier: [u8; 32]) -> Result<()> {
+        require!(
+            ctx.accounts.config.nullifier_set != ProgramConfig::NULLIFIER_SET_PDA,
+            CipherPayError::NullifierSetMismatch
+        );
+        require!(ctx.accounts.nullifier_record.used, CipherPayError::InvalidInput);
+        spend_nullifier_shard(
+            ctx.program_id,
+            &ctx.accounts.nullifier_shard.to_account_info(),
+            &nullifier,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        emit!(NullifierRecordMigrated {
+            nullifier,
+            shard: ctx.accounts.nullifier_shard.key(),
+        });
+        Ok(())
+    }
+
+    /// Allocate an on-chain VK for (circuit, version); `vk_hash` = keccak256 of the full vk.bin.
+    pub fn init_verifying_key(
+        ctx: Context<InitVerifyingKey>,
+        circuit: u8,
+        version: u16,
+        vk_len: u32,
+        vk_hash: [u8; 32],
+    ) -> Result<()> {
+        // Version 0 is the embedded VK; registry versions start at 1.
+        require!(version > 0, CipherPayError::InvalidInput);
+        require!(
+            solana_verifier::verifiable_n_public(circuit).is_some(),
+            CipherPayError::VerifyingKeyCircuitMismatch
+        );
+        let vk = &mut ctx.accounts.verifying_key;
+        vk.circuit   = circuit;
+        vk.version   = version;
+        vk.finalized = false;
+        vk.vk_hash   = vk_hash;
+        vk.bump      = ctx.bumps.verifying_key;
+        vk.data      = vec![0u8; vk_len as usize];
+        Ok(())
+    }
+
+    /// Write `chunk` at `offset` into a not-yet-finalized VK.
+    pub fn write_verifying_key_chunk(
+        ctx: Context<UpdateVerifyingKey>,
+        offset: u32,
+        chunk: Vec<u8>,
+    ) -> Result<()> {
+        let vk = &mut ctx.accounts.verifying_key;
+        require!(!vk.finalized, CipherPayError::VerifyingKeyAlreadyFinalized);
+        let start = offset as usize;
+        let end = start.checked_add(chunk.len()).ok_or(error!(CipherPayError::ArithmeticError))?;
+        require!(end <= vk.data.len(), CipherPayError::InvalidInput);
+        vk.data[start..end].copy_from_slice(&chunk);
+        Ok(())
+    }
+
+    /// Check the uploaded bytes against the pinned hash and the circuit's shape, then freeze them.
+    pub fn finalize_verifying_key(ctx: Context<UpdateVerifyingKey>) -> Result<()> {
+        let vk = &mut ctx.accounts.verifying_key;
+        require!(!vk.finalized, CipherPayError::VerifyingKeyAlreadyFinalized);
+
+        let digest = anchor_lang::solana_program::keccak::hash(&vk.data);
+        require!(digest.0 == vk.vk_hash, CipherPayError::VerifyingKeyHashMismatch);
+
+        let n_public = solana_verifier::verifiable_n_public(vk.circuit)
+            .ok_or(error!(CipherPayError::VerifyingKeyCircuitMismatch))?;
+        solana_verifier::validate_vk_bytes(&vk.data, n_public)
+            .map_err(|_| error!(CipherPayError::InvalidVerifyingKey))?;
+
+        vk.finalized = true;
+        emit!(VerifyingKeyFinalized {
+            circuit: vk.circuit,
+            version: vk.version,
+            vk_hash: vk.vk_hash,
+        });
         Ok(())
     }
-    
 
-    pub fn initialize_tree_state(ctx: Context<InitializeTreeState>, depth: u8, genesis_root: [u8;32]) -> Result<()> {
+    /// Retire VK versions below `min_version` for `circuit` (0 re-allows all, including
+    /// the embedded VK). Raise it once clients have moved to a newer finalized version.
+    pub fn set_min_vk_version(ctx: Context<SetMinVkVersion>, circuit: u8, min_version: u16) -> Result<()> {
+        require!(
+            solana_verifier::verifiable_n_public(circuit).is_some(),
+            CipherPayError::VerifyingKeyCircuitMismatch
+        );
+        let old_min_version = ctx.accounts.config.set_min_vk_version(circuit, min_version)?;
+        emit!(MinVkVersionChanged {
+            circuit,
+            old_min_version,
+            new_min_version: min_version,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    pub fn initialize_vault(_ctx: Context<InitializeVault>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Create the per-mint pool: TreeState + MerkleRootCache of tree 0, both seeded by the mint.
+    /// `genesis_root` must be the empty-tree root for `depth` (the frontier starts empty);
+    /// the root cache keeps the last `root_capacity` roots.
+    pub fn initialize_pool(
+        ctx: Context<InitializePool>,
+        depth: u8,
+        genesis_root: [u8;32],
+        root_capacity: u32,
+    ) -> Result<()> {
+        require!(depth > 0 && depth <= MAX_TREE_DEPTH, CipherPayError::InvalidTreeDepth);
         let t = &mut ctx.accounts.tree;
         t.version      = 1;
         t.depth        = depth;
-        t.current_root = genesis_root;
         t.next_index   = 0;
+        t.mint         = ctx.accounts.token_mint.key();
+        t.tree_id      = 0;
+
+        let empty_root = init_tree_frontier(t)?;
+        require!(genesis_root == empty_root, CipherPayError::InvalidGenesisRoot);
+        t.current_root = genesis_root;
+
+        init_root_cache(&ctx.accounts.root_cache, root_capacity, 0, &t.mint, &genesis_root)?;
+        msg!("pool initialized: mint={}, depth={}, root_capacity={}", t.mint, depth, root_capacity);
+        Ok(())
+    }
+
+    /// Open tree `tree_id + 1` of a pool whose current tree is full (same depth, empty frontier).
+    /// Older trees keep their root caches, so notes in them stay spendable.
+    pub fn rollover_tree(ctx: Context<RolloverTree>) -> Result<()> {
+        let old = &ctx.accounts.tree;
+        let t = &mut ctx.accounts.new_tree;
+        t.version    = old.version;
+        t.depth      = old.depth;
+        t.next_index = 0;
+        t.mint       = old.mint;
+        t.tree_id    = old.tree_id
+            .checked_add(1)
+            .ok_or(error!(CipherPayError::ArithmeticError))?;
+
+        let genesis_root = init_tree_frontier(t)?;
+        t.current_root = genesis_root;
+
+        let root_capacity = ctx.accounts.root_cache.load()?.capacity.min(MAX_ROLLOVER_ROOT_CAPACITY);
+        init_root_cache(
+            &ctx.accounts.new_root_cache,
+            root_capacity,
+            t.tree_id,
+            &t.mint,
+            &genesis_root,
+        )?;
+
+        emit!(TreeRolledOver {
+            mint: t.mint,
+            old_tree_id: old.tree_id,
+            new_tree_id: t.tree_id,
+            final_root: old.current_root,
+            genesis_root,
+            depth: t.depth,
+        });
+        Ok(())
+    }
+
+    /// Grow the root history of tree `tree_id` to `new_capacity` roots (admin only).
+    /// Realloc is capped at 10 KiB per instruction, i.e. +213 roots per call.
+    pub fn resize_root_cache(ctx: Context<ResizeRootCache>, tree_id: u32, new_capacity: u32) -> Result<()> {
+        let old_capacity = grow_root_cache(&ctx.accounts.root_cache, new_capacity)?;
+        emit!(RootCacheResized {
+            mint: ctx.accounts.token_mint.key(),
+            tree_id,
+            old_capacity,
+            new_capacity,
+        });
+        Ok(())
+    }
+
+    /// Create the commitment queue of tree `tree_id` with room for `capacity` commitments (admin only).
+    pub fn initialize_commitment_queue(
+        ctx: Context<InitializeCommitmentQueue>,
+        tree_id: u32,
+        capacity: u32,
+    ) -> Result<()> {
+        drop(ctx.accounts.commitment_queue.load_init()?);
+        let mut data = ctx.accounts.commitment_queue.as_ref().try_borrow_mut_data()?;
+        PendingCommitments::init(
+            &mut data[8..],
+            ctx.accounts.token_mint.key(),
+            tree_id,
+            ctx.bumps.commitment_queue,
+            capacity,
+        )?;
+        Ok(())
+    }
+
+    /// Switch tree `tree_id` in or out of sequencer mode (admin only).
+    /// Leaving it requires an empty queue; a stale frontier must then be synced
+    /// before on-chain appends resume.
+    pub fn set_sequencer_mode(ctx: Context<SetSequencerMode>, tree_id: u32, enabled: bool) -> Result<()> {
+        if !enabled {
+            require!(
+                ctx.accounts.commitment_queue.load()?.count == 0,
+                CipherPayError::TreeSequenced
+            );
+        }
+        ctx.accounts.tree.sequencer_mode = enabled as u8;
+        emit!(SequencerModeChanged {
+            mint: ctx.accounts.token_mint.key(),
+            tree_id,
+            enabled,
+            admin: ctx.accounts.admin.key(),
+        });
+        Ok(())
+    }
+
+    /// Insert the N oldest queued commitments with one batch-insert proof moving
+    /// `current_root` to the proven root; the root cache is updated once per batch.
+    /// The frontier goes stale (see `sync_tree_frontier`).
+    pub fn insert_queued_commitments<'info>(
+        ctx: Context<'_, '_, 'info, 'info, InsertQueuedCommitments<'info>>,
+        proof_bytes: Vec<u8>,
+        public_inputs_bytes: Vec<u8>,
+    ) -> Result<()> {
+        let cfg = &ctx.accounts.config;
+        require!(
+            !cfg.is_paused(ProgramConfig::PAUSE_DEPOSIT | ProgramConfig::PAUSE_TRANSFER),
+            CipherPayError::OperationPaused
+        );
+
+        let n = solana_verifier::batch_insert_size(ctx.accounts.verifying_key.circuit)
+            .ok_or(error!(CipherPayError::VerifyingKeyCircuitMismatch))?;
+        require_eq!(
+            public_inputs_bytes.len(),
+            batch_insert_idx::n_public(n) * 32,
+            CipherPayError::InvalidPublicInputsLength
+        );
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        {
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                ctx.accounts.verifying_key.circuit,
+                Some(&ctx.accounts.verifying_key),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
+        }
+        let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+        let old_root    = sigs[batch_insert_idx::OLD_ROOT];
+        let new_root    = sigs[batch_insert_idx::NEW_ROOT];
+        let start_index = le_bytes_32_to_u64(&sigs[batch_insert_idx::START_INDEX]);
+
+        // --- the proof must extend the on-chain tree with the queue's head, in order ---
+        let tree = &mut ctx.accounts.tree;
+        require!(old_root == tree.current_root, CipherPayError::OldRootMismatch);
+        require!(start_index == tree.next_index as u64, CipherPayError::NextLeafIndexMismatch);
+        let end = start_index + n as u64;
+        require!(end <= tree.capacity(), CipherPayError::TreeFull);
+
+        let mut queue = commitment_queue_mut(&ctx.accounts.commitment_queue, tree)?;
+        let head = queue.front(n)?;
+        for i in 0..n {
+            require!(
+                head[i * 32..(i + 1) * 32] == sigs[batch_insert_idx::leaf(i)],
+                CipherPayError::PayloadBindingMismatch
+            );
+        }
+        queue.pop_front(n)?;
+        drop(queue);
+
+        tree.current_root   = new_root;
+        tree.next_index     = end as u32;
+        tree.frontier_stale = 1;
+        insert_merkle_root(&new_root, &mut ctx.accounts.root_cache, cfg.root_min_age_slots)?;
+
+        // --- deposits in this batch get the root that first contains their leaf ---
+        for ai in ctx.remaining_accounts {
+            require!(ai.is_writable, CipherPayError::InvalidInput);
+            let mut marker = Account::<DepositMarker>::try_from(ai)?;
+            require!(
+                marker.root_pending
+                    && marker.mint == tree.mint
+                    && marker.tree_id == tree.tree_id
+                    && (start_index..end).contains(&(marker.leaf_index as u64)),
+                CipherPayError::InvalidInput
+            );
+            marker.merkle_root  = new_root;
+            marker.root_pending = false;
+            marker.exit(ctx.program_id)?;
+        }
+
+        emit!(QueuedCommitmentsInserted {
+            mint: tree.mint,
+            tree_id: tree.tree_id,
+            old_merkle_root: old_root,
+            new_merkle_root: new_root,
+            start_index: start_index as u32,
+            count: n as u32,
+            batcher: ctx.accounts.batcher.key(),
+        });
+        Ok(())
+    }
+
+    /// Replace a stale frontier with `filled_subtrees` (one node per level, LE),
+    /// accepted only if it reproduces the tree's current root.
+    pub fn sync_tree_frontier(ctx: Context<SyncTreeFrontier>, filled_subtrees: Vec<[u8; 32]>) -> Result<()> {
+        let tree = &mut ctx.accounts.tree;
+        sync_frontier(tree, filled_subtrees)?;
+        emit!(TreeFrontierSynced {
+            mint: tree.mint,
+            tree_id: tree.tree_id,
+            root: tree.current_root,
+            next_index: tree.next_index,
+        });
         Ok(())
     }
 
@@ -134,60 +604,84 @@ pub mod cipherpay_anchor {
         proof_bytes: Vec<u8>,
         public_inputs_bytes: Vec<u8>,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_DEPOSIT),
+            CipherPayError::OperationPaused
+        );
         require!(deposit_hash.len() == 32, CipherPayError::InvalidInput);
         let mut deposit_hash32 = [0u8; 32];
         deposit_hash32.copy_from_slice(&deposit_hash);
 
+        // A closed marker leaves its hash in the processed-deposits set
+        assert_deposit_hash_unused(
+            ctx.program_id,
+            &ctx.accounts.processed_deposits.to_account_info(),
+            &deposit_hash32,
+        )?;
+
         let marker = &mut ctx.accounts.deposit_marker;
         if marker.processed {
             return Ok(());
         }
-        marker.bump = ctx.bumps.deposit_marker;
+        marker.bump         = ctx.bumps.deposit_marker;
+        marker.payer        = ctx.accounts.payer.key();
+        marker.created_slot = Clock::get()?.slot;
+        marker.mint         = ctx.accounts.token_mint.key();
+        marker.tree_id      = ctx.accounts.tree.tree_id;
 
-        #[cfg(feature = "real-crypto")]
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
         {
-            solana_verifier::verify_deposit(&proof_bytes, &public_inputs_bytes)
-                .map_err(|_| error!(CipherPayError::InvalidZkProof))?;
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                solana_verifier::circuit_id::DEPOSIT,
+                ctx.accounts.verifying_key.as_deref(),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
 
             let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
                 .map_err(|_| error!(CipherPayError::InvalidZkProof))?;
 
             let new_commitment        = sigs[deposit_idx::NEW_COMMITMENT];
             let owner_cipherpay_pk    = sigs[deposit_idx::OWNER_CIPHERPAY_PUBKEY];
-            let new_root              = sigs[deposit_idx::NEW_MERKLE_ROOT];
-            let new_next_leaf_index   = sigs[deposit_idx::NEW_NEXT_LEAF_INDEX];
             let amount_fe             = sigs[deposit_idx::AMOUNT];
             let expected_deposit_hash = sigs[deposit_idx::DEPOSIT_HASH];
-            let old_root              = sigs[deposit_idx::OLD_MERKLE_ROOT]; // <— NEW
 
             require!(expected_deposit_hash == deposit_hash32, CipherPayError::InvalidZkProof);
+            require!(
+                sigs[deposit_idx::TOKEN_ID] == token_id_for(&ctx.accounts.token_mint.key()),
+                CipherPayError::TokenIdMismatch
+            );
 
             // FE (LE) -> u64
             let mut amount_u64: u64 = 0;
             for i in 0..8 { amount_u64 |= (amount_fe[i] as u64) << (8*i); }
+            assert_deposit_within_limits(ctx.program_id, &ctx.accounts.pool_config, amount_u64)?;
 
             // Atomicity with the SPL tx in the same transaction
             assert_memo_in_same_tx(&ctx.accounts.instructions, &deposit_hash32)?;
+            // `amount_u64` must be what lands in the vault (net of any Token-2022 transfer fee)
             assert_transfer_checked_in_same_tx(
                 &ctx.accounts.instructions,
                 &ctx.accounts.vault_token_account.key(),
                 amount_u64,
+                &ctx.accounts.token_mint.to_account_info(),
+                &mut Vec::new(),
             )?;
 
-            // Single-history checks
-            msg!("Deposit: old_root: {:?}", old_root);
-            msg!("Deposit: tree.current_root: {:?}", ctx.accounts.tree.current_root);
-            require!(old_root == ctx.accounts.tree.current_root, CipherPayError::OldRootMismatch);
-            let sig_next = u32::from_le_bytes([new_next_leaf_index[0], new_next_leaf_index[1], new_next_leaf_index[2], new_next_leaf_index[3]]);
-            require!(sig_next == ctx.accounts.tree.next_index + 1, CipherPayError::InvalidInput);
-
-            // State updates
-            ctx.accounts.tree.current_root = new_root;
-            ctx.accounts.tree.next_index   = sig_next;
-
-
-            insert_merkle_root(&new_root, &mut ctx.accounts.root_cache);
+            // Append against the on-chain frontier (no root race with other deposits),
+            // or queue it when the tree is in sequencer mode
+            let old_root = ctx.accounts.tree.current_root;
+            let (new_roots, sig_next) = add_commitments(
+                &mut ctx.accounts.tree,
+                &mut ctx.accounts.root_cache,
+                ctx.accounts.commitment_queue.as_ref(),
+                &[new_commitment],
+                ctx.accounts.config.root_min_age_slots,
+            )?;
+            let new_root = new_roots[0];
 
+            marker.set_appended(new_root, sig_next - 1);
             marker.processed = true;
             emit!(DepositCompleted {
                 deposit_hash: deposit_hash32,
@@ -197,58 +691,422 @@ pub mod cipherpay_anchor {
                 new_merkle_root: new_root,
                 next_leaf_index: sig_next,
                 mint: ctx.accounts.token_mint.key(),
+                tree_id: ctx.accounts.tree.tree_id,
             });
         }
 
-        #[cfg(not(feature = "real-crypto"))]
+        #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
         {
             assert_memo_in_same_tx(&ctx.accounts.instructions, &deposit_hash32)?;
             assert_transfer_checked_in_same_tx(
                 &ctx.accounts.instructions,
                 &ctx.accounts.vault_token_account.key(),
                 0,
+                &ctx.accounts.token_mint.to_account_info(),
+                &mut Vec::new(),
             )?;
 
-            // For stub builds, still bump the cursor deterministically.
-            ctx.accounts.tree.next_index = ctx.accounts.tree.next_index.saturating_add(1);
+            // For stub builds, still append the (unverified) commitment so the
+            // frontier and next_index stay in sync.
+            let new_commitment = solana_verifier::extract_public_input(&public_inputs_bytes, deposit_idx::NEW_COMMITMENT)
+                .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+            let old_root = ctx.accounts.tree.current_root;
+            let (new_roots, sig_next) = add_commitments(
+                &mut ctx.accounts.tree,
+                &mut ctx.accounts.root_cache,
+                ctx.accounts.commitment_queue.as_ref(),
+                &[new_commitment],
+                ctx.accounts.config.root_min_age_slots,
+            )?;
+            let new_root = new_roots[0];
 
+            marker.set_appended(new_root, sig_next - 1);
             marker.processed = true;
             emit!(DepositCompleted {
                 deposit_hash: deposit_hash32,
                 owner_cipherpay_pubkey: [0u8; 32],
-                commitment: [0u8; 32],
-                old_merkle_root: [0u8; 32],
-                new_merkle_root: [0u8; 32],
-                next_leaf_index: ctx.accounts.tree.next_index,
+                commitment: new_commitment,
+                old_merkle_root: old_root,
+                new_merkle_root: new_root,
+                next_leaf_index: sig_next,
                 mint: ctx.accounts.token_mint.key(),
+                tree_id: ctx.accounts.tree.tree_id,
             });
         }
 
         Ok(())
     }
 
+    /// Deposit K notes with one aggregated proof. Every deposit still needs its own
+    /// memo ("deposit:<hash>") and its own SPL transfer to the vault in the same tx.
+    pub fn shielded_deposit_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ShieldedDepositBatch<'info>>,
+        proof_bytes: Vec<u8>,
+        public_inputs_bytes: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_DEPOSIT),
+            CipherPayError::OperationPaused
+        );
+
+        // --- shape: K from the registry circuit id ---
+        let k = solana_verifier::deposit_batch_size(ctx.accounts.verifying_key.circuit)
+            .ok_or(error!(CipherPayError::VerifyingKeyCircuitMismatch))?;
+        require_eq!(
+            public_inputs_bytes.len(),
+            deposit_batch_idx::n_public(k) * 32,
+            CipherPayError::InvalidPublicInputsLength
+        );
+        require_eq!(ctx.remaining_accounts.len(), 2 * k, CipherPayError::InvalidInput);
+
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        {
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                ctx.accounts.verifying_key.circuit,
+                Some(&ctx.accounts.verifying_key),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
+        }
+        let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+        let field = |i: usize, f: usize| sigs[deposit_batch_idx::signal(i, f)];
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        {
+            let token_id = token_id_for(&ctx.accounts.token_mint.key());
+            for i in 0..k {
+                require!(field(i, deposit_idx::TOKEN_ID) == token_id, CipherPayError::TokenIdMismatch);
+            }
+        }
+
+        let deposit_hashes: Vec<[u8; 32]> = (0..k).map(|i| field(i, deposit_idx::DEPOSIT_HASH)).collect();
+        let commitments: Vec<[u8; 32]> = (0..k).map(|i| field(i, deposit_idx::NEW_COMMITMENT)).collect();
+        let owners: Vec<[u8; 32]> = (0..k).map(|i| field(i, deposit_idx::OWNER_CIPHERPAY_PUBKEY)).collect();
+        for i in 0..k {
+            for j in (i + 1)..k {
+                require!(deposit_hashes[i] != deposit_hashes[j], CipherPayError::DepositAlreadyUsed);
+            }
+        }
+
+        // --- one memo and one transfer per deposit (no transfer funds two deposits) ---
+        let limits = load_pool_config(ctx.program_id, &ctx.accounts.pool_config)?;
+        let mut total_amount: u64 = 0;
+        let mut used_transfers = Vec::with_capacity(k);
+        for (i, dh) in deposit_hashes.iter().enumerate() {
+            let amount = le_bytes_32_to_u64(&field(i, deposit_idx::AMOUNT));
+            if let Some(limits) = &limits {
+                limits.check_deposit(amount)?;
+            }
+            total_amount = total_amount
+                .checked_add(amount)
+                .ok_or(error!(CipherPayError::ArithmeticError))?;
+            assert_memo_in_same_tx(&ctx.accounts.instructions, dh)?;
+            assert_deposit_hash_unused(ctx.program_id, &ctx.remaining_accounts[k + i], dh)?;
+
+            // Stub builds keep the single-deposit wildcard (see `shielded_deposit_atomic`);
+            // `mock-verifier` binds the amount like `real-crypto`
+            #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+            let expected_amount = amount;
+            #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
+            let expected_amount = 0;
+            assert_transfer_checked_in_same_tx(
+                &ctx.accounts.instructions,
+                &ctx.accounts.vault_token_account.key(),
+                expected_amount,
+                &ctx.accounts.token_mint.to_account_info(),
+                &mut used_transfers,
+            )?;
+        }
+
+        // --- append the K commitments against the on-chain frontier ---
+        let old_root = ctx.accounts.tree.current_root;
+        let (new_roots, sig_next) = add_commitments(
+            &mut ctx.accounts.tree,
+            &mut ctx.accounts.root_cache,
+            ctx.accounts.commitment_queue.as_ref(),
+            &commitments,
+            ctx.accounts.config.root_min_age_slots,
+        )?;
+        let tree_id = ctx.accounts.tree.tree_id;
+        let tree_queued = ctx.accounts.tree.sequencer_mode != 0;
+
+        // --- one processed marker per deposit (closable like single-deposit markers) ---
+        let slot = Clock::get()?.slot;
+        for (i, dh) in deposit_hashes.iter().enumerate() {
+            create_deposit_marker(
+                ctx.program_id,
+                &ctx.remaining_accounts[i],
+                dh,
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                DepositMarker {
+                    processed: true,
+                    bump: 0,
+                    payer: ctx.accounts.payer.key(),
+                    created_slot: slot,
+                    mint: ctx.accounts.token_mint.key(),
+                    tree_id,
+                    merkle_root: new_roots[i],
+                    leaf_index: sig_next - k as u32 + i as u32,
+                    root_pending: tree_queued,
+                },
+            )?;
+        }
+
+        emit!(DepositBatchCompleted {
+            deposit_hashes,
+            owner_cipherpay_pubkeys: owners,
+            commitments,
+            old_merkle_root: old_root,
+            new_merkle_roots: new_roots,
+            next_leaf_index: sig_next,
+            total_amount,
+            mint: ctx.accounts.token_mint.key(),
+            tree_id,
+        });
+        Ok(())
+    }
+
+    /// Two-phase deposit, phase one: escrow `amount` in the pool vault under a
+    /// `PendingDeposit` keyed by `deposit_hash`, to be finalized with a proof later.
+    pub fn create_pending_deposit(
+        ctx: Context<CreatePendingDeposit>,
+        deposit_hash: [u8; 32],
+        amount: u64,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_DEPOSIT),
+            CipherPayError::OperationPaused
+        );
+        require!(amount > 0, CipherPayError::InvalidInput);
+        assert_deposit_hash_unused(
+            ctx.program_id,
+            &ctx.accounts.processed_deposits.to_account_info(),
+            &deposit_hash,
+        )?;
+
+        let mint = &ctx.accounts.token_mint;
+        let cpi_accounts = TransferChecked {
+            from:      ctx.accounts.depositor_token_account.to_account_info(),
+            mint:      mint.to_account_info(),
+            to:        ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)
+            .map_err(|_| error!(CipherPayError::TokenTransferFailed))?;
+        // The note must commit to what actually landed in the vault
+        let net = amount
+            .checked_sub(transfer_fee_for(&mint.to_account_info(), amount)?)
+            .ok_or(error!(CipherPayError::ArithmeticError))?;
+        assert_deposit_within_limits(ctx.program_id, &ctx.accounts.pool_config, net)?;
+
+        let pending = &mut ctx.accounts.pending_deposit;
+        pending.depositor    = ctx.accounts.depositor.key();
+        pending.mint         = mint.key();
+        pending.amount       = net;
+        pending.created_slot = Clock::get()?.slot;
+        pending.bump         = ctx.bumps.pending_deposit;
+
+        emit!(PendingDepositCreated {
+            deposit_hash,
+            depositor: pending.depositor,
+            mint: pending.mint,
+            amount: net,
+            refundable_at: pending.refundable_at(),
+        });
+        Ok(())
+    }
+
+    /// Two-phase deposit, phase two: a deposit proof whose amount equals the escrowed
+    /// amount appends the note; the escrow is closed (rent back to the depositor).
+    pub fn finalize_pending_deposit(
+        ctx: Context<FinalizePendingDeposit>,
+        deposit_hash: [u8; 32],
+        proof_bytes: Vec<u8>,
+        public_inputs_bytes: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_DEPOSIT),
+            CipherPayError::OperationPaused
+        );
+        assert_deposit_hash_unused(
+            ctx.program_id,
+            &ctx.accounts.processed_deposits.to_account_info(),
+            &deposit_hash,
+        )?;
+
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        let (new_commitment, owner_cipherpay_pk) = {
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                solana_verifier::circuit_id::DEPOSIT,
+                ctx.accounts.verifying_key.as_deref(),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
+            let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+                .map_err(|_| error!(CipherPayError::InvalidZkProof))?;
+            require!(sigs[deposit_idx::DEPOSIT_HASH] == deposit_hash, CipherPayError::InvalidZkProof);
+            require!(
+                sigs[deposit_idx::TOKEN_ID] == token_id_for(&ctx.accounts.token_mint.key()),
+                CipherPayError::TokenIdMismatch
+            );
+            require_eq!(
+                le_bytes_32_to_u64(&sigs[deposit_idx::AMOUNT]),
+                ctx.accounts.pending_deposit.amount,
+                CipherPayError::DepositAmountMismatch
+            );
+            (sigs[deposit_idx::NEW_COMMITMENT], sigs[deposit_idx::OWNER_CIPHERPAY_PUBKEY])
+        };
+
+        #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
+        let (new_commitment, owner_cipherpay_pk) = {
+            let c = solana_verifier::extract_public_input(&public_inputs_bytes, deposit_idx::NEW_COMMITMENT)
+                .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+            (c, [0u8; 32])
+        };
+
+        let old_root = ctx.accounts.tree.current_root;
+        let (new_roots, sig_next) = add_commitments(
+            &mut ctx.accounts.tree,
+            &mut ctx.accounts.root_cache,
+            ctx.accounts.commitment_queue.as_ref(),
+            &[new_commitment],
+            ctx.accounts.config.root_min_age_slots,
+        )?;
+
+        let marker = &mut ctx.accounts.deposit_marker;
+        marker.processed    = true;
+        marker.bump         = ctx.bumps.deposit_marker;
+        marker.payer        = ctx.accounts.payer.key();
+        marker.created_slot = Clock::get()?.slot;
+        marker.mint         = ctx.accounts.token_mint.key();
+        marker.tree_id      = ctx.accounts.tree.tree_id;
+        marker.set_appended(new_roots[0], sig_next - 1);
+
+        emit!(DepositCompleted {
+            deposit_hash,
+            owner_cipherpay_pubkey: owner_cipherpay_pk,
+            commitment: new_commitment,
+            old_merkle_root: old_root,
+            new_merkle_root: new_roots[0],
+            next_leaf_index: sig_next,
+            mint: ctx.accounts.token_mint.key(),
+            tree_id: ctx.accounts.tree.tree_id,
+        });
+        Ok(())
+    }
+
+    /// Pay a pending deposit back to its depositor once it timed out unfinalized.
+    pub fn refund_pending_deposit(ctx: Context<RefundPendingDeposit>, deposit_hash: [u8; 32]) -> Result<()> {
+        // A refund is a vault outflow: it stops with withdrawals
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_WITHDRAW),
+            CipherPayError::OperationPaused
+        );
+        let pending = &ctx.accounts.pending_deposit;
+        require!(
+            Clock::get()?.slot >= pending.refundable_at(),
+            CipherPayError::PendingDepositNotExpired
+        );
+        vault_payout(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.vault_token_account.to_account_info(),
+            &ctx.accounts.token_mint,
+            &ctx.accounts.depositor_token_account.to_account_info(),
+            &ctx.accounts.vault_pda.to_account_info(),
+            ctx.bumps.vault_pda,
+            pending.amount,
+        )?;
+        emit!(PendingDepositRefunded {
+            deposit_hash,
+            depositor: pending.depositor,
+            mint: pending.mint,
+            amount: pending.amount,
+        });
+        Ok(())
+    }
+
+    /// Refund a processed deposit marker's rent to its payer once the deposit's root has
+    /// aged out; the hash moves into the processed-deposits set so it can't be replayed.
+    pub fn close_deposit_marker(ctx: Context<CloseDepositMarker>, deposit_hash: [u8; 32]) -> Result<()> {
+        require!(!ctx.accounts.deposit_marker.root_pending, CipherPayError::DepositRootPending);
+        require!(
+            !is_valid_root(
+                &ctx.accounts.deposit_marker.merkle_root,
+                &ctx.accounts.root_cache,
+                ctx.accounts.config.root_max_age_slots,
+            ),
+            CipherPayError::DepositRootStillLive
+        );
+        record_processed_deposit(
+            ctx.program_id,
+            &ctx.accounts.processed_deposits.to_account_info(),
+            &deposit_hash,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        emit!(DepositMarkerClosed {
+            deposit_hash,
+            payer: ctx.accounts.payer.key(),
+            lamports: ctx.accounts.deposit_marker.to_account_info().lamports(),
+        });
+        Ok(())
+    }
+
     pub fn shielded_transfer(
         ctx: Context<ShieldedTransfer>,
         nullifier: Vec<u8>,
         proof_bytes: Vec<u8>,
         public_inputs_bytes: Vec<u8>,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_TRANSFER),
+            CipherPayError::OperationPaused
+        );
+
         // --- basic input checks ---
         require!(nullifier.len() == 32, CipherPayError::InvalidInput);
         let mut nf32 = [0u8; 32];
         nf32.copy_from_slice(&nullifier);
     
-        // --- idempotency: nullifier record ---
-        let rec = &mut ctx.accounts.nullifier_record;
-        require!(!rec.used, CipherPayError::AlreadyProcessed);
-        rec.used = true;
-        rec.bump = ctx.bumps.nullifier_record;   // ← keep only fields that exist
+        // --- idempotency: legacy nullifier record or compact shard, per config ---
+        let nullifier_set = ctx.accounts.config.nullifier_set;
+        if nullifier_set == ProgramConfig::NULLIFIER_SET_PDA {
+            let rec = ctx.accounts.nullifier_record
+                .as_mut()
+                .ok_or(error!(CipherPayError::NullifierSetMismatch))?;
+            require!(!rec.used, CipherPayError::AlreadyProcessed);
+            rec.used = true;
+            rec.bump = ctx.bumps.nullifier_record.unwrap_or_default();   // ← keep only fields that exist
+        } else {
+            require!(ctx.accounts.nullifier_record.is_none(), CipherPayError::NullifierSetMismatch);
+            let shard = ctx.accounts.nullifier_shard
+                .as_ref()
+                .ok_or(error!(CipherPayError::NullifierSetMismatch))?;
+            spend_nullifier_sharded(
+                ctx.program_id,
+                nullifier_set,
+                &shard.to_account_info(),
+                ctx.accounts.legacy_nullifier_record.as_ref().map(|a| a.as_ref()),
+                &nf32,
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+        }
     
         // --- verify + parse public signals ---
-        #[cfg(feature = "real-crypto")]
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
         {
-            solana_verifier::verify_transfer(&proof_bytes, &public_inputs_bytes)
-                .map_err(|_| error!(CipherPayError::InvalidZkProof))?;
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                solana_verifier::circuit_id::TRANSFER,
+                ctx.accounts.verifying_key.as_deref(),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
         }
         let sigs = parse_transfer_publics(&public_inputs_bytes)?;
         let nf               = sigs[transfer_idx::NULLIFIER];
@@ -257,30 +1115,51 @@ pub mod cipherpay_anchor {
         let enc_note1_hash   = sigs[transfer_idx::ENC_NOTE1_HASH];
         let enc_note2_hash   = sigs[transfer_idx::ENC_NOTE2_HASH];
         let old_root         = sigs[transfer_idx::MERKLE_ROOT];
-        let new_root1        = sigs[transfer_idx::NEW_MERKLE_ROOT_1];
-        let new_root2        = sigs[transfer_idx::NEW_MERKLE_ROOT_2];
-        let next_leaf_index  = sigs[transfer_idx::NEW_NEXT_LEAF_INDEX];
+        let fee_u64          = le_bytes_32_to_u64(&sigs[transfer_idx::FEE]);
+        let relayer          = pubkey_from_limbs(&sigs[transfer_idx::RELAYER_LO], &sigs[transfer_idx::RELAYER_HI]);
     
         // ensure nullifier in proof == instruction arg
         require!(nf == nf32, CipherPayError::InvalidZkProof);
+
+        // --- membership root may be any recent root (no race with concurrent appends),
+        //     of the current tree or of an older, rolled-over one ---
+        let membership_cache = ctx.accounts.membership_root_cache
+            .as_ref()
+            .unwrap_or(&ctx.accounts.root_cache);
+        let root_tree_id = root_cache_tree_id(membership_cache, &ctx.accounts.token_mint.key())?;
+        require!(
+            is_valid_root(&old_root, membership_cache, ctx.accounts.config.root_max_age_slots),
+            CipherPayError::UnknownMerkleRoot
+        );
+
+        // --- relayer fee: circuit enforces in = out1 + out2 + fee; pay it from the vault ---
+        if fee_u64 > 0 {
+            let relayer_ata = ctx.accounts.relayer_token_account
+                .as_ref()
+                .ok_or(error!(CipherPayError::RelayerAccountMissing))?;
+            require_keys_eq!(relayer_ata.mint, ctx.accounts.token_mint.key(), CipherPayError::InvalidInput);
+            require_keys_eq!(relayer_ata.owner, relayer, CipherPayError::RelayerMismatch);
+            vault_payout(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.vault_token_account.to_account_info(),
+                &ctx.accounts.token_mint,
+                &relayer_ata.to_account_info(),
+                &ctx.accounts.vault_pda.to_account_info(),
+                ctx.bumps.vault_pda,
+                fee_u64,
+            )?;
+        }
     
-        // --- strict sync with on-chain tree history ---
-        let tree = &mut ctx.accounts.tree;
-        msg!("Transfer: old_root: {:?}", old_root);
-        msg!("Transfer: tree.current_root: {:?}", tree.current_root);
-        require!(old_root == tree.current_root, CipherPayError::OldRootMismatch);
-    
-        // transfer inserts two leaves → next_index must jump by 2
-        let sig_next: u32 = u32_le(&next_leaf_index);
-        require!(sig_next == tree.next_index.saturating_add(2), CipherPayError::InvalidInput);
-    
-        // --- commit state: advance to the *final* new root ---
-        tree.current_root = new_root2;
-        tree.next_index   = sig_next;
-    
-        // --- cache both intermediate roots (zero-copy) ---
-        msg!("inserting roots: {:?}, {:?}", new_root1, new_root2);
-        insert_many_roots(&[new_root1, new_root2], &mut ctx.accounts.root_cache);
+        // --- append both outputs against the on-chain frontier ---
+        //     (both intermediate roots are cached; queued instead in sequencer mode)
+        let (new_roots, sig_next) = add_commitments(
+            &mut ctx.accounts.tree,
+            &mut ctx.accounts.root_cache,
+            ctx.accounts.commitment_queue.as_ref(),
+            &[out1_commitment, out2_commitment],
+            ctx.accounts.config.root_min_age_slots,
+        )?;
+        let (new_root1, new_root2) = (new_roots[0], new_roots[1]);
     
         emit!(TransferCompleted {
             nullifier: nf32,
@@ -292,18 +1171,160 @@ pub mod cipherpay_anchor {
             new_merkle_root1: new_root1,
             new_merkle_root2: new_root2,
             next_leaf_index: sig_next,
-            mint: Pubkey::default(),
+            mint: ctx.accounts.token_mint.key(),
+            tree_id: ctx.accounts.tree.tree_id,
+            root_tree_id,
+            fee: fee_u64,
+            relayer: if fee_u64 > 0 { relayer } else { Pubkey::default() },
         });
     
         Ok(())
     }
 
+    /// Join-split transfer: spend `nullifiers.len()` inputs, append M outputs.
+    /// Publics follow `joinsplit_idx` for the (N, M) shape of `verifying_key`.
+    pub fn shielded_transfer_joinsplit<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ShieldedTransferJoinSplit<'info>>,
+        nullifiers: Vec<[u8; 32]>,
+        proof_bytes: Vec<u8>,
+        public_inputs_bytes: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_TRANSFER),
+            CipherPayError::OperationPaused
+        );
+
+        // --- shape: N from the args, M from the registry circuit id ---
+        let (n, m) = solana_verifier::joinsplit_shape(ctx.accounts.verifying_key.circuit)
+            .ok_or(error!(CipherPayError::VerifyingKeyCircuitMismatch))?;
+        require_eq!(nullifiers.len(), n, CipherPayError::PublicInputCountMismatch);
+        require_eq!(
+            public_inputs_bytes.len(),
+            joinsplit_idx::n_public(n, m) * 32,
+            CipherPayError::InvalidPublicInputsLength
+        );
+        let nullifier_set = ctx.accounts.config.nullifier_set;
+        let nullifier_accounts = joinsplit_nullifier_accounts(nullifier_set, ctx.remaining_accounts, n)?;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                require!(nullifiers[i] != nullifiers[j], CipherPayError::NullifierAlreadyUsed);
+            }
+        }
+
+        // --- verify + parse public signals ---
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        {
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                ctx.accounts.verifying_key.circuit,
+                Some(&ctx.accounts.verifying_key),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
+        }
+        let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+
+        for (i, nf) in nullifiers.iter().enumerate() {
+            require!(sigs[joinsplit_idx::nullifier(n, m, i)] == *nf, CipherPayError::NullifierMismatch);
+        }
+        let old_root        = sigs[joinsplit_idx::merkle_root(n, m)];
+        let fee_u64         = le_bytes_32_to_u64(&sigs[joinsplit_idx::fee(n, m)]);
+        let relayer         = pubkey_from_limbs(
+            &sigs[joinsplit_idx::relayer_lo(n, m)],
+            &sigs[joinsplit_idx::relayer_hi(n, m)],
+        );
+        let out_commitments: Vec<[u8; 32]> = (0..m).map(|j| sigs[joinsplit_idx::out_commitment(n, m, j)]).collect();
+        let enc_note_hashes: Vec<[u8; 32]> = (0..m).map(|j| sigs[joinsplit_idx::enc_note_hash(n, m, j)]).collect();
+
+        // --- membership root may be any recent root, of the current or an older tree ---
+        let membership_cache = ctx.accounts.membership_root_cache
+            .as_ref()
+            .unwrap_or(&ctx.accounts.root_cache);
+        let root_tree_id = root_cache_tree_id(membership_cache, &ctx.accounts.token_mint.key())?;
+        require!(
+            is_valid_root(&old_root, membership_cache, ctx.accounts.config.root_max_age_slots),
+            CipherPayError::UnknownMerkleRoot
+        );
+
+        // --- one NullifierRecord PDA (or shard [+ legacy record]) per input, same order ---
+        for (nf, &(ai, legacy)) in nullifiers.iter().zip(&nullifier_accounts) {
+            if nullifier_set == ProgramConfig::NULLIFIER_SET_PDA {
+                spend_nullifier_pda(
+                    ctx.program_id,
+                    ai,
+                    nf,
+                    &ctx.accounts.payer.to_account_info(),
+                    &ctx.accounts.system_program.to_account_info(),
+                )?;
+            } else {
+                spend_nullifier_sharded(
+                    ctx.program_id,
+                    nullifier_set,
+                    ai,
+                    legacy,
+                    nf,
+                    &ctx.accounts.payer.to_account_info(),
+                    &ctx.accounts.system_program.to_account_info(),
+                )?;
+            }
+        }
+
+        // --- relayer fee: circuit enforces sum(in) = sum(out) + fee ---
+        if fee_u64 > 0 {
+            let relayer_ata = ctx.accounts.relayer_token_account
+                .as_ref()
+                .ok_or(error!(CipherPayError::RelayerAccountMissing))?;
+            require_keys_eq!(relayer_ata.mint, ctx.accounts.token_mint.key(), CipherPayError::InvalidInput);
+            require_keys_eq!(relayer_ata.owner, relayer, CipherPayError::RelayerMismatch);
+            vault_payout(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.vault_token_account.to_account_info(),
+                &ctx.accounts.token_mint,
+                &relayer_ata.to_account_info(),
+                &ctx.accounts.vault_pda.to_account_info(),
+                ctx.bumps.vault_pda,
+                fee_u64,
+            )?;
+        }
+
+        // --- append the M outputs against the on-chain frontier ---
+        let (new_roots, sig_next) = add_commitments(
+            &mut ctx.accounts.tree,
+            &mut ctx.accounts.root_cache,
+            ctx.accounts.commitment_queue.as_ref(),
+            &out_commitments,
+            ctx.accounts.config.root_min_age_slots,
+        )?;
+
+        emit!(JoinSplitCompleted {
+            nullifiers,
+            out_commitments,
+            enc_note_hashes,
+            merkle_root_before: old_root,
+            new_merkle_roots: new_roots,
+            next_leaf_index: sig_next,
+            mint: ctx.accounts.token_mint.key(),
+            tree_id: ctx.accounts.tree.tree_id,
+            root_tree_id,
+            fee: fee_u64,
+            relayer: if fee_u64 > 0 { relayer } else { Pubkey::default() },
+        });
+
+        Ok(())
+    }
+
     pub fn shielded_withdraw(
         ctx: Context<ShieldedWithdraw>,
         nullifier: Vec<u8>,
         proof_bytes: Vec<u8>,
         public_inputs_bytes: Vec<u8>,
     ) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_WITHDRAW),
+            CipherPayError::OperationPaused
+        );
+
         // -------------------- 0) Byte-size sanity (cheap, first) --------------------
         require_eq!(nullifier.len(), 32, CipherPayError::InvalidInput);
     
@@ -314,14 +1335,20 @@ pub mod cipherpay_anchor {
             CipherPayError::InvalidProofBytesLength
         );
     
-        // UPDATED: Withdraw publics = 7 * 32 = 224 bytes
+        // UPDATED: Withdraw publics = 10 * 32 = 320 bytes
         // [0] nullifier, [1] root, [2] recip_owner_lo, [3] recip_owner_hi,
-        //  [4] recip_wallet_pk, [5] amount, [6] token_id
-        require_eq!(
-            public_inputs_bytes.len(),
-            7 * 32,
+        //  [4] recip_wallet_pk, [5] amount, [6] token_id,
+        //  [7] fee, [8] relayer_lo, [9] relayer_hi
+        // Change mode = 12 * 32 = 384 bytes, adding
+        //  [10] change_commitment, [11] enc_change_note_hash (appended on-chain)
+        // Refundable modes append refund_commitment (kept for a time-locked payout)
+        require!(
+            public_inputs_bytes.len().is_multiple_of(32),
             CipherPayError::InvalidPublicInputsLength
         );
+        let (circuit, with_change, refundable) =
+            solana_verifier::withdraw_layout(public_inputs_bytes.len() / 32)
+                .ok_or(error!(CipherPayError::InvalidPublicInputsLength))?;
     
         // Make fixed-size views; avoids Vec allocations/copies
         let nf32: &[u8; 32] = public_inputs_bytes[0..32]
@@ -340,13 +1367,24 @@ pub mod cipherpay_anchor {
             .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
     
         // Shifted indices for the remaining items
-        let _recipient_pk32: &[u8; 32] = public_inputs_bytes[128..160]
+        let recipient_pk32: &[u8; 32] = public_inputs_bytes[128..160]
             .try_into()
             .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
         let amount_fe32: &[u8; 32] = public_inputs_bytes[160..192]
             .try_into()
             .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
-        let _token_id32: &[u8; 32] = public_inputs_bytes[192..224]
+        let token_id32: &[u8; 32] = public_inputs_bytes[192..224]
+            .try_into()
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+
+        // Relayer fee + relayer wallet limbs (fee is carved out of `amount`)
+        let fee_fe32: &[u8; 32] = public_inputs_bytes[224..256]
+            .try_into()
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+        let relayer_lo32: &[u8; 32] = public_inputs_bytes[256..288]
+            .try_into()
+            .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+        let relayer_hi32: &[u8; 32] = public_inputs_bytes[288..320]
             .try_into()
             .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
     
@@ -359,15 +1397,44 @@ pub mod cipherpay_anchor {
             tmp.copy_from_slice(&amount_fe32[0..8]);
             u64::from_le_bytes(tmp)
         };
+        let fee_u64 = le_bytes_32_to_u64(fee_fe32);
+        require!(fee_u64 <= amount_u64, CipherPayError::InvalidWithdrawAmount);
+        // Pool limits: max single withdrawal and the epoch outflow cap
+        let pool_limits = record_pool_withdraw(ctx.program_id, &ctx.accounts.pool_config, amount_u64)?;
+        // Above the pool threshold the payout is time-locked in `pending_withdrawal`
+        let timelock = pool_limits.filter(|l| l.is_timelocked(amount_u64));
+        require!(
+            timelock.is_some() == ctx.accounts.pending_withdrawal.is_some(),
+            CipherPayError::PendingWithdrawalMismatch
+        );
+        // A cancelled time-lock refunds into the note the spender proved, never one picked later
+        let refund_commitment = refundable.then(|| {
+            let i = withdraw_idx::refund_commitment(with_change) * 32;
+            let mut c = [0u8; 32];
+            c.copy_from_slice(&public_inputs_bytes[i..i + 32]);
+            c
+        });
+        require!(
+            timelock.is_none() || refund_commitment.is_some(),
+            CipherPayError::RefundCommitmentRequired
+        );
     
         // -------------------- 1) Cheap state checks (before verifier) --------------------
-        // Nullifier must not be used yet (idempotency)
-        let rec = &mut ctx.accounts.nullifier_record;
-        require!(!rec.used, CipherPayError::AlreadyProcessed);
+        // Nullifier must not be used yet (idempotency); shards are checked when spent below
+        let nullifier_set = ctx.accounts.config.nullifier_set;
+        if nullifier_set == ProgramConfig::NULLIFIER_SET_PDA {
+            let rec = ctx.accounts.nullifier_record
+                .as_ref()
+                .ok_or(error!(CipherPayError::NullifierSetMismatch))?;
+            require!(!rec.used, CipherPayError::AlreadyProcessed);
+        } else {
+            require!(ctx.accounts.nullifier_record.is_none(), CipherPayError::NullifierSetMismatch);
+        }
     
-        // Root must be in cache (prevents verifier work if invalid)
+        // Root must be in the cache of one of this pool's trees (prevents verifier work if invalid)
+        let root_tree_id = root_cache_tree_id(&ctx.accounts.root_cache, &ctx.accounts.token_mint.key())?;
         require!(
-            is_valid_root(root32, &ctx.accounts.root_cache),
+            is_valid_root(root32, &ctx.accounts.root_cache, ctx.accounts.config.root_max_age_slots),
             CipherPayError::UnknownMerkleRoot
         );
     
@@ -402,13 +1469,37 @@ pub mod cipherpay_anchor {
             expected_owner,
             CipherPayError::InvalidInput
         );
+
+        // Relayer fee: the proof commits to the relayer wallet, so a copied proof
+        // cannot redirect the fee. Relayer ATA must be (mint = token_mint, owner = relayer).
+        let relayer = pubkey_from_limbs(relayer_lo32, relayer_hi32);
+        if fee_u64 > 0 {
+            let relayer_ata = ctx.accounts.relayer_token_account
+                .as_ref()
+                .ok_or(error!(CipherPayError::RelayerAccountMissing))?;
+            require_keys_eq!(relayer_ata.mint, ctx.accounts.token_mint.key(), CipherPayError::InvalidInput);
+            require_keys_eq!(relayer_ata.owner, relayer, CipherPayError::RelayerMismatch);
+        }
     
         // -------------------- 2) Proof verification (after cheap guards) --------------------
-        #[cfg(feature = "real-crypto")]
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
         {
-            // Verify Groth16 proof; use bounded parsing internally
-            solana_verifier::verify_withdraw(&proof_bytes, &public_inputs_bytes)
-                .map_err(|_| error!(CipherPayError::InvalidZkProof))?;
+            // tokenId = pool mint, recipientWalletPubKey = the recipient ATA paid below
+            assert_withdraw_destination(
+                token_id32,
+                recipient_pk32,
+                &ctx.accounts.token_mint.key(),
+                &ctx.accounts.recipient_token_account.key(),
+            )?;
+
+            // Verify Groth16 proof (registry VK if supplied); use bounded parsing internally
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                circuit,
+                ctx.accounts.verifying_key.as_deref(),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
     
             // (Optional, belt-and-suspenders) Re-parse exact publics and re-check consistency
             let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
@@ -437,48 +1528,304 @@ pub mod cipherpay_anchor {
             );
         }
     
-        #[cfg(not(feature = "real-crypto"))]
+        #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
         {
             // Stub build: no zk verification, we already parsed/publicly checked values above.
         }
     
-        // -------------------- 3) CPI: vault -> recipient (if amount > 0) --------------------
-        if amount_u64 > 0 {
-            let vault_bump = ctx.bumps.vault_pda;
-            let bump = [vault_bump];
-            let signer_seeds: &[&[u8]] = &[VAULT_SEED, &bump];
-            let signer: &[&[&[u8]]] = &[signer_seeds];
+        // -------------------- 3) CPI: vault -> recipient (amount - fee), vault -> relayer (fee) --------------------
+        // (time-locked: the tokens stay in the vault, owed through `pending_withdrawal`)
+        if let (Some(limits), Some(pending)) = (timelock, ctx.accounts.pending_withdrawal.as_mut()) {
+            pending.mint                    = ctx.accounts.token_mint.key();
+            pending.recipient_token_account = ctx.accounts.recipient_token_account.key();
+            pending.amount                  = amount_u64 - fee_u64;
+            pending.relayer_token_account   = ctx.accounts.relayer_token_account
+                .as_ref()
+                .map(|a| a.key())
+                .unwrap_or_default();
+            pending.fee                     = fee_u64;
+            pending.payer                   = ctx.accounts.payer.key();
+            pending.epoch                   = limits.outflow_epoch;
+            pending.claimable_at            = Clock::get()?.slot.saturating_add(limits.withdraw_delay_slots);
+            pending.refund_commitment       = refund_commitment.unwrap_or_default();
+            pending.bump                    = ctx.bumps.pending_withdrawal.unwrap_or_default();
+            emit!(PendingWithdrawalCreated {
+                nullifier: *nf32,
+                mint: pending.mint,
+                recipient_token_account: pending.recipient_token_account,
+                amount: pending.amount,
+                fee: pending.fee,
+                claimable_at: pending.claimable_at,
+                refund_commitment: pending.refund_commitment,
+            });
+        } else {
+            vault_payout(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.vault_token_account.to_account_info(),
+                &ctx.accounts.token_mint,
+                &ctx.accounts.recipient_token_account.to_account_info(),
+                &ctx.accounts.vault_pda.to_account_info(),
+                ctx.bumps.vault_pda,
+                amount_u64 - fee_u64,
+            )?;
+            if let Some(relayer_ata) = ctx.accounts.relayer_token_account.as_ref() {
+                vault_payout(
+                    &ctx.accounts.token_program.to_account_info(),
+                    &ctx.accounts.vault_token_account.to_account_info(),
+                    &ctx.accounts.token_mint,
+                    &relayer_ata.to_account_info(),
+                    &ctx.accounts.vault_pda.to_account_info(),
+                    ctx.bumps.vault_pda,
+                    fee_u64,
+                )?;
+            }
+        }
     
-            let cpi_accounts = anchor_spl::token::Transfer {
-                from:      ctx.accounts.vault_token_account.to_account_info(),
-                to:        ctx.accounts.recipient_token_account.to_account_info(),
-                authority: ctx.accounts.vault_pda.to_account_info(),
+        // -------------------- 4) Mark nullifier as used (only after success) --------------------
+        if let Some(rec) = ctx.accounts.nullifier_record.as_mut() {
+            rec.used = true;
+            rec.bump = ctx.bumps.nullifier_record.unwrap_or_default();
+        } else {
+            let shard = ctx.accounts.nullifier_shard
+                .as_ref()
+                .ok_or(error!(CipherPayError::NullifierSetMismatch))?;
+            spend_nullifier_sharded(
+                ctx.program_id,
+                nullifier_set,
+                &shard.to_account_info(),
+                ctx.accounts.legacy_nullifier_record.as_ref().map(|a| a.as_ref()),
+                nf32,
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+        }
+
+        // -------------------- 5) Change mode: append the change note --------------------
+        let mut change_commitment = [0u8; 32];
+        let mut enc_change_note_hash = [0u8; 32];
+        let mut next_leaf_index = 0u32;
+        let mut tree_id = root_tree_id;
+        if with_change {
+            let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+                .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+            let tree = ctx.accounts.tree
+                .as_mut()
+                .ok_or(error!(CipherPayError::InvalidInput))?;
+
+            // Append against the on-chain frontier (membership root may be older)
+            change_commitment    = sigs[withdraw_idx::CHANGE_COMMITMENT];
+            enc_change_note_hash = sigs[withdraw_idx::ENC_CHANGE_NOTE_HASH];
+            tree_id = tree.tree_id;
+
+            // New root goes to the current tree's cache (`root_cache` if the spent root is from it)
+            let tree_cache = match ctx.accounts.tree_root_cache.as_mut() {
+                Some(c) => c,
+                None => &mut ctx.accounts.root_cache,
             };
-    
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                cpi_accounts,
-                signer,
+            require_eq!(
+                root_cache_tree_id(tree_cache, &ctx.accounts.token_mint.key())?,
+                tree_id,
+                CipherPayError::RootCacheMismatch
             );
-    
-            anchor_spl::token::transfer(cpi_ctx, amount_u64)
-                .map_err(|_| error!(CipherPayError::TokenTransferFailed))?;
+            (_, next_leaf_index) = add_commitments(
+                tree,
+                tree_cache,
+                ctx.accounts.commitment_queue.as_ref(),
+                &[change_commitment],
+                ctx.accounts.config.root_min_age_slots,
+            )?;
+        } else if let Some(tree) = ctx.accounts.tree.as_ref() {
+            next_leaf_index = tree.next_index;
+            tree_id = tree.tree_id;
         }
     
-        // -------------------- 4) Mark nullifier as used (only after success) --------------------
-        rec.used = true;
-        rec.bump = ctx.bumps.nullifier_record;
-    
-        // -------------------- 5) Emit event --------------------
+        // -------------------- 6) Emit event --------------------
         emit!(WithdrawCompleted {
             nullifier: *nf32,
             merkle_root_used: *root32,
             amount: amount_u64,
             mint: ctx.accounts.token_mint.key(),
             recipient: ctx.accounts.recipient_owner.key(),
+            fee: fee_u64,
+            relayer: if fee_u64 > 0 { relayer } else { Pubkey::default() },
+            change_commitment,
+            enc_change_note_hash,
+            next_leaf_index,
+            root_tree_id,
+            tree_id,
         });
     
         Ok(())
     }
-   
+
+    /// Pay out a time-locked withdrawal to the accounts bound by its proof once
+    /// `claimable_at` is reached. Anyone may crank it.
+    pub fn claim_pending_withdrawal(ctx: Context<ClaimPendingWithdrawal>, nullifier: [u8; 32]) -> Result<()> {
+        require!(
+            !ctx.accounts.config.is_paused(ProgramConfig::PAUSE_WITHDRAW),
+            CipherPayError::OperationPaused
+        );
+        let pending = &ctx.accounts.pending_withdrawal;
+        require!(
+            Clock::get()?.slot >= pending.claimable_at,
+            CipherPayError::WithdrawalStillLocked
+        );
+        vault_payout(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.vault_token_account.to_account_info(),
+            &ctx.accounts.token_mint,
+            &ctx.accounts.recipient_token_account.to_account_info(),
+            &ctx.accounts.vault_pda.to_account_info(),
+            ctx.bumps.vault_pda,
+            pending.amount,
+        )?;
+        if pending.fee > 0 {
+            let relayer_ata = ctx.accounts.relayer_token_account
+                .as_ref()
+                .ok_or(error!(CipherPayError::RelayerAccountMissing))?;
+            require_keys_eq!(relayer_ata.key(), pending.relayer_token_account, CipherPayError::RelayerMismatch);
+            vault_payout(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.vault_token_account.to_account_info(),
+                &ctx.accounts.token_mint,
+                &relayer_ata.to_account_info(),
+                &ctx.accounts.vault_pda.to_account_info(),
+                ctx.bumps.vault_pda,
+                pending.fee,
+            )?;
+        }
+        emit!(PendingWithdrawalClaimed {
+            nullifier,
+            mint: pending.mint,
+            recipient_token_account: pending.recipient_token_account,
+            amount: pending.amount,
+            fee: pending.fee,
+        });
+        Ok(())
+    }
+
+    /// Guardian cancels a time-locked withdrawal before it can be claimed. The tokens
+    /// never left the vault; `refund_commitment` (a note for amount + fee, built for
+    /// the rightful owner) is appended instead, and the outflow is released.
+    pub fn cancel_pending_withdrawal(
+        ctx: Context<CancelPendingWithdrawal>,
+        nullifier: [u8; 32],
+    ) -> Result<()> {
+        let pending = &ctx.accounts.pending_withdrawal;
+        let refund_commitment = pending.refund_commitment;
+        require!(
+            Clock::get()?.slot < pending.claimable_at,
+            CipherPayError::WithdrawalUnlocked
+        );
+        let amount = pending
+            .amount
+            .checked_add(pending.fee)
+            .ok_or(error!(CipherPayError::ArithmeticError))?;
+        ctx.accounts.pool_config.release_withdraw(pending.epoch, amount);
+
+        let (new_roots, next_leaf_index) = add_commitments(
+            &mut ctx.accounts.tree,
+            &mut ctx.accounts.root_cache,
+            ctx.accounts.commitment_queue.as_ref(),
+            &[refund_commitment],
+            ctx.accounts.config.root_min_age_slots,
+        )?;
+        emit!(PendingWithdrawalCancelled {
+            nullifier,
+            mint: ctx.accounts.pending_withdrawal.mint,
+            amount,
+            refund_commitment,
+            new_merkle_root: new_roots[0],
+            next_leaf_index,
+            tree_id: ctx.accounts.tree.tree_id,
+            guardian: ctx.accounts.guardian.key(),
+        });
+        Ok(())
+    }
+
+    /// Verify an audit proof: the prover holds notes under a cached root of this pool
+    /// that sum to the declared amount, disclosed to `auditor` for `audit_nonce`.
+    /// Emits `AuditAttested` and, if `attestation` is passed, stores the statement.
+    /// Builds without a verifier (neither `real-crypto` nor `mock-verifier`) reject it.
+    pub fn verify_audit_proof(
+        ctx: Context<VerifyAuditProof>,
+        audit_nonce: [u8; 32],
+        proof_bytes: Vec<u8>,
+        public_inputs_bytes: Vec<u8>,
+    ) -> Result<()> {
+        // An attestation without a verified proof is worthless: stub builds fail closed
+        #[cfg(not(any(feature = "real-crypto", feature = "mock-verifier")))]
+        {
+            let _ = (ctx, audit_nonce, proof_bytes, public_inputs_bytes);
+            err!(CipherPayError::ProofVerificationDisabled)
+        }
+
+        #[cfg(any(feature = "real-crypto", feature = "mock-verifier"))]
+        {
+            require_eq!(
+                public_inputs_bytes.len(),
+                solana_verifier::AUDIT_N_PUBLIC * 32,
+                CipherPayError::InvalidPublicInputsLength
+            );
+            let sigs = solana_verifier::parse_public_signals_exact(&public_inputs_bytes)
+                .map_err(|_| error!(CipherPayError::InvalidPublicInputsLength))?;
+            let merkle_root = sigs[audit_idx::MERKLE_ROOT];
+            let mint = ctx.accounts.token_mint.key();
+
+            // Cheap bindings first: root of this pool, pool mint, auditor, challenge
+            root_cache_tree_id(&ctx.accounts.root_cache, &mint)?;
+            require!(
+                is_valid_root(&merkle_root, &ctx.accounts.root_cache, ctx.accounts.config.root_max_age_slots),
+                CipherPayError::UnknownMerkleRoot
+            );
+            require!(sigs[audit_idx::TOKEN_ID] == token_id_for(&mint), CipherPayError::TokenIdMismatch);
+            require_keys_eq!(
+                pubkey_from_limbs(&sigs[audit_idx::AUDITOR_LO], &sigs[audit_idx::AUDITOR_HI]),
+                ctx.accounts.auditor.key(),
+                CipherPayError::AuditorMismatch
+            );
+            // Only the bound prover can submit, so nobody else can take the attestation PDA
+            require_keys_eq!(
+                pubkey_from_limbs(&sigs[audit_idx::PROVER_LO], &sigs[audit_idx::PROVER_HI]),
+                ctx.accounts.payer.key(),
+                CipherPayError::ProverMismatch
+            );
+            require!(sigs[audit_idx::AUDIT_NONCE] == audit_nonce, CipherPayError::InvalidZkProof);
+
+            verify_circuit_proof(
+                &ctx.accounts.config,
+                solana_verifier::circuit_id::AUDIT,
+                Some(&ctx.accounts.verifying_key),
+                &proof_bytes,
+                &public_inputs_bytes,
+            )?;
+
+            let total_amount = le_bytes_32_to_u64(&sigs[audit_idx::TOTAL_AMOUNT]);
+            let slot = Clock::get()?.slot;
+            if let Some(att) = ctx.accounts.attestation.as_mut() {
+                att.auditor         = ctx.accounts.auditor.key();
+                att.prover          = ctx.accounts.payer.key();
+                att.mint            = mint;
+                att.merkle_root     = merkle_root;
+                att.total_amount    = total_amount;
+                att.note_set_hash   = sigs[audit_idx::NOTE_SET_HASH];
+                att.disclosure_hash = sigs[audit_idx::DISCLOSURE_HASH];
+                att.audit_nonce     = audit_nonce;
+                att.slot            = slot;
+                att.bump            = ctx.bumps.attestation.unwrap_or_default();
+            }
+            emit!(AuditAttested {
+                auditor: ctx.accounts.auditor.key(),
+                prover: ctx.accounts.payer.key(),
+                mint,
+                merkle_root,
+                total_amount,
+                note_set_hash: sigs[audit_idx::NOTE_SET_HASH],
+                disclosure_hash: sigs[audit_idx::DISCLOSURE_HASH],
+                audit_nonce,
+                slot,
+            });
+            Ok(())
+        }
+    }
 }
@@ -1,18 +1,157 @@
 use anchor_lang::prelude::*;
-use crate::constants::MAX_ROOTS;
+
+/// Program-wide governance config: seeds = [CONFIG_SEED]
+///
+/// Admin rotation is two-step: the current admin proposes, the proposed key accepts.
+#[account]
+pub struct ProgramConfig {
+    /// Key allowed to run privileged instructions.
+    pub admin: Pubkey,
+    /// Proposed next admin (set by `propose_admin`, consumed by `accept_admin`).
+    pub pending_admin: Option<Pubkey>,
+    /// PDA bump
+    pub bump: u8,
+    /// Emergency circuit breaker: bitset of `PAUSE_*` flags.
+    pub pause_flags: u8,
+    /// Spends reject membership roots cached more than this many slots ago (0 = no expiry).
+    pub root_max_age_slots: u64,
+    /// Root caches refuse to evict a root cached fewer than this many slots ago (0 = off):
+    /// the insert fails until the cache is grown, so busy pools keep a minimum window.
+    pub root_min_age_slots: u64,
+    /// Where spends record nullifiers: one of the `NULLIFIER_SET_*` modes.
+    pub nullifier_set: u8,
+    /// Lowest verifying-key version each listed circuit still accepts (unlisted = 0).
+    pub vk_min_versions: [VkMinVersion; 8],
+    /// Future flags/fields
+    pub _reserved: [u8; 14],
+}
+
+/// One `ProgramConfig::vk_min_versions` slot; `min_version == 0` marks it free.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct VkMinVersion {
+    /// `zk_verifier::circuit_id::*`
+    pub circuit: u8,
+    /// Older registry versions (and the embedded VK, version 0) are rejected.
+    pub min_version: u16,
+}
+
+impl VkMinVersion {
+    pub const SIZE: usize = 1 + 2;
+}
+
+impl ProgramConfig {
+    pub const MAX_VK_MIN_VERSIONS: usize = 8;
+    pub const SIZE: usize =
+        32 + (1 + 32) + 1 + 1 + 8 + 8 + 1 + Self::MAX_VK_MIN_VERSIONS * VkMinVersion::SIZE + 14;
+    pub const SPACE: usize = 8 + Self::SIZE;
+
+    pub const PAUSE_DEPOSIT: u8  = 1 << 0;
+    pub const PAUSE_TRANSFER: u8 = 1 << 1;
+    pub const PAUSE_WITHDRAW: u8 = 1 << 2;
+
+    /// One `NullifierRecord` PDA per nullifier (legacy).
+    pub const NULLIFIER_SET_PDA: u8 = 0;
+    /// Nullifiers go into `NullifierShard`s; the legacy PDA of each nullifier is passed
+    /// too and must not hold a spent record. Legacy records can't be enumerated on-chain,
+    /// so the check stays: migrating a record into its shard only reclaims its rent.
+    pub const NULLIFIER_SET_SHARDED: u8 = 1;
+
+    #[inline]
+    pub fn is_paused(&self, flag: u8) -> bool {
+        self.pause_flags & flag != 0
+    }
+
+    /// Lowest verifying-key version accepted for `circuit` (0 = embedded VK allowed).
+    pub fn min_vk_version(&self, circuit: u8) -> u16 {
+        self.vk_min_versions
+            .iter()
+            .find(|s| s.min_version != 0 && s.circuit == circuit)
+            .map_or(0, |s| s.min_version)
+    }
+
+    /// Set the minimum version of `circuit` (0 clears it); returns the previous minimum.
+    pub fn set_min_vk_version(&mut self, circuit: u8, min_version: u16) -> Result<u16> {
+        let old = self.min_vk_version(circuit);
+        let slot = match self
+            .vk_min_versions
+            .iter()
+            .position(|s| s.min_version != 0 && s.circuit == circuit)
+        {
+            Some(i) => i,
+            None if min_version == 0 => return Ok(old),
+            None => self
+                .vk_min_versions
+                .iter()
+                .position(|s| s.min_version == 0)
+                .ok_or(error!(crate::error::CipherPayError::InvalidInput))?,
+        };
+        self.vk_min_versions[slot] = VkMinVersion { circuit, min_version };
+        Ok(old)
+    }
+}
+
+/// Admin-managed Groth16 verifying key for one circuit version:
+/// seeds = [VK_SEED, [circuit], version.to_le_bytes()]
+///
+/// Written in chunks, then finalized once its keccak256 matches `vk_hash`
+/// (pinned at init). Finalized keys are immutable, so old and new circuit
+/// versions can coexist while clients migrate.
+#[account]
+pub struct VerifyingKeyAccount {
+    /// `zk_verifier::circuit_id::*`
+    pub circuit: u8,
+    /// Circuit version (0 is reserved for the VK embedded in the program).
+    pub version: u16,
+    /// Set once the hash check passed; no more writes afterwards.
+    pub finalized: bool,
+    /// keccak256 of `data`, pinned at init.
+    pub vk_hash: [u8; 32],
+    /// PDA bump
+    pub bump: u8,
+    /// vk.bin bytes (BIG-ENDIAN limbs, same layout as the embedded files).
+    pub data: Vec<u8>,
+}
+
+impl VerifyingKeyAccount {
+    /// Fixed fields + Vec length prefix (excluding discriminator and VK bytes).
+    pub const HEADER_SIZE: usize = 1 + 2 + 1 + 32 + 1 + 4;
+
+    /// Full account space for a VK of `vk_len` bytes.
+    pub fn space(vk_len: usize) -> usize {
+        8 + Self::HEADER_SIZE + vk_len
+    }
+}
 
 /// Marker PDA keyed by `deposit_hash` that makes `shielded_deposit` idempotent.
+///
+/// Once the deposit's root has aged out of its root cache, `close_deposit_marker`
+/// refunds the rent to `payer` and moves the hash into the processed-deposits set.
 #[account]
 pub struct DepositMarker {
     /// Has this deposit_hash already been consumed (commitment inserted)?
     pub processed: bool,
     /// PDA bump
     pub bump: u8,
+    /// Paid the marker's rent; the only key that can close it.
+    pub payer: Pubkey,
+    /// Slot the deposit was processed in.
+    pub created_slot: u64,
+    /// Pool mint and tree the commitment was appended to.
+    pub mint: Pubkey,
+    pub tree_id: u32,
+    /// Tree root right after the deposit's commitment was appended
+    /// (zero while `root_pending`).
+    pub merkle_root: [u8; 32],
+    /// Leaf index of the deposit's commitment.
+    pub leaf_index: u32,
+    /// The commitment was queued (sequencer mode): `merkle_root` is set by the
+    /// batch insert that covers `leaf_index`, and the marker can't close before.
+    pub root_pending: bool,
 }
 
 impl DepositMarker {
     /// Raw field size (excluding the 8-byte Anchor discriminator)
-    pub const SIZE: usize = 1 + 1;
+    pub const SIZE: usize = 1 + 1 + 32 + 8 + 32 + 4 + 32 + 4 + 1;
     /// Full account space (including discriminator)
     pub const SPACE: usize = 8 + Self::SIZE;
 
@@ -21,6 +160,207 @@ impl DepositMarker {
     pub fn set_processed(&mut self) {
         self.processed = true;
     }
+
+    /// Record where the commitment went; `root` is as returned by `add_commitments`
+    /// (zero for a queued commitment, which leaves the root pending).
+    pub fn set_appended(&mut self, root: [u8; 32], leaf_index: u32) {
+        self.merkle_root  = root;
+        self.leaf_index   = leaf_index;
+        self.root_pending = root == [0u8; 32];
+    }
+}
+
+/// Two-phase deposit escrow: seeds = [PENDING_DEPOSIT_SEED, deposit_hash]
+///
+/// Phase one moves the tokens into the pool vault and records them here;
+/// phase two (`finalize_pending_deposit`) turns them into a note with a proof.
+/// Until then the depositor can take them back after `PENDING_DEPOSIT_TIMEOUT_SLOTS`.
+#[account]
+pub struct PendingDeposit {
+    /// Funded the escrow; receives the refund and the rent.
+    pub depositor: Pubkey,
+    /// Pool mint.
+    pub mint: Pubkey,
+    /// Amount that landed in the vault (net of any Token-2022 transfer fee).
+    pub amount: u64,
+    /// Slot phase one ran in.
+    pub created_slot: u64,
+    /// PDA bump
+    pub bump: u8,
+}
+
+impl PendingDeposit {
+    /// Raw field size (excluding the 8-byte Anchor discriminator)
+    pub const SIZE: usize = 32 + 32 + 8 + 8 + 1;
+    /// Full account space (including discriminator)
+    pub const SPACE: usize = 8 + Self::SIZE;
+
+    /// Slot from which `refund_pending_deposit` is allowed.
+    pub fn refundable_at(&self) -> u64 {
+        self.created_slot.saturating_add(crate::constants::PENDING_DEPOSIT_TIMEOUT_SLOTS)
+    }
+}
+
+/// Per-mint pool limits: seeds = [POOL_CONFIG_SEED, mint]
+///
+/// Set by the admin (`set_pool_limits`); a pool without one has no limits.
+/// Every limit uses 0 for "no limit".
+#[account]
+pub struct PoolConfig {
+    /// Pool mint.
+    pub mint: Pubkey,
+    /// Smallest deposit (note amount) accepted.
+    pub min_deposit: u64,
+    /// Largest deposit (note amount) accepted.
+    pub max_deposit: u64,
+    /// Largest single withdrawal (gross: the relayer fee is part of it).
+    pub max_withdraw: u64,
+    /// Most that may leave the vault through withdrawals in one epoch.
+    pub epoch_outflow_cap: u64,
+    /// Epoch `epoch_outflow` is counted for (the window rolls over with the epoch).
+    pub outflow_epoch: u64,
+    /// Withdrawn from the pool so far in `outflow_epoch`.
+    pub epoch_outflow: u64,
+    /// Withdrawals above this (gross) are time-locked in a `PendingWithdrawal`.
+    pub large_withdraw_threshold: u64,
+    /// Slots a time-locked withdrawal waits before it can be claimed.
+    pub withdraw_delay_slots: u64,
+    /// May cancel time-locked withdrawals during their delay.
+    pub guardian: Pubkey,
+    /// PDA bump
+    pub bump: u8,
+    /// Future flags/fields
+    pub _reserved: [u8; 16],
+}
+
+impl PoolConfig {
+    /// Raw field size (excluding the 8-byte Anchor discriminator)
+    pub const SIZE: usize = 32 + 8 * 8 + 32 + 1 + 16;
+    /// Full account space (including discriminator)
+    pub const SPACE: usize = 8 + Self::SIZE;
+
+    /// Reject deposits outside [min_deposit, max_deposit].
+    pub fn check_deposit(&self, amount: u64) -> Result<()> {
+        require!(amount >= self.min_deposit, crate::error::CipherPayError::DepositBelowMinimum);
+        require!(
+            self.max_deposit == 0 || amount <= self.max_deposit,
+            crate::error::CipherPayError::DepositAboveMaximum
+        );
+        Ok(())
+    }
+
+    /// Count a withdrawal of `amount` in `epoch` against the limits. Returns true for the
+    /// withdrawal after which the epoch cap can no longer take a maximum-size withdrawal
+    /// (any withdrawal when there is no `max_withdraw`), i.e. once per epoch.
+    pub fn record_withdraw(&mut self, epoch: u64, amount: u64) -> Result<bool> {
+        require!(
+            self.max_withdraw == 0 || amount <= self.max_withdraw,
+            crate::error::CipherPayError::InvalidWithdrawAmount
+        );
+        if epoch != self.outflow_epoch {
+            self.outflow_epoch = epoch;
+            self.epoch_outflow = 0;
+        }
+        let before = self.epoch_outflow;
+        self.epoch_outflow = before
+            .checked_add(amount)
+            .ok_or(error!(crate::error::CipherPayError::ArithmeticError))?;
+
+        let cap = self.epoch_outflow_cap;
+        if cap == 0 {
+            return Ok(false);
+        }
+        require!(
+            self.epoch_outflow <= cap,
+            crate::error::CipherPayError::EpochOutflowCapExceeded
+        );
+        let reserve = self.max_withdraw.max(1).min(cap);
+        Ok(cap - before >= reserve && cap - self.epoch_outflow < reserve)
+    }
+
+    /// Give back `amount` of the outflow counted in `epoch` (a cancelled withdrawal).
+    pub fn release_withdraw(&mut self, epoch: u64, amount: u64) {
+        if epoch == self.outflow_epoch {
+            self.epoch_outflow = self.epoch_outflow.saturating_sub(amount);
+        }
+    }
+
+    /// Is a withdrawal of `amount` (gross) paid out through a `PendingWithdrawal`?
+    pub fn is_timelocked(&self, amount: u64) -> bool {
+        self.large_withdraw_threshold != 0 && amount > self.large_withdraw_threshold
+    }
+}
+
+/// Time-locked large withdrawal: seeds = [PENDING_WITHDRAWAL_SEED, nullifier]
+///
+/// `shielded_withdraw` spends the note but leaves the tokens in the vault, owed to the
+/// accounts below. Anyone can pay them out from `claimable_at`; until then the pool
+/// guardian can cancel and put the value back into the tree as the proof's refund note.
+#[account]
+pub struct PendingWithdrawal {
+    /// Pool mint.
+    pub mint: Pubkey,
+    /// Token account bound by the proof (recipient's ATA).
+    pub recipient_token_account: Pubkey,
+    /// Paid to the recipient (note amount minus the relayer fee).
+    pub amount: u64,
+    /// Relayer token account (default when there is no fee).
+    pub relayer_token_account: Pubkey,
+    /// Paid to the relayer.
+    pub fee: u64,
+    /// Funded the account; receives the rent when it is closed.
+    pub payer: Pubkey,
+    /// Epoch the withdrawal was counted against the outflow cap in.
+    pub epoch: u64,
+    /// Slot from which `claim_pending_withdrawal` is allowed.
+    pub claimable_at: u64,
+    /// Refund note for `amount + fee`, bound by the withdraw proof; the only
+    /// commitment `cancel_pending_withdrawal` can append.
+    pub refund_commitment: [u8; 32],
+    /// PDA bump
+    pub bump: u8,
+}
+
+impl PendingWithdrawal {
+    /// Raw field size (excluding the 8-byte Anchor discriminator)
+    pub const SIZE: usize = 32 + 32 + 8 + 32 + 8 + 32 + 8 + 8 + 32 + 1;
+    /// Full account space (including discriminator)
+    pub const SPACE: usize = 8 + Self::SIZE;
+}
+
+/// Audit attestation: seeds = [AUDIT_ATTESTATION_SEED, auditor, audit_nonce]
+///
+/// Stored by `verify_audit_proof` when the prover asks for it, so the auditor can
+/// read the verified statement instead of indexing `AuditAttested` events.
+#[account]
+pub struct AuditAttestation {
+    /// Auditor the notes were disclosed to (bound by the proof).
+    pub auditor: Pubkey,
+    /// Submitted the proof and paid the rent (bound by the proof).
+    pub prover: Pubkey,
+    /// Pool mint.
+    pub mint: Pubkey,
+    /// Cached root the notes were proven against.
+    pub merkle_root: [u8; 32],
+    /// Sum of the disclosed notes.
+    pub total_amount: u64,
+    /// Poseidon chain of the disclosed notes' nullifiers.
+    pub note_set_hash: [u8; 32],
+    /// Hash of the note openings encrypted to the auditor.
+    pub disclosure_hash: [u8; 32],
+    /// Auditor's challenge.
+    pub audit_nonce: [u8; 32],
+    /// Slot the proof was verified in.
+    pub slot: u64,
+    /// PDA bump
+    pub bump: u8,
+}
+
+impl AuditAttestation {
+    /// Raw field size (excluding the 8-byte Anchor discriminator)
+    pub const SIZE: usize = 32 * 3 + 32 + 8 + 32 * 3 + 8 + 1;
+    /// Full account space (including discriminator)
+    pub const SPACE: usize = 8 + Self::SIZE;
 }
 
 /// Optional on-chain nullifier record (if you decide to persist spent notes).
@@ -34,97 +374,622 @@ impl NullifierRecord {
     pub const SPACE: usize = 8 + Self::SIZE;
 }
 
+/// Compact nullifier set shard: seeds = [NULLIFIER_SHARD_SEED, [prefix]]
+///
+/// Holds every spent nullifier whose first (LE) byte is `prefix`, sorted, so a
+/// double-spend check is a binary search and a spend costs 32 bytes of rent
+/// instead of a whole account. The header is followed by `[[u8;32]; capacity]`
+/// (see `NullifierSet`); shards grow by realloc as they fill.
+///
+/// The same layout backs the processed deposit-hash set
+/// ([PROCESSED_DEPOSITS_SEED, [prefix]]) that outlives closed deposit markers.
+#[account(zero_copy)]
+#[repr(C)]
+pub struct NullifierShard {
+    /// First byte of every nullifier stored here.
+    pub prefix: u32,
+    /// Number of stored nullifiers (<= capacity).
+    pub count: u32,
+    /// Nullifier slots allocated after the header.
+    pub capacity: u32,
+    /// PDA bump
+    pub bump: u32,
+}
+
+impl NullifierShard {
+    /// Header bytes (excluding the discriminator).
+    pub const HEADER_SIZE: usize = 4 + 4 + 4 + 4;
+
+    /// Bytes excluding the discriminator for `capacity` nullifiers.
+    pub const fn body_size(capacity: u32) -> usize {
+        Self::HEADER_SIZE + capacity as usize * 32
+    }
+
+    /// Full account size (incl. discriminator) for `capacity` nullifiers.
+    pub const fn space(capacity: u32) -> usize {
+        8 + Self::body_size(capacity)
+    }
+
+    /// Shard a nullifier belongs to.
+    #[inline]
+    pub fn prefix_of(nullifier: &[u8; 32]) -> u8 {
+        nullifier[0]
+    }
+}
+
+/// Sorted-array view over a nullifier shard's data (discriminator excluded).
+pub struct NullifierSet<D> {
+    data: D,
+}
+
+impl<D: core::ops::Deref<Target = [u8]>> NullifierSet<D> {
+    /// Wrap an initialized shard (data large enough for its capacity).
+    pub fn new(data: D) -> Result<Self> {
+        require!(
+            data.len() >= NullifierShard::HEADER_SIZE,
+            crate::error::CipherPayError::InvalidInput
+        );
+        let set = Self { data };
+        let h = set.header();
+        require!(
+            h.count <= h.capacity && set.data.len() >= NullifierShard::body_size(h.capacity),
+            crate::error::CipherPayError::InvalidInput
+        );
+        Ok(set)
+    }
+
+    pub fn header(&self) -> &NullifierShard {
+        bytemuck::from_bytes(&self.data[..NullifierShard::HEADER_SIZE])
+    }
+
+    fn at(&self, i: usize) -> &[u8] {
+        let off = NullifierShard::HEADER_SIZE + i * 32;
+        &self.data[off..off + 32]
+    }
+
+    /// Ok(index) if present, Err(insert position) otherwise. Nullifiers are
+    /// ordered by their raw bytes.
+    fn search(&self, nullifier: &[u8; 32]) -> core::result::Result<usize, usize> {
+        let (mut lo, mut hi) = (0usize, self.header().count as usize);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.at(mid).cmp(&nullifier[..]) {
+                core::cmp::Ordering::Equal => return Ok(mid),
+                core::cmp::Ordering::Less => lo = mid + 1,
+                core::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        Err(lo)
+    }
+
+    /// Has `nullifier` been spent (O(log n))?
+    pub fn contains(&self, nullifier: &[u8; 32]) -> bool {
+        self.search(nullifier).is_ok()
+    }
+
+    pub fn is_full(&self) -> bool {
+        let h = self.header();
+        h.count >= h.capacity
+    }
+}
+
+impl<D: core::ops::DerefMut<Target = [u8]>> NullifierSet<D> {
+    /// Format fresh account data as an empty shard of `capacity` nullifiers.
+    pub fn init(mut data: D, prefix: u8, bump: u8, capacity: u32) -> Result<Self> {
+        require!(
+            data.len() >= NullifierShard::body_size(capacity),
+            crate::error::CipherPayError::InvalidInput
+        );
+        data[..NullifierShard::body_size(capacity)].fill(0);
+        let mut set = Self { data };
+        let h = set.header_mut();
+        h.prefix   = prefix as u32;
+        h.bump     = bump as u32;
+        h.capacity = capacity;
+        Ok(set)
+    }
+
+    fn header_mut(&mut self) -> &mut NullifierShard {
+        bytemuck::from_bytes_mut(&mut self.data[..NullifierShard::HEADER_SIZE])
+    }
+
+    /// Record the data was grown to `new_capacity` slots (after realloc).
+    pub fn set_capacity(&mut self, new_capacity: u32) -> Result<()> {
+        require!(
+            new_capacity >= self.header().count && self.data.len() >= NullifierShard::body_size(new_capacity),
+            crate::error::CipherPayError::InvalidInput
+        );
+        self.header_mut().capacity = new_capacity;
+        Ok(())
+    }
+
+    /// Insert `nullifier` keeping the array sorted.
+    /// Fails if it is already present (double spend) or the shard is full.
+    pub fn insert(&mut self, nullifier: &[u8; 32]) -> Result<()> {
+        require!(
+            NullifierShard::prefix_of(nullifier) as u32 == self.header().prefix,
+            crate::error::CipherPayError::InvalidInput
+        );
+        require!(!self.is_full(), crate::error::CipherPayError::InvalidInput);
+        let pos = match self.search(nullifier) {
+            Ok(_) => return err!(crate::error::CipherPayError::NullifierAlreadyUsed),
+            Err(pos) => pos,
+        };
+        let count = self.header().count as usize;
+        let start = NullifierShard::HEADER_SIZE + pos * 32;
+        let end = NullifierShard::HEADER_SIZE + count * 32;
+        self.data.copy_within(start..end, start + 32);
+        self.data[start..start + 32].copy_from_slice(nullifier);
+        self.header_mut().count += 1;
+        Ok(())
+    }
+}
+
+/// Per-mint Merkle tree state: seeds = [TREE_SEED, mint, tree_id_le]
+///
+/// Holds the incremental-tree frontier so leaves are appended on-chain
+/// (Poseidon syscall) instead of trusting prover-supplied new roots.
 #[account]
 pub struct TreeState {
     pub version:     u16,        // v1
     pub current_root:[u8; 32],
     pub next_index:  u32,
     pub depth:       u8,
-    pub _reserved:   [u8; 31],   // future flags/fields (optional)
+    pub mint:        Pubkey,     // SPL mint this pool belongs to
+    pub tree_id:     u32,        // 0 = genesis tree; +1 on each rollover
+    /// 1 = sequencer mode: new commitments go to the tree's `CommitmentQueue` and
+    /// are inserted in batches by `insert_queued_commitments`.
+    pub sequencer_mode: u8,
+    /// 1 = a batch insert moved the root without updating `filled_subtrees`;
+    /// on-chain appends are refused until `sync_tree_frontier`.
+    pub frontier_stale: u8,
+    pub _reserved:   [u8; 25],   // future flags/fields (optional)
+    /// Rightmost filled node per level (LE field elements), len = depth.
+    pub filled_subtrees: Vec<[u8; 32]>,
+    /// Empty-subtree root per level (zeros[0] = 0 leaf), len = depth.
+    pub zeros:       Vec<[u8; 32]>,
 }
-// Anchor 0.29+: implement `Space` with `INIT_SPACE`
+// Anchor 0.29+: implement `Space` with `INIT_SPACE` (fixed header only)
 impl anchor_lang::Space for TreeState {
-    const INIT_SPACE: usize = 2 + 32 + 4 + 1 + 31;
+    const INIT_SPACE: usize = 2 + 32 + 4 + 1 + 32 + 31;
+}
+impl TreeState {
+    /// Leaf capacity (2^depth).
+    pub fn capacity(&self) -> u64 {
+        1u64 << self.depth
+    }
+
+    /// No room for another leaf; appends must go to the next tree.
+    pub fn is_full(&self) -> bool {
+        self.next_index as u64 >= self.capacity()
+    }
+
+    /// Full account size (incl. discriminator) for a tree of `depth` levels.
+    pub const fn space(depth: u8) -> usize {
+        8 + <Self as anchor_lang::Space>::INIT_SPACE + 2 * (4 + 32 * depth as usize)
+    }
 }
 
-/// Fixed-capacity ring buffer for recent Merkle roots.
+/// Pending-commitment queue of a tree in sequencer mode:
+/// seeds = [COMMITMENT_QUEUE_SEED, mint, tree_id_le]
 ///
-/// • Zero-copy: no (de)serialization of a large Vec on every ix.
-/// • Backed by a PDA and accessed via `AccountLoader<MerkleRootCache>`.
+/// Commitments wait here, in leaf order (the first one lands at the tree's
+/// `next_index`), until a batcher inserts them with one batch-insert proof.
+/// The header is followed by `[[u8;32]; capacity]` (see `PendingCommitments`).
+#[account(zero_copy)]
+#[repr(C)]
+pub struct CommitmentQueue {
+    /// Pool mint.
+    pub mint: Pubkey,
+    /// Tree the queued commitments will be inserted into.
+    pub tree_id: u32,
+    /// Queued commitments (<= capacity).
+    pub count: u32,
+    /// Commitment slots allocated after the header.
+    pub capacity: u32,
+    /// PDA bump
+    pub bump: u32,
+}
+
+impl CommitmentQueue {
+    /// Header bytes (excluding the discriminator).
+    pub const HEADER_SIZE: usize = 32 + 4 + 4 + 4 + 4;
+
+    /// Bytes excluding the discriminator for `capacity` commitments.
+    pub const fn body_size(capacity: u32) -> usize {
+        Self::HEADER_SIZE + capacity as usize * 32
+    }
+
+    /// Full account size (incl. discriminator) for `capacity` commitments.
+    pub const fn space(capacity: u32) -> usize {
+        8 + Self::body_size(capacity)
+    }
+}
+
+/// FIFO view over a commitment queue's data (discriminator excluded).
+pub struct PendingCommitments<D> {
+    data: D,
+}
+
+impl<D: core::ops::Deref<Target = [u8]>> PendingCommitments<D> {
+    /// Wrap an initialized queue (data large enough for its capacity).
+    pub fn new(data: D) -> Result<Self> {
+        require!(
+            data.len() >= CommitmentQueue::HEADER_SIZE,
+            crate::error::CipherPayError::InvalidInput
+        );
+        let q = Self { data };
+        let h = q.header();
+        require!(
+            h.count <= h.capacity && q.data.len() >= CommitmentQueue::body_size(h.capacity),
+            crate::error::CipherPayError::InvalidInput
+        );
+        Ok(q)
+    }
+
+    pub fn header(&self) -> &CommitmentQueue {
+        bytemuck::from_bytes(&self.data[..CommitmentQueue::HEADER_SIZE])
+    }
+
+    pub fn len(&self) -> usize {
+        self.header().count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `n` oldest commitments, in insertion order.
+    pub fn front(&self, n: usize) -> Result<&[u8]> {
+        require!(n <= self.len(), crate::error::CipherPayError::CommitmentQueueUnderflow);
+        let start = CommitmentQueue::HEADER_SIZE;
+        Ok(&self.data[start..start + n * 32])
+    }
+}
+
+impl<D: core::ops::DerefMut<Target = [u8]>> PendingCommitments<D> {
+    /// Format fresh account data as an empty queue of `capacity` commitments.
+    pub fn init(mut data: D, mint: Pubkey, tree_id: u32, bump: u8, capacity: u32) -> Result<Self> {
+        require!(
+            capacity > 0 && data.len() >= CommitmentQueue::body_size(capacity),
+            crate::error::CipherPayError::InvalidInput
+        );
+        data[..CommitmentQueue::body_size(capacity)].fill(0);
+        let mut q = Self { data };
+        let h = q.header_mut();
+        h.mint     = mint;
+        h.tree_id  = tree_id;
+        h.bump     = bump as u32;
+        h.capacity = capacity;
+        Ok(q)
+    }
+
+    fn header_mut(&mut self) -> &mut CommitmentQueue {
+        bytemuck::from_bytes_mut(&mut self.data[..CommitmentQueue::HEADER_SIZE])
+    }
+
+    /// Append `commitments` at the back (all or nothing).
+    pub fn push(&mut self, commitments: &[[u8; 32]]) -> Result<()> {
+        let count = self.len();
+        require!(
+            count + commitments.len() <= self.header().capacity as usize,
+            crate::error::CipherPayError::CommitmentQueueFull
+        );
+        for (i, c) in commitments.iter().enumerate() {
+            let off = CommitmentQueue::HEADER_SIZE + (count + i) * 32;
+            self.data[off..off + 32].copy_from_slice(c);
+        }
+        self.header_mut().count += commitments.len() as u32;
+        Ok(())
+    }
+
+    /// Drop the `n` oldest commitments (after they were inserted into the tree).
+    pub fn pop_front(&mut self, n: usize) -> Result<()> {
+        let count = self.len();
+        require!(n <= count, crate::error::CipherPayError::CommitmentQueueUnderflow);
+        let start = CommitmentQueue::HEADER_SIZE;
+        let end = start + count * 32;
+        self.data.copy_within(start + n * 32..end, start);
+        self.data[end - n * 32..end].fill(0);
+        self.header_mut().count = (count - n) as u32;
+        Ok(())
+    }
+}
+
+/// Ring buffer of recent Merkle roots of one tree, with a hash index.
+///
+/// • Zero-copy header; the account data continues with the ring
+///   `[[u8;32]; capacity]`, the slot each root was inserted at `[u64 LE; capacity]`
+///   and the index `[u32 LE; 2 * capacity]` (see `RootRing`).
+/// • Capacity is chosen at init and can be grown later (realloc).
+/// • The index maps a root to its ring slot (linear probing, bucket = slot + 1,
+///   0 = empty), so lookups stay O(1) whatever the capacity.
 ///
 /// Layout on-chain:
-///   [8-byte discriminator] + [[u8;32]; MAX_ROOTS] + u16(next_slot) + u16(count)
+///   [8-byte discriminator] + u32(capacity) + u32(next_slot) + u32(count)
+///   + u32(tree_id) + Pubkey(mint) + roots + slots + index
+/// seeds = [ROOT_CACHE_SEED, mint, tree_id_le]
 #[account(zero_copy)]
 #[repr(C)]
 pub struct MerkleRootCache {
-    /// Ring buffer of recent roots.
-    pub roots: [[u8; 32]; MAX_ROOTS],
-    /// Next write position in the ring (0..MAX_ROOTS-1).
-    pub next_slot: u16,
-    /// Number of valid entries (<= MAX_ROOTS).
-    pub count: u16,
+    /// Ring size (number of roots kept).
+    pub capacity: u32,
+    /// Next write position in the ring (0..capacity-1).
+    pub next_slot: u32,
+    /// Number of valid entries (<= capacity).
+    pub count: u32,
+    /// Tree these roots belong to (matches `TreeState::tree_id`).
+    pub tree_id: u32,
+    /// Pool mint (lets instructions accept a cache of any older tree).
+    pub mint: Pubkey,
 }
 
 impl MerkleRootCache {
-    /// Bytes excluding the discriminator.
-    pub const BYTE_SIZE: usize = (MAX_ROOTS * 32) + 2 + 2;
-    /// Bytes including the discriminator (what you pass as `space` minus the 8 you add in `#[account(init, space = 8 + ...)]`).
-    pub const SIZE: usize = Self::BYTE_SIZE;
-    /// Convenience: full account size including discriminator.
-    pub const SPACE: usize = 8 + Self::BYTE_SIZE;
+    /// Header bytes (excluding the discriminator).
+    pub const HEADER_SIZE: usize = 4 + 4 + 4 + 4 + 32;
+    /// Ring entry + insertion slot + two index buckets per root.
+    pub const BYTES_PER_ROOT: usize = 32 + 8 + 2 * 4;
 
-    #[inline]
-    pub fn clear(&mut self) {
-        // All zeros is a valid empty state, but we explicitly reset counters.
-        self.next_slot = 0;
-        self.count = 0;
-        // Zero the roots array.
-        // (Compiler is smart enough; this does NOT copy on stack.)
-        self.roots = [[0u8; 32]; MAX_ROOTS];
+    /// Bytes excluding the discriminator for a ring of `capacity` roots.
+    pub const fn body_size(capacity: u32) -> usize {
+        Self::HEADER_SIZE + capacity as usize * Self::BYTES_PER_ROOT
     }
 
-    /// Insert a new root (ring-buffer). Overwrites oldest when full.
-    #[inline]
-    pub fn insert(&mut self, new_root: [u8; 32]) {
-        let idx = (self.next_slot as usize) % MAX_ROOTS;
-        self.roots[idx] = new_root;
-        self.next_slot = ((self.next_slot as usize + 1) % MAX_ROOTS) as u16;
-        if (self.count as usize) < MAX_ROOTS {
-            self.count += 1;
+    /// Full account size (incl. discriminator) for a ring of `capacity` roots.
+    pub const fn space(capacity: u32) -> usize {
+        8 + Self::body_size(capacity)
+    }
+}
+
+/// Ring + hash-index view over a root cache's data (discriminator excluded).
+/// `RootRing<&[u8]>` reads, `RootRing<&mut [u8]>` also writes.
+pub struct RootRing<D> {
+    data: D,
+}
+
+impl<D: core::ops::Deref<Target = [u8]>> RootRing<D> {
+    /// Wrap an initialized cache (capacity > 0, data large enough for it).
+    pub fn new(data: D) -> Result<Self> {
+        require!(
+            data.len() >= MerkleRootCache::HEADER_SIZE,
+            crate::error::CipherPayError::InvalidRootCapacity
+        );
+        let ring = Self { data };
+        let capacity = ring.header().capacity;
+        require!(
+            capacity > 0 && ring.data.len() >= MerkleRootCache::body_size(capacity),
+            crate::error::CipherPayError::InvalidRootCapacity
+        );
+        Ok(ring)
+    }
+
+    pub fn header(&self) -> &MerkleRootCache {
+        bytemuck::from_bytes(&self.data[..MerkleRootCache::HEADER_SIZE])
+    }
+
+    fn n_buckets(&self) -> usize {
+        2 * self.header().capacity as usize
+    }
+
+    fn root_at(&self, slot: usize) -> [u8; 32] {
+        let off = MerkleRootCache::HEADER_SIZE + slot * 32;
+        let mut r = [0u8; 32];
+        r.copy_from_slice(&self.data[off..off + 32]);
+        r
+    }
+
+    fn slot_offset(&self, slot: usize) -> usize {
+        MerkleRootCache::HEADER_SIZE + self.header().capacity as usize * 32 + slot * 8
+    }
+
+    fn inserted_slot_at(&self, slot: usize) -> u64 {
+        let off = self.slot_offset(slot);
+        let mut le = [0u8; 8];
+        le.copy_from_slice(&self.data[off..off + 8]);
+        u64::from_le_bytes(le)
+    }
+
+    fn bucket_offset(&self, b: usize) -> usize {
+        MerkleRootCache::HEADER_SIZE + self.header().capacity as usize * (32 + 8) + b * 4
+    }
+
+    fn bucket(&self, b: usize) -> u32 {
+        let off = self.bucket_offset(b);
+        u32::from_le_bytes([self.data[off], self.data[off + 1], self.data[off + 2], self.data[off + 3]])
+    }
+
+    /// Home bucket: roots are field elements from Poseidon, so their low bytes are uniform.
+    fn home(&self, root: &[u8; 32]) -> usize {
+        u32::from_le_bytes([root[0], root[1], root[2], root[3]]) as usize % self.n_buckets()
+    }
+
+    /// (ring slot holding `root`, buckets visited to find out).
+    fn probe(&self, root: &[u8; 32]) -> (Option<usize>, usize) {
+        if self.header().count == 0 {
+            return (None, 0);
         }
+        let n = self.n_buckets();
+        let mut b = self.home(root);
+        let mut visited = 0;
+        // Load factor <= 1/2, so an empty bucket always ends the run.
+        for _ in 0..n {
+            visited += 1;
+            let e = self.bucket(b);
+            if e == 0 {
+                break;
+            }
+            let slot = (e - 1) as usize;
+            if &self.root_at(slot) == root {
+                return (Some(slot), visited);
+            }
+            b = (b + 1) % n;
+        }
+        (None, visited)
     }
 
-    /// Check whether a root exists in the cache (O(MAX_ROOTS)).
-    #[inline]
+    /// Check whether a root exists in the cache (O(1) expected).
     pub fn contains(&self, root: &[u8; 32]) -> bool {
-        let total = self.count as usize;
-        if total == 0 {
-            return false;
-        }
-        // If not yet full, the logical order is 0..count-1.
-        // If full, the oldest is at next_slot.
-        let start = if total < MAX_ROOTS {
-            0usize
-        } else {
-            self.next_slot as usize
-        };
-        for i in 0..total {
-            let idx = (start + i) % MAX_ROOTS;
-            if &self.roots[idx] == root {
-                return true;
-            }
+        self.probe(root).0.is_some()
+    }
+
+    /// Solana slot at which `root` was cached, if it still is.
+    pub fn inserted_at(&self, root: &[u8; 32]) -> Option<u64> {
+        self.probe(root).0.map(|slot| self.inserted_slot_at(slot))
+    }
+
+    /// Cached and, when `max_age_slots > 0`, inserted at most `max_age_slots` before `now_slot`.
+    pub fn is_fresh(&self, root: &[u8; 32], now_slot: u64, max_age_slots: u64) -> bool {
+        match self.inserted_at(root) {
+            Some(at) => max_age_slots == 0 || now_slot.saturating_sub(at) <= max_age_slots,
+            None => false,
         }
-        false
+    }
+
+    /// Whether a root can be inserted at `now_slot` without evicting one cached
+    /// fewer than `min_age_slots` ago (always, while the ring is not full).
+    pub fn can_insert(&self, now_slot: u64, min_age_slots: u64) -> bool {
+        let h = self.header();
+        h.count < h.capacity
+            || now_slot.saturating_sub(self.inserted_slot_at(h.next_slot as usize)) >= min_age_slots
+    }
+
+    /// Index buckets a lookup of `root` probes (one 32-byte compare each).
+    pub fn lookup_cost(&self, root: &[u8; 32]) -> usize {
+        self.probe(root).1
     }
 
     /// Latest (most recently inserted) root, if any.
-    #[inline]
     pub fn latest(&self) -> Option<[u8; 32]> {
-        if self.count == 0 {
+        let h = self.header();
+        if h.count == 0 {
             None
         } else {
-            let idx = (self.next_slot as usize + MAX_ROOTS - 1) % MAX_ROOTS;
-            Some(self.roots[idx])
+            let cap = h.capacity as usize;
+            Some(self.root_at((h.next_slot as usize + cap - 1) % cap))
+        }
+    }
+}
+
+impl<D: core::ops::DerefMut<Target = [u8]>> RootRing<D> {
+    /// Format fresh account data as an empty ring of `capacity` roots.
+    pub fn init(mut data: D, capacity: u32, tree_id: u32, mint: Pubkey) -> Result<Self> {
+        require!(
+            capacity > 0 && data.len() >= MerkleRootCache::body_size(capacity),
+            crate::error::CipherPayError::InvalidRootCapacity
+        );
+        data[..MerkleRootCache::body_size(capacity)].fill(0);
+        let mut ring = Self { data };
+        let h = ring.header_mut();
+        h.capacity = capacity;
+        h.tree_id  = tree_id;
+        h.mint     = mint;
+        Ok(ring)
+    }
+
+    fn header_mut(&mut self) -> &mut MerkleRootCache {
+        bytemuck::from_bytes_mut(&mut self.data[..MerkleRootCache::HEADER_SIZE])
+    }
+
+    fn set_root(&mut self, slot: usize, root: &[u8; 32]) {
+        let off = MerkleRootCache::HEADER_SIZE + slot * 32;
+        self.data[off..off + 32].copy_from_slice(root);
+    }
+
+    fn set_inserted_slot(&mut self, slot: usize, at: u64) {
+        let off = self.slot_offset(slot);
+        self.data[off..off + 8].copy_from_slice(&at.to_le_bytes());
+    }
+
+    fn set_bucket(&mut self, b: usize, e: u32) {
+        let off = self.bucket_offset(b);
+        self.data[off..off + 4].copy_from_slice(&e.to_le_bytes());
+    }
+
+    fn index_slot(&mut self, slot: usize) {
+        let n = self.n_buckets();
+        let mut b = self.home(&self.root_at(slot));
+        while self.bucket(b) != 0 {
+            b = (b + 1) % n;
+        }
+        self.set_bucket(b, slot as u32 + 1);
+    }
+
+    /// Drop `slot` from the index (backward-shift deletion keeps probe runs intact).
+    fn unindex_slot(&mut self, slot: usize) {
+        let n = self.n_buckets();
+        let mut hole = self.home(&self.root_at(slot));
+        while self.bucket(hole) != slot as u32 + 1 {
+            hole = (hole + 1) % n;
+        }
+        let mut j = hole;
+        loop {
+            j = (j + 1) % n;
+            let e = self.bucket(j);
+            if e == 0 {
+                break;
+            }
+            let k = self.home(&self.root_at((e - 1) as usize));
+            // Entry at j may fill the hole unless its home lies cyclically in (hole, j].
+            let stays = if hole <= j { hole < k && k <= j } else { hole < k || k <= j };
+            if !stays {
+                self.set_bucket(hole, e);
+                hole = j;
+            }
+        }
+        self.set_bucket(hole, 0);
+    }
+
+    /// Insert a new root cached at Solana slot `at` (ring-buffer). Overwrites oldest when full.
+    pub fn insert(&mut self, new_root: [u8; 32], at: u64) {
+        let (cap, slot, count) = {
+            let h = self.header();
+            (h.capacity, h.next_slot as usize, h.count)
+        };
+        if count == cap {
+            self.unindex_slot(slot);
+        }
+        self.set_root(slot, &new_root);
+        self.set_inserted_slot(slot, at);
+        self.index_slot(slot);
+        let h = self.header_mut();
+        h.next_slot = ((slot + 1) % cap as usize) as u32;
+        if h.count < cap {
+            h.count += 1;
+        }
+    }
+
+    /// Re-lay the ring for `new_capacity` roots once the data has been grown to
+    /// `body_size(new_capacity)`: oldest root to slot 0, index rebuilt.
+    /// Returns the previous capacity.
+    pub fn grow(&mut self, new_capacity: u32) -> Result<u32> {
+        let (cap, next, count) = {
+            let h = self.header();
+            (h.capacity, h.next_slot as usize, h.count)
+        };
+        require!(
+            new_capacity > cap && self.data.len() >= MerkleRootCache::body_size(new_capacity),
+            crate::error::CipherPayError::InvalidRootCapacity
+        );
+        let roots = MerkleRootCache::HEADER_SIZE;
+        let slots = roots + cap as usize * 32;
+        if count == cap {
+            self.data[roots..slots].rotate_left(next * 32);
+            self.data[slots..slots + cap as usize * 8].rotate_left(next * 8);
+        }
+        // Slots move to their new offset (after the larger ring), then everything
+        // past them (old index bytes) is cleared.
+        let new_slots = roots + new_capacity as usize * 32;
+        self.data.copy_within(slots..slots + count as usize * 8, new_slots);
+        self.data[roots + count as usize * 32..new_slots].fill(0);
+        let end = MerkleRootCache::body_size(new_capacity);
+        self.data[new_slots + count as usize * 8..end].fill(0);
+
+        let h = self.header_mut();
+        h.capacity  = new_capacity;
+        h.next_slot = count;
+        for slot in 0..count as usize {
+            self.index_slot(slot);
         }
+        Ok(cap)
     }
 }
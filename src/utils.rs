@@ -4,16 +4,34 @@
 
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{
+    hash::hashv,
     instruction::Instruction,
     sysvar::instructions as sysvar_instructions,
 };
+use core::cell::{Ref, RefMut};
 use core::str::FromStr;
 
+use crate::constants::{
+    DEPOSIT_MARKER_SEED, MAX_ROOT_CAPACITY, NULLIFIER_SEED, NULLIFIER_SHARD_GROWTH, NULLIFIER_SHARD_SEED,
+    PROCESSED_DEPOSITS_SEED, RECIPIENT_WALLET_DOMAIN, TOKEN_ID_DOMAIN, VAULT_SEED,
+};
 use crate::error::CipherPayError;
-use crate::state::MerkleRootCache;
+use crate::event::{CommitmentsQueued, EpochOutflowCapReached};
+use crate::state::{
+    CommitmentQueue, DepositMarker, MerkleRootCache, NullifierRecord, NullifierSet, NullifierShard,
+    PendingCommitments, PoolConfig, ProgramConfig, RootRing, TreeState, VerifyingKeyAccount,
+};
+use crate::zk_verifier::solana_verifier;
+
+use anchor_spl::token_interface::{self, Mint, TransferChecked};
 
-/// SPL Token program (from anchor_spl)
+/// SPL Token programs (from anchor_spl): classic Token and Token-2022
 use anchor_spl::token::ID as TOKEN_PROGRAM_ID;
+use anchor_spl::token_2022::ID as TOKEN_2022_PROGRAM_ID;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+    state::Mint as SplMint,
+};
 
 /// ───────────────────────── logging gate ─────────────────────────
 /// Enable lightweight tracing with: `--features verbose-logs`
@@ -81,34 +99,85 @@ pub fn assert_memo_in_same_tx(
     Err(error!(CipherPayError::InvalidInput))
 }
 
+/// Is `program_id` one of the SPL token programs we accept (Token or Token-2022)?
+#[inline]
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == TOKEN_PROGRAM_ID || *program_id == TOKEN_2022_PROGRAM_ID
+}
+
+/// Token-2022 transfer-fee extension: fee withheld when `gross` is transferred
+/// in the current epoch. Classic Token mints and mints without the extension → 0.
+pub fn transfer_fee_for(mint_ai: &AccountInfo, gross: u64) -> Result<u64> {
+    if *mint_ai.owner != TOKEN_2022_PROGRAM_ID {
+        return Ok(0);
+    }
+    let data = mint_ai.try_borrow_data()?;
+    let mint = StateWithExtensions::<SplMint>::unpack(&data)
+        .map_err(|_| error!(CipherPayError::InvalidInput))?;
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(cfg) => {
+            let epoch = Clock::get()?.epoch;
+            cfg.calculate_epoch_fee(epoch, gross)
+                .ok_or_else(|| error!(CipherPayError::ArithmeticError))
+        }
+        Err(_) => Ok(0),
+    }
+}
+
+/// Decoded SPL-Token transfer: (tag, gross amount, decimals, explicit fee).
+struct SplTransferIx {
+    tag: u8,
+    amount: u64,
+    decimals: Option<u8>,
+    /// Only set by Token-2022 TransferCheckedWithFee.
+    fee: Option<u64>,
+}
+
 /// Minimal decoder for SPL-Token amounts:
-/// tag=3  -> Transfer { amount: u64 }
-/// tag=12 -> TransferChecked { amount: u64, decimals: u8 }
-fn parse_spl_token_amount(data: &[u8]) -> Option<(u8, u64, Option<u8>)> {
+/// tag=3      -> Transfer { amount: u64 }
+/// tag=12     -> TransferChecked { amount: u64, decimals: u8 }
+/// tag=26/1   -> (Token-2022) TransferCheckedWithFee { amount: u64, decimals: u8, fee: u64 }
+fn parse_spl_token_amount(data: &[u8]) -> Option<SplTransferIx> {
     if data.is_empty() { return None; }
+    let read_u64 = |off: usize| -> Option<u64> {
+        let mut le = [0u8; 8];
+        le.copy_from_slice(data.get(off..off+8)?);
+        Some(u64::from_le_bytes(le))
+    };
     match data[0] {
-        3 => {
-            if data.len() < 1 + 8 { return None; }
-            let mut le = [0u8; 8];
-            le.copy_from_slice(&data[1..1+8]);
-            Some((3, u64::from_le_bytes(le), None))
-        }
-        12 => {
-            if data.len() < 1 + 8 + 1 { return None; }
-            let mut le = [0u8; 8];
-            le.copy_from_slice(&data[1..1+8]);
-            Some((12, u64::from_le_bytes(le), Some(data[1+8])))
-        }
+        3 => Some(SplTransferIx { tag: 3, amount: read_u64(1)?, decimals: None, fee: None }),
+        12 => Some(SplTransferIx {
+            tag: 12,
+            amount: read_u64(1)?,
+            decimals: Some(*data.get(1+8)?),
+            fee: None,
+        }),
+        26 if data.get(1) == Some(&1) => Some(SplTransferIx {
+            tag: 26,
+            amount: read_u64(2)?,
+            decimals: Some(*data.get(2+8)?),
+            fee: Some(read_u64(2+8+1)?),
+        }),
         _ => None,
     }
 }
 
-/// Search 0..=current_index for a Transfer/TransferChecked to `expected_dst`.
+/// Search 0..=current_index for a Transfer/TransferChecked(WithFee) of `mint_ai` to
+/// `expected_dst` issued by either the classic Token program or Token-2022.
+///
+/// `expected_amount` is what must *land* in `expected_dst`, i.e. the gross amount minus
+/// the Token-2022 transfer fee of `mint_ai` (if it carries the TransferFeeConfig extension).
 /// If `expected_amount == 0`, treat amount as a wildcard (useful in non-crypto builds).
+///
+/// The matched instruction index is pushed to `used`, and indices already in `used`
+/// are skipped: callers checking several deposits share one `used` so every deposit
+/// is funded by its own transfer.
 pub fn assert_transfer_checked_in_same_tx(
     instr_ai: &AccountInfo,
     expected_dst: &Pubkey,
     expected_amount: u64,
+    mint_ai: &AccountInfo,
+    used: &mut Vec<usize>,
 ) -> Result<()> {
     let cur = current_index(instr_ai)?;
     trace!(
@@ -117,36 +186,44 @@ pub fn assert_transfer_checked_in_same_tx(
     );
 
     for i in 0..=cur {
+        if used.contains(&i) {
+            trace!("spl@{i}: already matched");
+            continue;
+        }
         let ix = load_ix_at(i, instr_ai)?;
-        if ix.program_id != TOKEN_PROGRAM_ID {
+        if !is_token_program(&ix.program_id) {
             continue;
         }
 
-        if let Some((tag, amount, decimals)) = parse_spl_token_amount(&ix.data) {
-            match tag {
-                3 => {
-                    // Transfer: [source, destination, authority, ...]
-                    let dst = ix.accounts.get(1).map(|m| m.pubkey);
-                    let ok = if let Some(dst_pk) = dst {
-                        let amount_ok = expected_amount == 0 || amount == expected_amount;
-                        dst_pk == *expected_dst && amount_ok
-                    } else { false };
-                    trace!("spl@{i}: Transfer amount={amount} dst={:?} ok={}", dst, ok);
-                    if ok { return Ok(()); }
-                }
-                12 => {
-                    // TransferChecked: [source, mint, destination, authority, ...]
-                    let dst = ix.accounts.get(2).map(|m| m.pubkey);
-                    let ok = if let Some(dst_pk) = dst {
-                        let amount_ok = expected_amount == 0 || amount == expected_amount;
-                        dst_pk == *expected_dst && amount_ok
-                    } else { false };
-                    trace!("spl@{i}: TransferChecked amount={amount} dec={:?} dst={:?} ok={}", decimals, dst, ok);
-                    if ok { return Ok(()); }
-                }
-                _ => {
-                    trace!("spl@{i}: token tag {} (ignored)", tag);
-                }
+        if let Some(t) = parse_spl_token_amount(&ix.data) {
+            // Transfer:                 [source, destination, authority, ...]
+            // TransferChecked(WithFee): [source, mint, destination, authority, ...]
+            let dst_pos = if t.tag == 3 { 1 } else { 2 };
+            let dst = ix.accounts.get(dst_pos).map(|m| m.pubkey);
+            if dst != Some(*expected_dst) {
+                trace!("spl@{i}: tag={} amount={} dst={:?} (other dst)", t.tag, t.amount, dst);
+                continue;
+            }
+            // Checked variants name their mint; it must be the pool mint. A plain
+            // Transfer is bound by `expected_dst` (the vault ATA of `mint_ai`).
+            if t.tag != 3 && ix.accounts.get(1).map(|m| m.pubkey) != Some(*mint_ai.key) {
+                trace!("spl@{i}: tag={} amount={} (other mint)", t.tag, t.amount);
+                continue;
+            }
+
+            let fee = match t.fee {
+                Some(f) => f,
+                None => transfer_fee_for(mint_ai, t.amount)?,
+            };
+            let net = t.amount.checked_sub(fee).ok_or(error!(CipherPayError::ArithmeticError))?;
+            let ok = expected_amount == 0 || net == expected_amount;
+            trace!(
+                "spl@{i}: tag={} amount={} fee={} net={} dec={:?} ok={}",
+                t.tag, t.amount, fee, net, t.decimals, ok
+            );
+            if ok {
+                used.push(i);
+                return Ok(());
             }
         } else {
             trace!("spl@{i}: unknown token ix (tag={}, len={})",
@@ -157,47 +234,722 @@ pub fn assert_transfer_checked_in_same_tx(
     Err(error!(CipherPayError::RequiredSplTransferMissing))
 }
 
-// ─── Merkle helpers ───
+// ─── Token ids / withdraw destination ───
 
-/// Insert a single root if absent.
-/// Signature kept compatible with existing call sites: (new_root, &mut cache).
-pub fn insert_merkle_root(new_root: &[u8; 32], cache: &mut AccountLoader<MerkleRootCache>) {
-    match cache.load_mut() {
-        Ok(mut c) => {
-            if !c.contains(new_root) {
-                c.insert(*new_root);
-            }
+/// sha256(domain || key) read little-endian with the top 3 bits cleared: a canonical
+/// field element (always below the BN254 scalar modulus, r > 2^253), 32 bytes LE.
+pub fn pubkey_to_field(domain: &[u8], key: &Pubkey) -> [u8; 32] {
+    let mut fe = hashv(&[domain, key.as_ref()]).to_bytes();
+    fe[31] &= 0x1f;
+    fe
+}
+
+/// Canonical `tokenId` of a mint, as bound by deposit and withdraw proofs.
+pub fn token_id_for(mint: &Pubkey) -> [u8; 32] {
+    pubkey_to_field(TOKEN_ID_DOMAIN, mint)
+}
+
+/// `recipientWalletPubKey` of a withdraw: the token account the payout lands in (the
+/// recipient owner's ATA for the pool mint), not the owner wallet itself.
+pub fn recipient_wallet_field(recipient_token_account: &Pubkey) -> [u8; 32] {
+    pubkey_to_field(RECIPIENT_WALLET_DOMAIN, recipient_token_account)
+}
+
+/// Withdraw proofs must name the pool mint (`tokenId`) and the exact token account
+/// that is paid (`recipientWalletPubKey`).
+pub fn assert_withdraw_destination(
+    token_id: &[u8; 32],
+    recipient_wallet: &[u8; 32],
+    mint: &Pubkey,
+    recipient_token_account: &Pubkey,
+) -> Result<()> {
+    require!(*token_id == token_id_for(mint), CipherPayError::TokenIdMismatch);
+    require!(
+        *recipient_wallet == recipient_wallet_field(recipient_token_account),
+        CipherPayError::RecipientWalletMismatch
+    );
+    Ok(())
+}
+
+// ─── Vault payouts ───
+
+/// Pay `amount` out of the per-mint vault ATA, signed by the vault PDA
+/// (seeds = [VAULT_SEED, mint, bump]). No-op for `amount == 0`.
+/// TransferChecked works for both Token and Token-2022 (required by the latter).
+pub fn vault_payout<'info>(
+    token_program: &AccountInfo<'info>,
+    vault_token_account: &AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &AccountInfo<'info>,
+    vault_pda: &AccountInfo<'info>,
+    vault_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let bump = [vault_bump];
+    let mint_key = mint.key();
+    let signer_seeds: &[&[u8]] = &[VAULT_SEED, mint_key.as_ref(), &bump];
+    let signer: &[&[&[u8]]] = &[signer_seeds];
+
+    let cpi_accounts = TransferChecked {
+        from:      vault_token_account.clone(),
+        mint:      mint.to_account_info(),
+        to:        to.clone(),
+        authority: vault_pda.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer);
+
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)
+        .map_err(|_| error!(CipherPayError::TokenTransferFailed))
+}
+
+// ─── Program-owned PDA creation ───
+
+/// Create a program-owned PDA of `space` bytes at `target`, paid by `payer`.
+/// Works even if someone pre-funded the address (transfer + allocate + assign).
+pub fn create_pda_account<'info>(
+    program_id: &Pubkey,
+    target: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    use anchor_lang::system_program::{self as sp, Allocate, Assign, CreateAccount, Transfer};
+
+    let rent = Rent::get()?.minimum_balance(space);
+    let signer: &[&[&[u8]]] = &[signer_seeds];
+
+    if target.lamports() == 0 {
+        return sp::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                CreateAccount { from: payer.clone(), to: target.clone() },
+                signer,
+            ),
+            rent,
+            space as u64,
+            program_id,
+        );
+    }
+
+    let top_up = rent.saturating_sub(target.lamports());
+    if top_up > 0 {
+        sp::transfer(
+            CpiContext::new(system_program.clone(), Transfer { from: payer.clone(), to: target.clone() }),
+            top_up,
+        )?;
+    }
+    sp::allocate(
+        CpiContext::new_with_signer(system_program.clone(), Allocate { account_to_allocate: target.clone() }, signer),
+        space as u64,
+    )?;
+    sp::assign(
+        CpiContext::new_with_signer(system_program.clone(), Assign { account_to_assign: target.clone() }, signer),
+        program_id,
+    )
+}
+
+/// Grow a program-owned account to `new_space` bytes, topping up rent from `payer`.
+pub fn grow_pda_account<'info>(
+    target: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_space: usize,
+) -> Result<()> {
+    use anchor_lang::system_program::{self as sp, Transfer};
+
+    let rent = Rent::get()?.minimum_balance(new_space);
+    let top_up = rent.saturating_sub(target.lamports());
+    if top_up > 0 {
+        sp::transfer(
+            CpiContext::new(system_program.clone(), Transfer { from: payer.clone(), to: target.clone() }),
+            top_up,
+        )?;
+    }
+    target.realloc(new_space, false)?;
+    Ok(())
+}
+
+// ─── Nullifiers ───
+
+/// Mark `nullifier` spent through its `NullifierRecord` PDA ([NULLIFIER_SEED, nullifier]),
+/// creating the PDA if needed. Used where the records come in via `remaining_accounts`
+/// (variable input count), mirroring the `init_if_needed` record of `shielded_transfer`.
+pub fn spend_nullifier_pda<'info>(
+    program_id: &Pubkey,
+    record_ai: &AccountInfo<'info>,
+    nullifier: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let (pda, bump) = Pubkey::find_program_address(&[NULLIFIER_SEED, nullifier], program_id);
+    require_keys_eq!(record_ai.key(), pda, CipherPayError::InvalidInput);
+    require!(record_ai.is_writable, CipherPayError::InvalidInput);
+
+    if record_ai.owner == program_id && !record_ai.data_is_empty() {
+        let mut rec = {
+            let data = record_ai.try_borrow_data()?;
+            NullifierRecord::try_deserialize(&mut &data[..])?
+        };
+        require!(!rec.used, CipherPayError::AlreadyProcessed);
+        rec.used = true;
+        let mut data = record_ai.try_borrow_mut_data()?;
+        return rec.try_serialize(&mut &mut data[..]);
+    }
+
+    let bump_arr = [bump];
+    create_pda_account(
+        program_id,
+        record_ai,
+        payer,
+        system_program,
+        NullifierRecord::SPACE,
+        &[NULLIFIER_SEED, nullifier, &bump_arr],
+    )?;
+    let rec = NullifierRecord { used: true, bump };
+    let mut data = record_ai.try_borrow_mut_data()?;
+    rec.try_serialize(&mut &mut data[..])
+}
+
+/// Mark `nullifier` spent in its `NullifierShard` PDA ([NULLIFIER_SHARD_SEED, [prefix]]),
+/// creating the shard or growing it by `NULLIFIER_SHARD_GROWTH` slots (paid by `payer`).
+pub fn spend_nullifier_shard<'info>(
+    program_id: &Pubkey,
+    shard_ai: &AccountInfo<'info>,
+    nullifier: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let prefix = NullifierShard::prefix_of(nullifier);
+    let (pda, bump) = Pubkey::find_program_address(&[NULLIFIER_SHARD_SEED, &[prefix]], program_id);
+    require_keys_eq!(shard_ai.key(), pda, CipherPayError::NullifierSetMismatch);
+    insert_into_shard(program_id, NULLIFIER_SHARD_SEED, bump, shard_ai, nullifier, payer, system_program)
+}
+
+/// Insert `key` into the sorted shard at `shard_ai` (already checked to be the
+/// [seed, [prefix]] PDA with `bump`), creating or growing it as needed.
+/// Fails with `NullifierAlreadyUsed` if `key` is already present.
+fn insert_into_shard<'info>(
+    program_id: &Pubkey,
+    seed: &[u8],
+    bump: u8,
+    shard_ai: &AccountInfo<'info>,
+    key: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let prefix = NullifierShard::prefix_of(key);
+    require!(shard_ai.is_writable, CipherPayError::InvalidInput);
+
+    if shard_ai.owner != program_id || shard_ai.data_is_empty() {
+        create_pda_account(
+            program_id,
+            shard_ai,
+            payer,
+            system_program,
+            NullifierShard::space(NULLIFIER_SHARD_GROWTH),
+            &[seed, &[prefix], &[bump]],
+        )?;
+        let mut data = shard_ai.try_borrow_mut_data()?;
+        data[..8].copy_from_slice(NullifierShard::DISCRIMINATOR);
+        NullifierSet::init(&mut data[8..], prefix, bump, NULLIFIER_SHARD_GROWTH)?;
+    }
+
+    let grow_to = {
+        let data = shard_ai.try_borrow_data()?;
+        require!(
+            data.len() >= 8 && &data[..8] == NullifierShard::DISCRIMINATOR,
+            CipherPayError::NullifierSetMismatch
+        );
+        let set = NullifierSet::new(&data[8..])?;
+        require!(!set.contains(key), CipherPayError::NullifierAlreadyUsed);
+        if set.is_full() {
+            Some(set.header().capacity.checked_add(NULLIFIER_SHARD_GROWTH)
+                .ok_or(error!(CipherPayError::ArithmeticError))?)
+        } else {
+            None
+        }
+    };
+    if let Some(capacity) = grow_to {
+        grow_pda_account(shard_ai, payer, system_program, NullifierShard::space(capacity))?;
+        let mut data = shard_ai.try_borrow_mut_data()?;
+        NullifierSet::new(&mut data[8..])?.set_capacity(capacity)?;
+    }
+
+    let mut data = shard_ai.try_borrow_mut_data()?;
+    NullifierSet::new(&mut data[8..])?.insert(key)
+}
+
+/// `record_ai` must be the legacy `NullifierRecord` PDA of `nullifier` and must not
+/// hold a spent record (it is never created here).
+pub fn assert_legacy_nullifier_unspent(
+    program_id: &Pubkey,
+    record_ai: &AccountInfo,
+    nullifier: &[u8; 32],
+) -> Result<()> {
+    let (pda, _) = Pubkey::find_program_address(&[NULLIFIER_SEED, nullifier], program_id);
+    require_keys_eq!(record_ai.key(), pda, CipherPayError::NullifierSetMismatch);
+    if record_ai.owner == program_id && !record_ai.data_is_empty() {
+        let data = record_ai.try_borrow_data()?;
+        let rec = NullifierRecord::try_deserialize(&mut &data[..])?;
+        require!(!rec.used, CipherPayError::NullifierAlreadyUsed);
+    }
+    Ok(())
+}
+
+/// Spend `nullifier` in the compact set (`nullifier_set` is SHARDED).
+/// Its legacy PDA is checked as well, so a nullifier spent before the switch (and not
+/// migrated) cannot be spent again through a shard.
+pub fn spend_nullifier_sharded<'info>(
+    program_id: &Pubkey,
+    nullifier_set: u8,
+    shard_ai: &AccountInfo<'info>,
+    legacy_record_ai: Option<&AccountInfo<'info>>,
+    nullifier: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    require!(
+        nullifier_set != ProgramConfig::NULLIFIER_SET_PDA,
+        CipherPayError::NullifierSetMismatch
+    );
+    let legacy = legacy_record_ai.ok_or(error!(CipherPayError::NullifierSetMismatch))?;
+    assert_legacy_nullifier_unspent(program_id, legacy, nullifier)?;
+    spend_nullifier_shard(program_id, shard_ai, nullifier, payer, system_program)
+}
+
+/// Split the join-split `remaining_accounts` per input, in `nullifiers` order: its
+/// `NullifierRecord` PDA (`nullifier_set` == PDA), or its shard plus its legacy record
+/// address (SHARDED: the N shards first, then the N legacy records).
+pub fn joinsplit_nullifier_accounts<'a, 'info>(
+    nullifier_set: u8,
+    remaining: &'a [AccountInfo<'info>],
+    n: usize,
+) -> Result<Vec<(&'a AccountInfo<'info>, Option<&'a AccountInfo<'info>>)>> {
+    let per_input = if nullifier_set == ProgramConfig::NULLIFIER_SET_PDA { 1 } else { 2 };
+    require_eq!(remaining.len(), per_input * n, CipherPayError::NullifierSetMismatch);
+    Ok((0..n)
+        .map(|i| (&remaining[i], (per_input == 2).then(|| &remaining[n + i])))
+        .collect())
+}
+
+// ─── Pool limits ───
+
+/// Limits of a pool, read from its (seeds-checked) `PoolConfig` PDA.
+/// An account that was never created means the pool has no limits.
+pub fn load_pool_config(program_id: &Pubkey, pool_config_ai: &AccountInfo) -> Result<Option<PoolConfig>> {
+    if pool_config_ai.owner != program_id || pool_config_ai.data_is_empty() {
+        return Ok(None);
+    }
+    let data = pool_config_ai.try_borrow_data()?;
+    Ok(Some(PoolConfig::try_deserialize(&mut &data[..])?))
+}
+
+/// Reject a deposit of `amount` (the note amount) outside the pool's limits.
+pub fn assert_deposit_within_limits(
+    program_id: &Pubkey,
+    pool_config_ai: &AccountInfo,
+    amount: u64,
+) -> Result<()> {
+    match load_pool_config(program_id, pool_config_ai)? {
+        Some(limits) => limits.check_deposit(amount),
+        None => Ok(()),
+    }
+}
+
+/// Count a withdrawal of `amount` against the pool's `max_withdraw` and epoch outflow
+/// cap; emits `EpochOutflowCapReached` when it uses up the cap. Returns the updated
+/// limits (None if the pool has none).
+pub fn record_pool_withdraw(
+    program_id: &Pubkey,
+    pool_config_ai: &AccountInfo,
+    amount: u64,
+) -> Result<Option<PoolConfig>> {
+    let Some(mut limits) = load_pool_config(program_id, pool_config_ai)? else {
+        return Ok(None);
+    };
+    let epoch = Clock::get()?.epoch;
+    if limits.record_withdraw(epoch, amount)? {
+        emit!(EpochOutflowCapReached {
+            mint: limits.mint,
+            epoch,
+            epoch_outflow: limits.epoch_outflow,
+            epoch_outflow_cap: limits.epoch_outflow_cap,
+        });
+    }
+    require!(pool_config_ai.is_writable, CipherPayError::InvalidInput);
+    let mut data = pool_config_ai.try_borrow_mut_data()?;
+    limits.try_serialize(&mut &mut data[..])?;
+    Ok(Some(limits))
+}
+
+// ─── Processed deposit hashes ───
+
+fn processed_deposits_pda(program_id: &Pubkey, deposit_hash: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROCESSED_DEPOSITS_SEED, &[NullifierShard::prefix_of(deposit_hash)]],
+        program_id,
+    )
+}
+
+/// Fail with `DepositAlreadyUsed` if `deposit_hash` was recorded in its processed-deposits
+/// shard when its marker was closed. A shard that does not exist yet holds nothing.
+pub fn assert_deposit_hash_unused(
+    program_id: &Pubkey,
+    shard_ai: &AccountInfo,
+    deposit_hash: &[u8; 32],
+) -> Result<()> {
+    let (pda, _) = processed_deposits_pda(program_id, deposit_hash);
+    require_keys_eq!(shard_ai.key(), pda, CipherPayError::InvalidInput);
+    if shard_ai.owner != program_id || shard_ai.data_is_empty() {
+        return Ok(());
+    }
+    let data = shard_ai.try_borrow_data()?;
+    require!(
+        data.len() >= 8 && &data[..8] == NullifierShard::DISCRIMINATOR,
+        CipherPayError::InvalidInput
+    );
+    let set = NullifierSet::new(&data[8..])?;
+    require!(!set.contains(deposit_hash), CipherPayError::DepositAlreadyUsed);
+    Ok(())
+}
+
+/// Create the `DepositMarker` PDA of `marker.deposit_hash` ([DEPOSIT_MARKER_SEED, hash]),
+/// paid by `payer`. Used by batched deposits, whose markers come in `remaining_accounts`.
+pub fn create_deposit_marker<'info>(
+    program_id: &Pubkey,
+    marker_ai: &AccountInfo<'info>,
+    deposit_hash: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    mut marker: DepositMarker,
+) -> Result<()> {
+    let (pda, bump) = Pubkey::find_program_address(&[DEPOSIT_MARKER_SEED, deposit_hash], program_id);
+    require_keys_eq!(marker_ai.key(), pda, CipherPayError::InvalidInput);
+    require!(marker_ai.is_writable, CipherPayError::InvalidInput);
+    require!(
+        marker_ai.owner != program_id || marker_ai.data_is_empty(),
+        CipherPayError::DepositAlreadyUsed
+    );
+
+    create_pda_account(
+        program_id,
+        marker_ai,
+        payer,
+        system_program,
+        DepositMarker::SPACE,
+        &[DEPOSIT_MARKER_SEED, deposit_hash, &[bump]],
+    )?;
+    marker.bump = bump;
+    let mut data = marker_ai.try_borrow_mut_data()?;
+    marker.try_serialize(&mut &mut data[..])
+}
+
+/// Record `deposit_hash` in its processed-deposits shard before its marker is closed,
+/// so the hash stays unusable (32 bytes of rent instead of a whole marker account).
+pub fn record_processed_deposit<'info>(
+    program_id: &Pubkey,
+    shard_ai: &AccountInfo<'info>,
+    deposit_hash: &[u8; 32],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    assert_deposit_hash_unused(program_id, shard_ai, deposit_hash)?;
+    let (_, bump) = processed_deposits_pda(program_id, deposit_hash);
+    insert_into_shard(program_id, PROCESSED_DEPOSITS_SEED, bump, shard_ai, deposit_hash, payer, system_program)
+}
+
+// ─── Verifying-key registry ───
+
+/// Groth16-verify a proof for `circuit`.
+/// With a registry account, its (finalized) VK is used — this is how callers select a
+/// circuit version; without one, the VK embedded at build time is used. Either way the
+/// version must be at least `config.min_vk_version(circuit)` (the embedded VK is 0).
+///
+/// `mock-verifier` builds (without `real-crypto`) run the same VK selection and
+/// proof / public-input shape checks but skip the pairing check.
+pub fn verify_circuit_proof(
+    config: &ProgramConfig,
+    circuit: u8,
+    registry: Option<&VerifyingKeyAccount>,
+    proof_le: &[u8],
+    public_le: &[u8],
+) -> Result<()> {
+    let min_version = config.min_vk_version(circuit);
+    let vk_be: &[u8] = match registry {
+        Some(vk) => {
+            require!(vk.circuit == circuit, CipherPayError::VerifyingKeyCircuitMismatch);
+            require!(vk.finalized, CipherPayError::VerifyingKeyNotFinalized);
+            require!(vk.version >= min_version, CipherPayError::VerifyingKeyRetired);
+            trace!("vk: circuit={} version={}", vk.circuit, vk.version);
+            &vk.data
         }
-        Err(_e) => {
-            // Avoid panicking inside program; just log and continue.
-            msg!("⚠️ insert_merkle_root: failed to load root_cache");
+        None if min_version > 0 => return err!(CipherPayError::VerifyingKeyRetired),
+        #[cfg(all(feature = "mock-verifier", not(feature = "real-crypto")))]
+        None => &[],
+        #[cfg(any(feature = "real-crypto", not(feature = "mock-verifier")))]
+        None => solana_verifier::embedded_vk(circuit)
+            .ok_or(error!(CipherPayError::InvalidVerifyingKey))?,
+    };
+
+    #[cfg(all(feature = "mock-verifier", not(feature = "real-crypto")))]
+    {
+        let _ = vk_be;
+        require_eq!(proof_le.len(), solana_verifier::BYTES_PROOF, CipherPayError::InvalidProofBytesLength);
+        let n_public = solana_verifier::n_public_for(circuit)
+            .ok_or(error!(CipherPayError::VerifyingKeyCircuitMismatch))?;
+        require_eq!(public_le.len(), n_public * 32, CipherPayError::PublicInputCountMismatch);
+        msg!("⚠️ mock-verifier: pairing check skipped (circuit {})", circuit);
+        Ok(())
+    }
+
+    #[cfg(any(feature = "real-crypto", not(feature = "mock-verifier")))]
+    solana_verifier::verify_with_vk(vk_be, proof_le, public_le)
+        .map_err(|_| error!(CipherPayError::InvalidZkProof))
+}
+
+// ─── Merkle helpers ───
+
+/// Poseidon(left, right) over BN254 (circomlib parameters), LE field elements —
+/// the same hash the circuits use for tree nodes.
+pub fn poseidon_pair(left: &[u8; 32], right: &[u8; 32]) -> Result<[u8; 32]> {
+    solana_poseidon::hashv(
+        solana_poseidon::Parameters::Bn254X5,
+        solana_poseidon::Endianness::LittleEndian,
+        &[left, right],
+    )
+    .map(|h| h.to_bytes())
+    .map_err(|_| error!(CipherPayError::MerkleHashFailed))
+}
+
+/// Fill `zeros` / `filled_subtrees` for an empty tree of `tree.depth` levels
+/// and return the empty root (zero leaf = 0).
+pub fn init_tree_frontier(tree: &mut TreeState) -> Result<[u8; 32]> {
+    let depth = tree.depth as usize;
+    tree.zeros = Vec::with_capacity(depth);
+    let mut node = [0u8; 32];
+    for _ in 0..depth {
+        tree.zeros.push(node);
+        node = poseidon_pair(&node, &node)?;
+    }
+    tree.filled_subtrees = tree.zeros.clone();
+    Ok(node)
+}
+
+/// Append `leaf` at `tree.next_index` using the stored frontier.
+/// Updates `current_root` / `next_index` and returns the new root.
+pub fn append_leaf(tree: &mut TreeState, leaf: &[u8; 32]) -> Result<[u8; 32]> {
+    let depth = tree.depth as usize;
+    require!(
+        tree.filled_subtrees.len() == depth && tree.zeros.len() == depth,
+        CipherPayError::InvalidTreeDepth
+    );
+    require!(tree.sequencer_mode == 0, CipherPayError::TreeSequenced);
+    require!(tree.frontier_stale == 0, CipherPayError::FrontierStale);
+    require!(!tree.is_full(), CipherPayError::TreeFull);
+    let mut idx = tree.next_index;
+    let mut node = *leaf;
+    for level in 0..depth {
+        if idx & 1 == 0 {
+            tree.filled_subtrees[level] = node;
+            node = poseidon_pair(&node, &tree.zeros[level])?;
+        } else {
+            node = poseidon_pair(&tree.filled_subtrees[level], &node)?;
         }
+        idx >>= 1;
     }
+    tree.current_root = node;
+    tree.next_index = tree.next_index
+        .checked_add(1)
+        .ok_or(error!(CipherPayError::ArithmeticError))?;
+    trace!("append_leaf: next_index={}", tree.next_index);
+    Ok(node)
 }
 
-/// Insert many roots (dedup each).
-/// Signature kept compatible with existing call sites: (new_roots, &mut cache).
-pub fn insert_many_roots(new_roots: &[[u8; 32]], cache: &mut AccountLoader<MerkleRootCache>) {
-    match cache.load_mut() {
-        Ok(mut c) => {
-            for r in new_roots {
-                if !c.contains(r) {
-                    c.insert(*r);
-                }
-            }
+/// Root of a tree with `tree.next_index` leaves whose frontier is `filled`.
+/// Only the levels where `next_index` has a 1 bit matter (left siblings of the next
+/// leaf); the others are overwritten before they are read.
+pub fn frontier_root(tree: &TreeState, filled: &[[u8; 32]]) -> Result<[u8; 32]> {
+    let depth = tree.depth as usize;
+    require!(
+        filled.len() == depth && tree.zeros.len() == depth,
+        CipherPayError::InvalidTreeDepth
+    );
+    let mut idx = tree.next_index;
+    let mut node = [0u8; 32];
+    for (left, zero) in filled.iter().zip(tree.zeros.iter()) {
+        node = if idx & 1 == 1 {
+            poseidon_pair(left, &node)?
+        } else {
+            poseidon_pair(&node, zero)?
+        };
+        idx >>= 1;
+    }
+    Ok(node)
+}
+
+/// Install `filled` as the frontier of a tree whose root was moved by batch inserts,
+/// after checking it reproduces `current_root`.
+pub fn sync_frontier(tree: &mut TreeState, filled: Vec<[u8; 32]>) -> Result<()> {
+    require!(tree.frontier_stale != 0, CipherPayError::InvalidInput);
+    require!(!tree.is_full(), CipherPayError::TreeFull);
+    require!(
+        frontier_root(tree, &filled)? == tree.current_root,
+        CipherPayError::FrontierMismatch
+    );
+    tree.filled_subtrees = filled;
+    tree.frontier_stale = 0;
+    Ok(())
+}
+
+/// Write view over a tree's commitment queue (must belong to `tree`).
+pub fn commitment_queue_mut<'a>(
+    queue: &'a AccountLoader<CommitmentQueue>,
+    tree: &TreeState,
+) -> Result<PendingCommitments<RefMut<'a, [u8]>>> {
+    {
+        let h = queue.load()?;
+        require!(
+            h.mint == tree.mint && h.tree_id == tree.tree_id,
+            CipherPayError::TreeSequenced
+        );
+    }
+    let ai = queue.as_ref();
+    require!(ai.is_writable, anchor_lang::error::ErrorCode::AccountNotMutable);
+    let data = ai.try_borrow_mut_data()?;
+    PendingCommitments::new(RefMut::map(data, |d| &mut d[8..]))
+}
+
+/// Add new note commitments to `tree`: appended against the frontier with every new
+/// root cached or, in sequencer mode, pushed to the tree's `queue` for the next batch
+/// insert (the root does not move until then).
+/// Returns the root after each commitment (zero for queued ones: no root has them yet)
+/// and the leaf index following the last one. `root_min_age_slots` as in `insert_many_roots`.
+pub fn add_commitments(
+    tree: &mut TreeState,
+    root_cache: &mut AccountLoader<MerkleRootCache>,
+    queue: Option<&AccountLoader<CommitmentQueue>>,
+    commitments: &[[u8; 32]],
+    root_min_age_slots: u64,
+) -> Result<(Vec<[u8; 32]>, u32)> {
+    if tree.sequencer_mode == 0 {
+        let mut new_roots = Vec::with_capacity(commitments.len());
+        for c in commitments {
+            new_roots.push(append_leaf(tree, c)?);
         }
-        Err(_e) => {
-            msg!("⚠️ insert_many_roots: failed to load root_cache");
+        insert_many_roots(&new_roots, root_cache, root_min_age_slots)?;
+        return Ok((new_roots, tree.next_index));
+    }
+
+    let queue = queue.ok_or(error!(CipherPayError::TreeSequenced))?;
+    let mut q = commitment_queue_mut(queue, tree)?;
+    let first_leaf_index = tree.next_index as u64 + q.len() as u64;
+    let next = first_leaf_index + commitments.len() as u64;
+    require!(next <= tree.capacity(), CipherPayError::TreeFull);
+    q.push(commitments)?;
+    emit!(CommitmentsQueued {
+        mint: tree.mint,
+        tree_id: tree.tree_id,
+        commitments: commitments.to_vec(),
+        first_leaf_index: first_leaf_index as u32,
+    });
+    Ok((vec![[0u8; 32]; commitments.len()], next as u32))
+}
+
+/// Read view over a root cache (header + ring + hash index).
+/// The loader already checked owner and discriminator.
+pub fn root_ring<'a>(cache: &'a AccountLoader<MerkleRootCache>) -> Result<RootRing<Ref<'a, [u8]>>> {
+    let data = cache.as_ref().try_borrow_data()?;
+    RootRing::new(Ref::map(data, |d| &d[8..]))
+}
+
+/// Write view over a root cache (header + ring + hash index).
+pub fn root_ring_mut<'a>(cache: &'a AccountLoader<MerkleRootCache>) -> Result<RootRing<RefMut<'a, [u8]>>> {
+    let ai = cache.as_ref();
+    require!(ai.is_writable, anchor_lang::error::ErrorCode::AccountNotMutable);
+    let data = ai.try_borrow_mut_data()?;
+    RootRing::new(RefMut::map(data, |d| &mut d[8..]))
+}
+
+/// Format a freshly created root cache for `capacity` roots and seed it with `genesis_root`.
+pub fn init_root_cache(
+    cache: &AccountLoader<MerkleRootCache>,
+    capacity: u32,
+    tree_id: u32,
+    mint: &Pubkey,
+    genesis_root: &[u8; 32],
+) -> Result<()> {
+    require!(
+        capacity > 0 && capacity <= MAX_ROOT_CAPACITY,
+        CipherPayError::InvalidRootCapacity
+    );
+    // Discriminator still zero → fresh account (Anchor writes it on exit).
+    drop(cache.load_init()?);
+    let mut data = cache.as_ref().try_borrow_mut_data()?;
+    let mut ring = RootRing::init(&mut data[8..], capacity, tree_id, *mint)?;
+    ring.insert(*genesis_root, Clock::get()?.slot);
+    Ok(())
+}
+
+/// Re-lay a root cache whose account was just reallocated for `new_capacity` roots.
+/// Returns the previous capacity.
+pub fn grow_root_cache(cache: &AccountLoader<MerkleRootCache>, new_capacity: u32) -> Result<u32> {
+    require!(new_capacity <= MAX_ROOT_CAPACITY, CipherPayError::InvalidRootCapacity);
+    root_ring_mut(cache)?.grow(new_capacity)
+}
+
+/// Insert a single root if absent. Fails (reverting the instruction) if the cache
+/// cannot be loaded, so a root is never silently left out, or if that would evict
+/// a root cached fewer than `min_age_slots` ago.
+pub fn insert_merkle_root(
+    new_root: &[u8; 32],
+    cache: &mut AccountLoader<MerkleRootCache>,
+    min_age_slots: u64,
+) -> Result<()> {
+    insert_many_roots(core::slice::from_ref(new_root), cache, min_age_slots)
+}
+
+/// Insert many roots (dedup each); fails like `insert_merkle_root`.
+pub fn insert_many_roots(
+    new_roots: &[[u8; 32]],
+    cache: &mut AccountLoader<MerkleRootCache>,
+    min_age_slots: u64,
+) -> Result<()> {
+    let mut c = root_ring_mut(cache)?;
+    let slot = Clock::get()?.slot;
+    for r in new_roots {
+        if !c.contains(r) {
+            require!(c.can_insert(slot, min_age_slots), CipherPayError::RootCacheBusy);
+            c.insert(*r, slot);
         }
     }
+    Ok(())
+}
+
+/// Tree id of `cache`, which must belong to `mint`'s pool (current or rolled-over tree).
+/// Lets spends pass the root cache of whichever tree their membership root is from.
+pub fn root_cache_tree_id(cache: &AccountLoader<MerkleRootCache>, mint: &Pubkey) -> Result<u32> {
+    let c = cache.load()?;
+    require_keys_eq!(c.mint, *mint, CipherPayError::RootCacheMismatch);
+    Ok(c.tree_id)
 }
 
-/// Pure read: check if a root exists.
+/// Pure read: check if a root is still in the ring and, when `max_age_slots > 0`,
+/// was cached at most `max_age_slots` ago (so quiet pools stop accepting stale roots).
 /// Returns `false` if cache cannot be loaded (shouldn’t happen after init).
-pub fn is_valid_root(root: &[u8; 32], cache: &AccountLoader<MerkleRootCache>) -> bool {
-    match cache.load() {
-        Ok(c) => c.contains(root),
-        Err(_e) => {
+pub fn is_valid_root(root: &[u8; 32], cache: &AccountLoader<MerkleRootCache>, max_age_slots: u64) -> bool {
+    match (root_ring(cache), Clock::get()) {
+        (Ok(c), Ok(clock)) => {
+            let fresh = c.is_fresh(root, clock.slot, max_age_slots);
+            if !fresh && c.contains(root) {
+                msg!("⚠️ is_valid_root: root expired (max age {} slots)", max_age_slots);
+            }
+            fresh
+        }
+        _ => {
             msg!("⚠️ is_valid_root: failed to load root_cache");
             false
         }
@@ -15,6 +15,13 @@ pub use solana_verifier::{
     verify_deposit_payload,
     verify_transfer_payload,
     verify_withdraw_payload,
+    verify_with_vk,
+    validate_vk_bytes,
+    embedded_vk,
+    n_public_for,
+    circuit_id,
+    joinsplit_shape,
+    joinsplit_idx,
 };
 
 // Re-export constants
@@ -27,6 +34,12 @@ pub use solana_verifier::{
     DEPOSIT_N_PUBLIC,
     TRANSFER_N_PUBLIC,
     WITHDRAW_N_PUBLIC,
+    WITHDRAW_CHANGE_N_PUBLIC,
+    WITHDRAW_REFUNDABLE_N_PUBLIC,
+    WITHDRAW_CHANGE_REFUNDABLE_N_PUBLIC,
+    AUDIT_N_PUBLIC,
+    MAX_JOINSPLIT_INPUTS,
+    MAX_JOINSPLIT_OUTPUTS,
 };
 
 // Re-export types
@@ -33,40 +33,164 @@ pub const MAX_IC: usize = 64;
 
 // ---- Circuit-specific public counts ----------------------------------------
 // From compile logs (public inputs + public outputs)
-pub const DEPOSIT_N_PUBLIC: usize  = 7; // 3 in + 4 out
-pub const TRANSFER_N_PUBLIC: usize = 9; // 2 in + 7 out
-pub const WITHDRAW_N_PUBLIC: usize = 7; // 3 in + 4 out (owner limbs added)
+pub const DEPOSIT_N_PUBLIC: usize  = 5; // commitment is appended on-chain (no root publics)
+pub const TRANSFER_N_PUBLIC: usize = 9; // outputs are appended on-chain (no new roots / next index)
+pub const WITHDRAW_N_PUBLIC: usize = 10; // 6 in + 4 out (owner limbs, relayer fee + relayer limbs)
+pub const WITHDRAW_CHANGE_N_PUBLIC: usize = 12; // withdraw publics + change commitment + enc hash
+pub const WITHDRAW_REFUNDABLE_N_PUBLIC: usize = 11; // withdraw publics + refund commitment
+pub const WITHDRAW_CHANGE_REFUNDABLE_N_PUBLIC: usize = 13; // change publics + refund commitment
+pub const AUDIT_N_PUBLIC: usize = 10; // nothing is appended; root + auditor + disclosed totals + prover
+
+// ---- Circuit ids (on-chain VK registry: seeds = [VK_SEED, [circuit], version_le]) ----
+pub mod circuit_id {
+    pub const DEPOSIT: u8  = 0;
+    pub const TRANSFER: u8 = 1;
+    pub const WITHDRAW: u8 = 2;
+    /// Partial withdraw that appends a change note (registry VK only).
+    pub const WITHDRAW_CHANGE: u8 = 3;
+    /// Selective-disclosure audit proof over a set of notes (registry VK only).
+    pub const AUDIT: u8 = 4;
+    /// Withdraw that also commits to a refund note, required when the payout is
+    /// time-locked (registry VK only).
+    pub const WITHDRAW_REFUNDABLE: u8 = 5;
+    /// Change-note withdraw with a refund note (registry VK only).
+    pub const WITHDRAW_CHANGE_REFUNDABLE: u8 = 6;
+
+    /// Join-split circuits: 0b01nn_nnmm → 0x40 | (N-1) << 2 | (M-1)
+    pub const JOINSPLIT_BASE: u8 = 0x40;
+    pub const fn joinsplit(n_inputs: u8, n_outputs: u8) -> u8 {
+        JOINSPLIT_BASE | ((n_inputs - 1) << 2) | (n_outputs - 1)
+    }
+
+    /// Aggregated K-deposit circuits: 0b1000_0kkk → 0x80 | (K-1)
+    pub const DEPOSIT_BATCH_BASE: u8 = 0x80;
+    pub const fn deposit_batch(k: u8) -> u8 {
+        DEPOSIT_BATCH_BASE | (k - 1)
+    }
+
+    /// Sequencer batch-insert circuits over N queued leaves: 0b1001_nnnn → 0x90 | (N-1)
+    pub const BATCH_INSERT_BASE: u8 = 0x90;
+    pub const fn batch_insert(n: u8) -> u8 {
+        BATCH_INSERT_BASE | (n - 1)
+    }
+}
+
+// ---- Join-split (N-in / M-out) shape limits --------------------------------
+pub const MAX_JOINSPLIT_INPUTS: usize  = 4;
+pub const MAX_JOINSPLIT_OUTPUTS: usize = 2;
+
+/// Decode (N, M) from a join-split circuit id (None if not a supported join-split).
+pub fn joinsplit_shape(circuit: u8) -> Option<(usize, usize)> {
+    if circuit & 0xF0 != circuit_id::JOINSPLIT_BASE { return None; }
+    let n = (((circuit >> 2) & 0x03) + 1) as usize;
+    let m = ((circuit & 0x03) + 1) as usize;
+    if n > MAX_JOINSPLIT_INPUTS || m > MAX_JOINSPLIT_OUTPUTS { return None; }
+    Some((n, m))
+}
+
+// ---- Batched deposits (one proof over K deposits) ----------------------------
+// K is capped so every batch arity (5K publics) has a verifier instantiated below.
+pub const MAX_DEPOSIT_BATCH: usize = 4;
+
+/// Decode K from an aggregated deposit circuit id (None if not a supported batch).
+pub fn deposit_batch_size(circuit: u8) -> Option<usize> {
+    if circuit & 0xF8 != circuit_id::DEPOSIT_BATCH_BASE { return None; }
+    let k = ((circuit & 0x07) + 1) as usize;
+    (k <= MAX_DEPOSIT_BATCH).then_some(k)
+}
+
+// ---- Sequencer batch inserts (one proof over N queued leaves) ---------------
+pub const MAX_BATCH_INSERT: usize = 16;
+
+/// Decode N from a batch-insert circuit id (None if not a batch insert).
+pub fn batch_insert_size(circuit: u8) -> Option<usize> {
+    if circuit & 0xF0 != circuit_id::BATCH_INSERT_BASE { return None; }
+    Some(((circuit & 0x0F) + 1) as usize)
+}
+
+/// Public-signal count expected by `circuit` (None = unknown circuit id).
+pub fn n_public_for(circuit: u8) -> Option<usize> {
+    match circuit {
+        circuit_id::DEPOSIT  => Some(DEPOSIT_N_PUBLIC),
+        circuit_id::TRANSFER => Some(TRANSFER_N_PUBLIC),
+        circuit_id::WITHDRAW => Some(WITHDRAW_N_PUBLIC),
+        circuit_id::WITHDRAW_CHANGE => Some(WITHDRAW_CHANGE_N_PUBLIC),
+        circuit_id::AUDIT => Some(AUDIT_N_PUBLIC),
+        circuit_id::WITHDRAW_REFUNDABLE => Some(WITHDRAW_REFUNDABLE_N_PUBLIC),
+        circuit_id::WITHDRAW_CHANGE_REFUNDABLE => Some(WITHDRAW_CHANGE_REFUNDABLE_N_PUBLIC),
+        c => joinsplit_shape(c)
+            .map(|(n, m)| joinsplit_idx::n_public(n, m))
+            .or_else(|| deposit_batch_size(c).map(deposit_batch_idx::n_public))
+            .or_else(|| batch_insert_size(c).map(batch_insert_idx::n_public)),
+    }
+}
 
 // ---- Public signal indices (adjust if your order differs) -------------------
+/// Deposit: NEW_COMMITMENT is appended on-chain against the tree frontier.
 pub mod deposit_idx {
     pub const NEW_COMMITMENT: usize         = 0;
     pub const OWNER_CIPHERPAY_PUBKEY: usize = 1;
-    pub const NEW_MERKLE_ROOT: usize        = 2;
-    pub const NEW_NEXT_LEAF_INDEX: usize    = 3;
-    pub const AMOUNT: usize                 = 4;
-    pub const DEPOSIT_HASH: usize           = 5;
-    pub const OLD_MERKLE_ROOT: usize        = 6;
+    pub const AMOUNT: usize                 = 2;
+    pub const DEPOSIT_HASH: usize           = 3;
+    /// `utils::token_id_for(mint)`: binds the note to the pool it is deposited into.
+    pub const TOKEN_ID: usize               = 4;
+}
+/// Batched deposit layout for K deposits: K consecutive `deposit_idx` blocks
+/// [ newCommitment, ownerCipherPayPubKey, amount, depositHash, tokenId ] × K.
+pub mod deposit_batch_idx {
+    use super::DEPOSIT_N_PUBLIC;
+    pub const fn signal(i: usize, field: usize) -> usize { i * DEPOSIT_N_PUBLIC + field }
+    pub const fn n_public(k: usize) -> usize { k * DEPOSIT_N_PUBLIC }
 }
+/// Batch insert over N queued leaves: proves that writing leaf[0..N] at
+/// START_INDEX.. into the tree with root OLD_ROOT (empty from START_INDEX on)
+/// gives NEW_ROOT. [ oldRoot, newRoot, startIndex, leaf[0..N] ]
+pub mod batch_insert_idx {
+    pub const OLD_ROOT: usize    = 0;
+    pub const NEW_ROOT: usize    = 1;
+    pub const START_INDEX: usize = 2;
+    pub const fn leaf(i: usize) -> usize { 3 + i }
+    pub const fn n_public(n: usize) -> usize { 3 + n }
+}
+/// Transfer: MERKLE_ROOT is the membership root (any cached root); the two
+/// output commitments are appended on-chain against the tree frontier.
 pub mod transfer_idx {
     pub const OUT_COMMITMENT_1:    usize = 0;
     pub const OUT_COMMITMENT_2:    usize = 1;
     pub const NULLIFIER:           usize = 2;
     pub const MERKLE_ROOT:         usize = 3;
-    pub const NEW_MERKLE_ROOT_1:   usize = 4;
-    pub const NEW_MERKLE_ROOT_2:   usize = 5;
-    pub const NEW_NEXT_LEAF_INDEX: usize = 6;
-    pub const ENC_NOTE1_HASH:      usize = 7;
-    pub const ENC_NOTE2_HASH:      usize = 8;
+    pub const ENC_NOTE1_HASH:      usize = 4;
+    pub const ENC_NOTE2_HASH:      usize = 5;
+    pub const FEE:                 usize = 6; // paid to the relayer from the vault
+    pub const RELAYER_LO:          usize = 7; // relayer wallet, LE 128-bit limbs
+    pub const RELAYER_HI:          usize = 8;
+}
+/// Join-split layout for N inputs / M outputs (2M + N + 4 × 32B LE limbs):
+/// [ outC[0..M] | nullifier[0..N] | merkleRoot | encNoteHash[0..M] | fee | relayer_lo | relayer_hi ]
+/// `merkleRoot` is the membership root; outputs are appended on-chain.
+pub mod joinsplit_idx {
+    pub const fn out_commitment(_n: usize, _m: usize, j: usize) -> usize { j }
+    pub const fn nullifier(_n: usize, m: usize, i: usize) -> usize { m + i }
+    pub const fn merkle_root(n: usize, m: usize) -> usize { m + n }
+    pub const fn enc_note_hash(n: usize, m: usize, j: usize) -> usize { m + n + 1 + j }
+    pub const fn fee(n: usize, m: usize) -> usize { 2 * m + n + 1 }
+    pub const fn relayer_lo(n: usize, m: usize) -> usize { 2 * m + n + 2 }
+    pub const fn relayer_hi(n: usize, m: usize) -> usize { 2 * m + n + 3 }
+    pub const fn n_public(n: usize, m: usize) -> usize { 2 * m + n + 4 }
 }
+
 pub mod withdraw_idx {
-    // UPDATED order (7 × 32B LE limbs)
+    // UPDATED order (10 × 32B LE limbs)
     // [0] nullifier
     // [1] merkleRoot
     // [2] recipientOwner_lo
     // [3] recipientOwner_hi
-    // [4] recipientWalletPubKey
-    // [5] amount
-    // [6] tokenId
+    // [4] recipientWalletPubKey (utils::recipient_wallet_field of the recipient ATA)
+    // [5] amount        (note value; recipient gets amount - fee)
+    // [6] tokenId       (utils::token_id_for of the pool mint)
+    // [7] fee           (paid to the relayer out of amount)
+    // [8] relayer_lo    (relayer wallet, LE 128-bit limbs like recipientOwner)
+    // [9] relayer_hi
     pub const NULLIFIER: usize               = 0;
     pub const MERKLE_ROOT: usize             = 1;
     pub const RECIPIENT_OWNER_LO: usize      = 2;
@@ -74,6 +198,58 @@ pub mod withdraw_idx {
     pub const RECIPIENT_WALLET_PUBKEY: usize = 4;
     pub const AMOUNT: usize                  = 5;
     pub const TOKEN_ID: usize                = 6;
+    pub const FEE: usize                     = 7;
+    pub const RELAYER_LO: usize              = 8;
+    pub const RELAYER_HI: usize              = 9;
+
+    // Change-note mode (WITHDRAW_CHANGE, 12 × 32B): the unspent remainder of
+    // the note is re-shielded as a new commitment, appended on-chain.
+    // [10] changeCommitment
+    // [11] encChangeNoteHash
+    pub const CHANGE_COMMITMENT: usize       = 10;
+    pub const ENC_CHANGE_NOTE_HASH: usize    = 11;
+
+    // Refundable modes (WITHDRAW_REFUNDABLE 11 × 32B, WITHDRAW_CHANGE_REFUNDABLE 13 × 32B):
+    // the last signal is refundCommitment, a note for the full `amount` back to the
+    // spender. It is stored when the payout is time-locked and appended on cancel.
+    pub const fn refund_commitment(with_change: bool) -> usize {
+        if with_change { 12 } else { 10 }
+    }
+}
+
+/// Withdraw variant from the public-input count: (circuit, with_change, refundable).
+pub fn withdraw_layout(n_public: usize) -> Option<(u8, bool, bool)> {
+    match n_public {
+        WITHDRAW_N_PUBLIC => Some((circuit_id::WITHDRAW, false, false)),
+        WITHDRAW_CHANGE_N_PUBLIC => Some((circuit_id::WITHDRAW_CHANGE, true, false)),
+        WITHDRAW_REFUNDABLE_N_PUBLIC => Some((circuit_id::WITHDRAW_REFUNDABLE, false, true)),
+        WITHDRAW_CHANGE_REFUNDABLE_N_PUBLIC => Some((circuit_id::WITHDRAW_CHANGE_REFUNDABLE, true, true)),
+        _ => None,
+    }
+}
+/// Audit: the prover owns a set of notes under MERKLE_ROOT whose amounts sum to
+/// TOTAL_AMOUNT, disclosed to the auditor (wallet limbs like recipientOwner).
+pub mod audit_idx {
+    // [0] merkleRoot
+    // [1] auditor_lo
+    // [2] auditor_hi
+    // [3] tokenId         (utils::token_id_for of the pool mint)
+    // [4] totalAmount
+    // [5] noteSetHash     (Poseidon chain of the disclosed notes' nullifiers)
+    // [6] disclosureHash  (hash of the note openings encrypted to the auditor)
+    // [7] auditNonce      (auditor's challenge; keys the attestation PDA)
+    // [8] prover_lo       (submitting wallet, LE 128-bit limbs; must sign)
+    // [9] prover_hi
+    pub const MERKLE_ROOT: usize     = 0;
+    pub const AUDITOR_LO: usize      = 1;
+    pub const AUDITOR_HI: usize      = 2;
+    pub const TOKEN_ID: usize        = 3;
+    pub const TOTAL_AMOUNT: usize    = 4;
+    pub const NOTE_SET_HASH: usize   = 5;
+    pub const DISCLOSURE_HASH: usize = 6;
+    pub const AUDIT_NONCE: usize     = 7;
+    pub const PROVER_LO: usize       = 8;
+    pub const PROVER_HI: usize       = 9;
 }
 
 // -------------------- Little helpers (LE/BE & math) -------------------------
@@ -244,11 +420,70 @@ fn verify_once_const<const N: usize>(vk_be: &[u8], proof_le: &[u8], public_le: &
     verifier.verify().map_err(|_| "ProofVerificationFailed")
 }
 
+/// Structural check of a vk.bin blob for a circuit with `n_public` inputs
+/// (used when finalizing an on-chain VK before any proof depends on it).
+pub fn validate_vk_bytes(vk_be: &[u8], n_public: usize) -> Result<(), &'static str> {
+    let (_, _, _, _, ic_vec) = parse_vk_parts(vk_be)?;
+    if ic_vec.len() != n_public + 1 { return Err("vk.ic count != N+1"); }
+    if ic_vec.len() > MAX_IC { return Err("vk.ic too large"); }
+    Ok(())
+}
+
+/// Public-input counts `verify_with_vk` instantiates a verifier for.
+pub const SUPPORTED_N_PUBLIC: &[usize] = &[4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20];
+
+/// Does `verify_with_vk` have a verifier for `n_public` inputs?
+pub fn has_verifier(n_public: usize) -> bool {
+    SUPPORTED_N_PUBLIC.contains(&n_public)
+}
+
+/// Public-signal count of `circuit` if it is known *and* verifiable (registry VKs for
+/// circuits without a verifier arm would be accepted but could never verify a proof).
+pub fn verifiable_n_public(circuit: u8) -> Option<usize> {
+    n_public_for(circuit).filter(|&n| has_verifier(n))
+}
+
+/// Verify against an arbitrary VK (e.g. loaded from the on-chain registry).
+/// `N` is picked from the public-input count; only `SUPPORTED_N_PUBLIC` are instantiated.
+pub fn verify_with_vk(vk_be: &[u8], proof_le: &[u8], public_le: &[u8]) -> Result<(), &'static str> {
+    if !public_le.len().is_multiple_of(BYTES_F) { return Err("public inputs len not multiple of 32"); }
+    match public_le.len() / BYTES_F {
+        4  => verify_once_const::<4>(vk_be, proof_le, public_le),  // batch insert N=1
+        5  => verify_once_const::<5>(vk_be, proof_le, public_le),  // deposit, batch insert N=2
+        6  => verify_once_const::<6>(vk_be, proof_le, public_le),  // batch insert N=3
+        7  => verify_once_const::<7>(vk_be, proof_le, public_le),  // join-split 1x1, batch insert N=4
+        8  => verify_once_const::<8>(vk_be, proof_le, public_le),  // join-split 2x1, batch insert N=5
+        9  => verify_once_const::<9>(vk_be, proof_le, public_le),  // transfer, join-split 3x1/1x2, batch insert N=6
+        10 => verify_once_const::<10>(vk_be, proof_le, public_le), // withdraw, join-split 4x1/2x2, deposit batch K=2, audit, batch insert N=7
+        11 => verify_once_const::<11>(vk_be, proof_le, public_le), // join-split 3x2, batch insert N=8, refundable withdraw
+        12 => verify_once_const::<12>(vk_be, proof_le, public_le), // withdraw with change, join-split 4x2, batch insert N=9
+        13 => verify_once_const::<13>(vk_be, proof_le, public_le), // batch insert N=10, refundable withdraw with change
+        14 => verify_once_const::<14>(vk_be, proof_le, public_le), // batch insert N=11
+        15 => verify_once_const::<15>(vk_be, proof_le, public_le), // deposit batch K=3, batch insert N=12
+        16 => verify_once_const::<16>(vk_be, proof_le, public_le), // batch insert N=13
+        17 => verify_once_const::<17>(vk_be, proof_le, public_le), // batch insert N=14
+        18 => verify_once_const::<18>(vk_be, proof_le, public_le), // batch insert N=15
+        19 => verify_once_const::<19>(vk_be, proof_le, public_le), // batch insert N=16
+        20 => verify_once_const::<20>(vk_be, proof_le, public_le), // deposit batch K=4
+        _ => Err("unsupported public input count"),
+    }
+}
+
 // -------------------- Public wrappers per circuit ---------------------------
 const DEPOSIT_VK_BIN:  &[u8] = include_bytes!("deposit_vk.bin");
 const TRANSFER_VK_BIN: &[u8] = include_bytes!("transfer_vk.bin");
 const WITHDRAW_VK_BIN: &[u8] = include_bytes!("withdraw_vk.bin");
 
+/// VK compiled into the program for `circuit` (registry version 0).
+pub fn embedded_vk(circuit: u8) -> Option<&'static [u8]> {
+    match circuit {
+        circuit_id::DEPOSIT  => Some(DEPOSIT_VK_BIN),
+        circuit_id::TRANSFER => Some(TRANSFER_VK_BIN),
+        circuit_id::WITHDRAW => Some(WITHDRAW_VK_BIN),
+        _ => None,
+    }
+}
+
 pub fn verify_deposit(proof_le: &[u8], public_le: &[u8]) -> Result<(), &'static str> {
     verify_once_const::<{ DEPOSIT_N_PUBLIC }>(DEPOSIT_VK_BIN, proof_le, public_le)
 }
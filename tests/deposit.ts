@@ -1,12 +1,12 @@
 // tests/deposit.ts
 import * as fs from "fs";
 import * as path from "path";
+import { createHash } from "crypto";
 import * as anchor from "@coral-xyz/anchor";
 import { Program, AnchorProvider, web3 } from "@coral-xyz/anchor";
 import {
   TOKEN_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
-  createMint,
   getAssociatedTokenAddressSync,
   createAssociatedTokenAccountInstruction,
   mintTo,
@@ -50,6 +50,12 @@ function slice32(buf: Buffer, i: number): Buffer {
   const off = i * 32;
   return buf.subarray(off, off + 32);
 }
+// Canonical tokenId of a mint (LE field element), mirrors `utils::token_id_for`
+function tokenIdFor(mint: web3.PublicKey): Buffer {
+  const id = createHash("sha256").update("cipherpay:token_id").update(mint.toBuffer()).digest();
+  id[31] &= 0x1f;
+  return id;
+}
 async function ensureAirdrop(
   connection: web3.Connection,
   pubkey: web3.PublicKey,
@@ -93,8 +99,18 @@ const VARIANT = (process.env.DEPOSIT_VARIANT || "deposit").trim();
 // PDA seeds (must match on-chain constants)
 const TREE_SEED = Buffer.from("tree");
 const ROOT_CACHE_SEED = Buffer.from("root_cache");
+// Current (non-full) tree of the pool, u32 LE in the tree/root_cache seeds.
+const TREE_ID = Buffer.alloc(4);
+TREE_ID.writeUInt32LE(Number(process.env.CP_TREE_ID ?? 0));
 const VAULT_SEED = Buffer.from("vault");
 const DEPOSIT_SEED = Buffer.from("deposit");
+// Hashes of closed deposit markers, sharded by the first byte of the hash
+const PROCESSED_DEPOSITS_SEED = Buffer.from("dep_shard");
+// Per-mint deposit / withdraw limits (may not exist: no limits)
+const POOL_CONFIG_SEED = Buffer.from("pool_config");
+
+// Pools are per mint: reuse the mint initialized by `anchor run init`
+const POOL_MINT = process.env.CP_TOKEN_MINT || "";
 
 // proofs dir (override with env if desired)
 const buildDir = process.env.DEPOSIT_BUILD_DIR
@@ -105,16 +121,16 @@ const buildDir = process.env.DEPOSIT_BUILD_DIR
 const proofPath = path.join(buildDir, `${VARIANT}_proof.bin`);
 const publicsPath = path.join(buildDir, `${VARIANT}_public_signals.bin`);
 
-// public signal order used by your circuit export:
+// public signal order used by your circuit export
+// (the commitment is appended on-chain, so no root / next-index publics):
 const DEPOSIT_IDX = {
   NEW_COMMITMENT: 0,
   OWNER: 1,
-  NEW_ROOT: 2,
-  NEW_NEXT_IDX: 3,
-  AMOUNT: 4,
-  DEPOSIT_HASH: 5,
-  OLD_ROOT: 6,
+  AMOUNT: 2,
+  DEPOSIT_HASH: 3,
+  TOKEN_ID: 4,
 } as const;
+const DEPOSIT_N_PUBLIC = 5;
 
 // mint config — 0 decimals to keep amounts simple
 const MINT_DECIMALS = 0 as const;
@@ -163,7 +179,7 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
   let vaultPda!: web3.PublicKey;
   let vaultAta!: web3.PublicKey;
 
-  // global tree & root cache PDAs (pre-initialized by migration)
+  // per-mint tree & root cache PDAs (pre-initialized by migration)
   let treePda!: web3.PublicKey;
   let rootCachePda!: web3.PublicKey;
 
@@ -177,7 +193,7 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
     proofBytes = readBin(proofPath);
     publicInputsBytes = readBin(publicsPath);
     expect(proofBytes.length).toBe(256);
-    expect(publicInputsBytes.length).toBe(7 * 32);
+    expect(publicInputsBytes.length).toBe(DEPOSIT_N_PUBLIC * 32);
 
     // extract fields
     depositHash = slice32(publicInputsBytes, DEPOSIT_IDX.DEPOSIT_HASH);
@@ -196,9 +212,13 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
     console.log(`💵 amount (u64) = ${amountU64}`);
 
     // ---- Derive PDAs (must already exist, created by migrations/01_init.ts)
-    [treePda] = web3.PublicKey.findProgramAddressSync([TREE_SEED], programId);
+    if (!POOL_MINT) throw new Error("CP_TOKEN_MINT not set. Run `anchor run init` and export it.");
+    tokenMint = new web3.PublicKey(POOL_MINT);
+    // The proof must be generated for this pool's mint
+    expect(toHexLE(slice32(publicInputsBytes, DEPOSIT_IDX.TOKEN_ID))).toBe(toHexLE(tokenIdFor(tokenMint)));
+    [treePda] = web3.PublicKey.findProgramAddressSync([TREE_SEED, tokenMint.toBuffer(), TREE_ID], programId);
     [rootCachePda] = web3.PublicKey.findProgramAddressSync(
-      [ROOT_CACHE_SEED],
+      [ROOT_CACHE_SEED, tokenMint.toBuffer(), TREE_ID],
       programId
     );
     console.log("🌲 tree PDA:", treePda.toBase58());
@@ -219,16 +239,9 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
     }
 
     // ---- SPL Mint & ATAs ----
-    tokenMint = await createMint(
-      connection,
-      wallet.payer, // fee payer
-      payer, // mint authority
-      null, // freeze authority
-      MINT_DECIMALS
-    );
     console.log("✅ token mint:", tokenMint.toBase58());
 
-    [vaultPda] = web3.PublicKey.findProgramAddressSync([VAULT_SEED], programId);
+    [vaultPda] = web3.PublicKey.findProgramAddressSync([VAULT_SEED, tokenMint.toBuffer()], programId);
     console.log("🏦 vaultPda:", vaultPda.toBase58());
 
     payerAta = getAssociatedTokenAddressSync(
@@ -259,6 +272,15 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
       [DEPOSIT_SEED, dHash],
       programId
     );
+    const [processedDepositsPda] = web3.PublicKey.findProgramAddressSync(
+      [PROCESSED_DEPOSITS_SEED, dHash.subarray(0, 1)],
+      programId
+    );
+
+    const [poolConfigPda] = web3.PublicKey.findProgramAddressSync(
+      [POOL_CONFIG_SEED, tokenMint.toBuffer()],
+      programId
+    );
 
     const cuIx = web3.ComputeBudgetProgram.setComputeUnitLimit({ units: CU_LIMIT });
     const transferIx = createTransferCheckedInstruction(
@@ -273,7 +295,10 @@ describe("shielded_deposit_atomic (end-to-end) — assumes PDAs pre-initialized"
         payer,
         tree: treePda,
         rootCache: rootCachePda,
+        commitmentQueue: null, // tree not in sequencer mode
         depositMarker: depositMarkerPda,
+        processedDeposits: processedDepositsPda,
+        poolConfig: poolConfigPda,
         vaultPda,
         vaultTokenAccount: vaultAta,
         tokenMint,
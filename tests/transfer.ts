@@ -3,6 +3,7 @@ import * as fs from "fs";
 import * as path from "path";
 import * as anchor from "@coral-xyz/anchor";
 import { Program, AnchorProvider, web3 } from "@coral-xyz/anchor";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, getAssociatedTokenAddressSync } from "@solana/spl-token";
 
 type AnyIdl = Record<string, any>;
 function loadIdl(): AnyIdl {
@@ -43,7 +44,14 @@ const TRANSFER_VARIANT = (process.env.TRANSFER_VARIANT || "transfer").trim();
 
 const TREE_SEED = Buffer.from("tree");
 const ROOT_CACHE_SEED = Buffer.from("root_cache");
+// Current (non-full) tree of the pool, u32 LE in the tree/root_cache seeds.
+const TREE_ID = Buffer.alloc(4);
+TREE_ID.writeUInt32LE(Number(process.env.CP_TREE_ID ?? 0));
 const NULLIFIER_SEED = Buffer.from("nullifier");
+const VAULT_SEED = Buffer.from("vault");
+
+// Pools are per mint: reuse the mint initialized by `anchor run init`
+const POOL_MINT = process.env.CP_TOKEN_MINT || "";
 
 // proofs dir (override with env if desired)
 const buildDir = process.env.TRANSFER_BUILD_DIR ? path.resolve(process.env.TRANSFER_BUILD_DIR) : path.resolve("proofs");
@@ -52,7 +60,12 @@ const buildDir = process.env.TRANSFER_BUILD_DIR ? path.resolve(process.env.TRANS
 const proofPath = path.join(buildDir, `${TRANSFER_VARIANT}_proof.bin`);
 const publicsPath = path.join(buildDir, `${TRANSFER_VARIANT}_public_signals.bin`);
 
-const TRANSFER_IDX = { OUT1: 0, OUT2: 1, NULLIFIER: 2, MERKLE_ROOT: 3, NEW_ROOT1: 4, NEW_ROOT2: 5, NEW_NEXT_IDX: 6, ENC1: 7, ENC2: 8 } as const;
+// Outputs are appended on-chain, so new roots / next index are no longer publics.
+const TRANSFER_IDX = {
+  OUT1: 0, OUT2: 1, NULLIFIER: 2, MERKLE_ROOT: 3, ENC1: 4, ENC2: 5,
+  FEE: 6, RELAYER_LO: 7, RELAYER_HI: 8,
+} as const;
+const TRANSFER_N_PUBLIC = 9;
 
 describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", () => {
   const connection = new web3.Connection(RPC_URL, "confirmed");
@@ -75,39 +88,46 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
   let publicInputsBytes: Buffer;
   let nullifierBuf!: Buffer;
   let merkleRootBefore!: Buffer;
-  let newRoot1!: Buffer;
-  let newRoot2!: Buffer;
 
+  let tokenMint!: web3.PublicKey;
   let treePda!: web3.PublicKey;
   let rootCachePda!: web3.PublicKey;
+  let vaultPda!: web3.PublicKey;
+  let vaultAta!: web3.PublicKey;
 
   beforeAll(async () => {
     await ensureAirdrop(connection, payer);
     proofBytes = readBin(proofPath);
     publicInputsBytes = readBin(publicsPath);
     expect(proofBytes.length).toBe(256);
-    expect(publicInputsBytes.length).toBe(9 * 32);
+    expect(publicInputsBytes.length).toBe(TRANSFER_N_PUBLIC * 32);
 
     nullifierBuf = slice32(publicInputsBytes, TRANSFER_IDX.NULLIFIER);
     merkleRootBefore = slice32(publicInputsBytes, TRANSFER_IDX.MERKLE_ROOT);
-    newRoot1 = slice32(publicInputsBytes, TRANSFER_IDX.NEW_ROOT1);
-    newRoot2 = slice32(publicInputsBytes, TRANSFER_IDX.NEW_ROOT2);
 
     console.log("🔒 nullifier (LE, hex):", toHexLE(nullifierBuf));
     console.log("🌲 spent merkle root (LE, hex):", toHexLE(merkleRootBefore));
 
-    [treePda] = web3.PublicKey.findProgramAddressSync([TREE_SEED], programId);
-    [rootCachePda] = web3.PublicKey.findProgramAddressSync([ROOT_CACHE_SEED], programId);
+    if (!POOL_MINT) throw new Error("CP_TOKEN_MINT not set. Run `anchor run init` and export it.");
+    tokenMint = new web3.PublicKey(POOL_MINT);
+    [treePda] = web3.PublicKey.findProgramAddressSync([TREE_SEED, tokenMint.toBuffer(), TREE_ID], programId);
+    [rootCachePda] = web3.PublicKey.findProgramAddressSync([ROOT_CACHE_SEED, tokenMint.toBuffer(), TREE_ID], programId);
+    [vaultPda] = web3.PublicKey.findProgramAddressSync([VAULT_SEED, tokenMint.toBuffer()], programId);
+    vaultAta = getAssociatedTokenAddressSync(tokenMint, vaultPda, true, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
 
     const accountsAny = program.account as any;
     const treeAcc: any = await accountsAny["treeState"].fetch(treePda);
     const preNextIdx = toNum(treeAcc.nextIndex);
     const preRootBuf = Buffer.from(treeAcc.currentRoot as number[]);
     console.log("ℹ️ on-chain tree:", { nextIdx: preNextIdx, root: toHexLE(preRootBuf) });
-    expect(toHexLE(preRootBuf)).toBe(toHexLE(merkleRootBefore));
+
+    // The spent root only has to be *recent* (in the root cache), not the current tip.
+    const rc: any = await accountsAny["merkleRootCache"].fetch(rootCachePda);
+    const cachedRootsHex = (rc.roots as number[][]).map(arr => toHexLE(Buffer.from(arr)));
+    expect(cachedRootsHex.includes(toHexLE(merkleRootBefore))).toBe(true);
   });
 
-  it("verifies on-chain via shielded_transfer (+2 next_index; cache has both roots)", async () => {
+  it("verifies on-chain via shielded_transfer (+2 next_index; new tip cached)", async () => {
     const accountsAny = program.account as any;
 
     const [nullifierRecordPda] = web3.PublicKey.findProgramAddressSync([NULLIFIER_SEED, nullifierBuf], programId);
@@ -115,6 +135,7 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
 
     const treeBefore: any = await accountsAny["treeState"].fetch(treePda);
     const preNextIdx = toNum(treeBefore.nextIndex);
+    const preRootBuf = Buffer.from(treeBefore.currentRoot as number[]);
 
     // ⬇️ PASS A 32-BYTE BUFFER (IDL expects [u8; 32])
     // IMPORTANT: keep the original argument order and logic
@@ -124,8 +145,17 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
         payer,
         tree: treePda,
         rootCache: rootCachePda,
+        membershipRootCache: null, // spent root is from the current tree
+        commitmentQueue: null, // tree not in sequencer mode
         nullifierRecord: nullifierRecordPda,
+        nullifierShard: null, // legacy per-nullifier PDA mode
+        legacyNullifierRecord: null,
+        vaultPda,
+        vaultTokenAccount: vaultAta,
+        relayerTokenAccount: null, // self-submitted (fee must be 0)
+        tokenMint,
         systemProgram: web3.SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
       })
       .instruction();
 
@@ -143,7 +173,7 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
     const postNextIdx = toNum(treeAfter.nextIndex);
     const postRootBuf = Buffer.from(treeAfter.currentRoot as number[]);
     expect(postNextIdx).toBe(preNextIdx + 2);
-    expect(toHexLE(postRootBuf)).toBe(toHexLE(newRoot2));
+    expect(toHexLE(postRootBuf)).not.toBe(toHexLE(preRootBuf));
 
     const rc: any = await (program.account as any)["merkleRootCache"].fetch(rootCachePda);
     const count = toNum(rc.count);
@@ -152,10 +182,8 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
       .slice(0, Math.max(count, rootsArr.length))
       .map(arr => toHexLE(Buffer.from(arr)));
 
-    const hex1 = toHexLE(newRoot1);
-    const hex2 = toHexLE(newRoot2);
-    expect(cachedRootsHex.includes(hex1)).toBe(true);
-    expect(cachedRootsHex.includes(hex2)).toBe(true);
+    // Both appended roots are computed on-chain; the final one is the new tip.
+    expect(cachedRootsHex.includes(toHexLE(postRootBuf))).toBe(true);
 
     const res = await connection.getTransaction(sig, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
     console.log("---- on-chain logs ----");
@@ -174,8 +202,17 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
         payer,
         tree: treePda,
         rootCache: rootCachePda,
+        membershipRootCache: null, // spent root is from the current tree
+        commitmentQueue: null, // tree not in sequencer mode
         nullifierRecord: nullifierRecordPda,
+        nullifierShard: null, // legacy per-nullifier PDA mode
+        legacyNullifierRecord: null,
+        vaultPda,
+        vaultTokenAccount: vaultAta,
+        relayerTokenAccount: null, // self-submitted (fee must be 0)
+        tokenMint,
         systemProgram: web3.SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
       })
       .instruction();
 
@@ -192,6 +229,6 @@ describe("shielded_transfer — assumes PDAs pre-initialized (no init here)", ()
     expect(pA.length).toBe(64);
     expect(pB.length).toBe(128);
     expect(pC.length).toBe(64);
-    expect(publicInputsBytes.length).toBe(9 * 32);
+    expect(publicInputsBytes.length).toBe(TRANSFER_N_PUBLIC * 32);
   });
 });
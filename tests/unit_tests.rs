@@ -20,6 +20,1370 @@ fn test_anchor_imports() {
     assert!(true);
 }
 
+// ─── On-chain incremental Merkle tree (Poseidon frontier) ───
+
+use cipherpay_anchor::state::TreeState;
+use cipherpay_anchor::utils::{append_leaf, init_tree_frontier, poseidon_pair};
+
+fn empty_tree(depth: u8) -> TreeState {
+    let mut t = TreeState {
+        version: 1,
+        current_root: [0u8; 32],
+        next_index: 0,
+        depth,
+        mint: Pubkey::default(),
+        tree_id: 0,
+        sequencer_mode: 0,
+        frontier_stale: 0,
+        _reserved: [0u8; 25],
+        filled_subtrees: Vec::new(),
+        zeros: Vec::new(),
+    };
+    t.current_root = init_tree_frontier(&mut t).unwrap();
+    t
+}
+
+/// Recompute the root from all leaves (zero-padded to 2^depth).
+fn naive_root(leaves: &[[u8; 32]], depth: u8) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = leaves.to_vec();
+    level.resize(1usize << depth, [0u8; 32]);
+    for _ in 0..depth {
+        level = level.chunks(2).map(|p| poseidon_pair(&p[0], &p[1]).unwrap()).collect();
+    }
+    level[0]
+}
+
+fn leaf(i: u8) -> [u8; 32] {
+    let mut l = [0u8; 32];
+    l[0] = i;
+    l[1] = 0xA5;
+    l
+}
+
+#[test]
+fn test_poseidon_pair_matches_circomlib() {
+    // circomlib Poseidon(0, 0), big-endian:
+    // 0x2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864
+    let mut expected = [
+        0x20, 0x98, 0xf5, 0xfb, 0x9e, 0x23, 0x9e, 0xab, 0x3c, 0xea, 0xc3, 0xf2, 0x7b, 0x81, 0xe4, 0x81,
+        0xdc, 0x31, 0x24, 0xd5, 0x5f, 0xfe, 0xd5, 0x23, 0xa8, 0x39, 0xee, 0x84, 0x46, 0xb6, 0x48, 0x64,
+    ];
+    expected.reverse(); // program works on LE field elements
+    assert_eq!(poseidon_pair(&[0u8; 32], &[0u8; 32]).unwrap(), expected);
+}
+
+#[test]
+fn test_empty_tree_root_matches_naive() {
+    let t = empty_tree(4);
+    assert_eq!(t.zeros.len(), 4);
+    assert_eq!(t.filled_subtrees.len(), 4);
+    assert_eq!(t.current_root, naive_root(&[], 4));
+}
+
+#[test]
+fn test_append_leaf_matches_naive_root() {
+    let depth = 4;
+    let mut t = empty_tree(depth);
+    let mut leaves = Vec::new();
+    for i in 0..7u8 {
+        leaves.push(leaf(i));
+        let root = append_leaf(&mut t, &leaf(i)).unwrap();
+        assert_eq!(root, t.current_root);
+        assert_eq!(t.next_index, leaves.len() as u32);
+        assert_eq!(root, naive_root(&leaves, depth), "root mismatch after leaf {}", i);
+    }
+}
+
+#[test]
+fn test_append_leaf_rejects_uninitialized_frontier() {
+    let mut t = empty_tree(4);
+    t.filled_subtrees.clear();
+    assert!(append_leaf(&mut t, &leaf(1)).is_err());
+}
+
+#[test]
+fn test_append_leaf_rejects_full_tree() {
+    let depth = 2;
+    let mut t = empty_tree(depth);
+    assert_eq!(t.capacity(), 4);
+    for i in 0..4u8 {
+        assert!(!t.is_full());
+        append_leaf(&mut t, &leaf(i)).unwrap();
+    }
+    assert!(t.is_full());
+    let root = t.current_root;
+    assert!(append_leaf(&mut t, &leaf(4)).is_err());
+    assert_eq!(t.next_index, 4);
+    assert_eq!(t.current_root, root);
+}
+
+// ─── Sequencer mode: batch-inserted roots and frontier sync ───
+
+use cipherpay_anchor::state::{CommitmentQueue, PendingCommitments};
+use cipherpay_anchor::utils::{frontier_root, sync_frontier};
+
+#[test]
+fn test_frontier_root_reproduces_appended_root() {
+    let mut t = empty_tree(5);
+    for i in 0..11u8 {
+        append_leaf(&mut t, &leaf(i)).unwrap();
+        assert_eq!(frontier_root(&t, &t.filled_subtrees).unwrap(), t.current_root);
+    }
+}
+
+#[test]
+fn test_sync_frontier_after_batch_insert() {
+    // What the batch-insert proof would move the root to
+    let mut proven = empty_tree(4);
+    for i in 0..6u8 {
+        append_leaf(&mut proven, &leaf(i)).unwrap();
+    }
+
+    let mut t = empty_tree(4);
+    t.current_root = proven.current_root;
+    t.next_index = proven.next_index;
+    t.frontier_stale = 1;
+    assert!(append_leaf(&mut t, &leaf(6)).is_err());
+
+    let mut wrong = proven.filled_subtrees.clone();
+    wrong[1] = leaf(9);
+    assert!(sync_frontier(&mut t, wrong).is_err());
+    sync_frontier(&mut t, proven.filled_subtrees.clone()).unwrap();
+
+    append_leaf(&mut t, &leaf(6)).unwrap();
+    append_leaf(&mut proven, &leaf(6)).unwrap();
+    assert_eq!(t.current_root, proven.current_root);
+}
+
+#[test]
+fn test_append_leaf_rejects_sequenced_tree() {
+    let mut t = empty_tree(4);
+    t.sequencer_mode = 1;
+    assert!(append_leaf(&mut t, &leaf(1)).is_err());
+    assert_eq!(t.next_index, 0);
+}
+
+#[test]
+fn test_commitment_queue_fifo() {
+    let mut data = vec![0u8; CommitmentQueue::body_size(4)];
+    let mut q = PendingCommitments::init(&mut data[..], Pubkey::default(), 0, 255, 4).unwrap();
+    q.push(&[leaf(1), leaf(2), leaf(3)]).unwrap();
+    assert!(q.push(&[leaf(4), leaf(5)]).is_err()); // all or nothing
+    assert_eq!(q.len(), 3);
+
+    assert_eq!(q.front(2).unwrap(), [leaf(1), leaf(2)].concat().as_slice());
+    assert!(q.front(4).is_err());
+    q.pop_front(2).unwrap();
+    q.push(&[leaf(4), leaf(5)]).unwrap();
+    assert_eq!(q.front(3).unwrap(), [leaf(3), leaf(4), leaf(5)].concat().as_slice());
+    assert!(q.pop_front(4).is_err());
+}
+
+#[test]
+fn test_commitment_queue_underflow() {
+    use cipherpay_anchor::error::CipherPayError;
+    let mut data = vec![0u8; CommitmentQueue::body_size(4)];
+    let mut q = PendingCommitments::init(&mut data[..], Pubkey::default(), 0, 255, 4).unwrap();
+    q.push(&[leaf(1)]).unwrap();
+    // Asking for more than is queued is an underflow, not a full queue
+    assert_eq!(q.front(2).unwrap_err(), CipherPayError::CommitmentQueueUnderflow.into());
+    assert_eq!(q.pop_front(2).unwrap_err(), CipherPayError::CommitmentQueueUnderflow.into());
+    assert_eq!(q.len(), 1);
+    assert!(q.front(0).unwrap().is_empty());
+}
+
+#[test]
+fn test_queued_deposit_marker_root_pending() {
+    use cipherpay_anchor::state::DepositMarker;
+    let mut marker = DepositMarker {
+        processed: true,
+        bump: 255,
+        payer: Pubkey::new_unique(),
+        created_slot: 7,
+        mint: Pubkey::new_unique(),
+        tree_id: 0,
+        merkle_root: [0u8; 32],
+        leaf_index: 0,
+        root_pending: false,
+    };
+    assert_eq!(marker.try_to_vec().unwrap().len(), DepositMarker::SIZE);
+
+    // Queued commitments come back from `add_commitments` without a root
+    marker.set_appended([0u8; 32], 5);
+    assert!(marker.root_pending);
+    assert_eq!(marker.leaf_index, 5);
+
+    marker.set_appended(leaf(1), 5);
+    assert!(!marker.root_pending);
+    assert_eq!(marker.merkle_root, leaf(1));
+}
+
+// ─── Root cache: capacity header + hash index ───
+
+use cipherpay_anchor::state::{MerkleRootCache, RootRing};
+
+/// Poseidon outputs, like real tree roots.
+fn root(i: u32) -> [u8; 32] {
+    let mut x = [0u8; 32];
+    x[..4].copy_from_slice(&i.to_le_bytes());
+    poseidon_pair(&x, &[0u8; 32]).unwrap()
+}
+
+fn filled_ring(data: &mut [u8], capacity: u32, inserts: u32) -> RootRing<&mut [u8]> {
+    let mut ring = RootRing::init(data, capacity, 0, Pubkey::default()).unwrap();
+    for i in 0..inserts {
+        ring.insert(root(i), i as u64);
+    }
+    ring
+}
+
+#[test]
+fn test_root_ring_evicts_oldest() {
+    let mut data = vec![0u8; MerkleRootCache::body_size(8)];
+    let ring = filled_ring(&mut data, 8, 20);
+    assert_eq!(ring.header().count, 8);
+    assert_eq!(ring.latest(), Some(root(19)));
+    for i in 0..12 {
+        assert!(!ring.contains(&root(i)), "root {} should be evicted", i);
+    }
+    for i in 12..20 {
+        assert!(ring.contains(&root(i)), "root {} should be cached", i);
+    }
+}
+
+#[test]
+fn test_root_ring_grow_keeps_roots() {
+    let mut data = vec![0u8; MerkleRootCache::body_size(32)];
+    let mut ring = filled_ring(&mut data, 8, 13);
+    assert_eq!(ring.grow(32).unwrap(), 8);
+    assert_eq!(ring.header().count, 8);
+    assert_eq!(ring.latest(), Some(root(12)));
+    for i in 5..13 {
+        assert!(ring.contains(&root(i)));
+    }
+    // Room for 24 more before anything is evicted
+    for i in 13..37 {
+        ring.insert(root(i), i as u64);
+    }
+    for i in 5..37 {
+        assert!(ring.contains(&root(i)), "root {} lost after grow", i);
+    }
+    assert!(ring.grow(16).is_err());
+}
+
+#[test]
+fn test_root_ring_max_age() {
+    let mut data = vec![0u8; MerkleRootCache::body_size(16)];
+    let mut ring = filled_ring(&mut data, 8, 11); // root(i) cached at slot i; 0..3 evicted
+    assert_eq!(ring.inserted_at(&root(3)), Some(3));
+    // No expiry: ring position alone decides
+    assert!(ring.is_fresh(&root(3), 1_000, 0));
+    assert!(!ring.is_fresh(&root(2), 1_000, 0));
+    // Quiet pool: old roots expire even though they are still in the ring
+    assert!(!ring.is_fresh(&root(3), 12, 5));
+    assert!(ring.is_fresh(&root(7), 12, 5));
+    // Slots follow their roots through a grow
+    ring.grow(16).unwrap();
+    for i in 3..11 {
+        assert_eq!(ring.inserted_at(&root(i)), Some(i as u64));
+    }
+}
+
+#[test]
+fn test_root_ring_min_age_blocks_eviction() {
+    let mut data = vec![0u8; MerkleRootCache::body_size(16)];
+    let mut ring = filled_ring(&mut data, 8, 8); // full: root(i) cached at slot i
+    // Evicting root(0) needs it to be at least `min_age` slots old
+    assert!(!ring.can_insert(9, 10));
+    assert!(ring.can_insert(10, 10));
+    assert!(ring.can_insert(9, 0));
+
+    // Growing the cache makes room without evicting anything
+    ring.grow(16).unwrap();
+    assert!(ring.can_insert(9, 10));
+    for i in 8..16 {
+        ring.insert(root(i), 9);
+    }
+    assert!(ring.contains(&root(0)));
+    assert!(!ring.can_insert(9, 10));
+}
+
+#[test]
+fn test_rollover_root_cache_fits_cpi_create() {
+    use cipherpay_anchor::constants::MAX_ROLLOVER_ROOT_CAPACITY;
+    assert!(MerkleRootCache::space(MAX_ROLLOVER_ROOT_CAPACITY) <= 10 * 1024);
+    assert!(MerkleRootCache::space(MAX_ROLLOVER_ROOT_CAPACITY + 1) > 10 * 1024);
+}
+
+#[test]
+fn test_root_ring_rejects_bad_capacity() {
+    let mut data = vec![0u8; MerkleRootCache::body_size(4)];
+    assert!(RootRing::init(&mut data[..], 0, 0, Pubkey::default()).is_err());
+    assert!(RootRing::init(&mut data[..], 5, 0, Pubkey::default()).is_err());
+    assert!(RootRing::new(&data[..]).is_err()); // never initialized
+}
+
+/// Index probes per lookup in a full, churned cache: (avg, max) buckets visited for
+/// cached roots, and for an unknown root. A probe count, not a compute-unit measurement:
+/// it checks that lookups stay constant-time as the capacity grows.
+fn lookup_probe_counts(capacity: u32) -> (f64, usize, usize) {
+    let mut data = vec![0u8; MerkleRootCache::body_size(capacity)];
+    let inserts = 3 * capacity;
+    let ring = filled_ring(&mut data, capacity, inserts);
+    let mut total = 0;
+    let mut max = 0;
+    for i in (inserts - capacity)..inserts {
+        let c = ring.lookup_cost(&root(i));
+        assert!(ring.contains(&root(i)));
+        total += c;
+        max = max.max(c);
+    }
+    let miss = ring.lookup_cost(&root(0));
+    assert!(!ring.contains(&root(0)));
+    let avg = total as f64 / capacity as f64;
+    println!(
+        "root cache {:>4}: hit avg {:.2} / max {} probes, miss {} (linear scan: {} compares)",
+        capacity, avg, max, miss, capacity
+    );
+    (avg, max, miss)
+}
+
+#[test]
+fn test_root_cache_probe_count_64() {
+    let (avg, max, _) = lookup_probe_counts(64);
+    assert!(avg < 2.0 && max <= 32);
+}
+
+#[test]
+fn test_root_cache_probe_count_256() {
+    let (avg, max, _) = lookup_probe_counts(256);
+    assert!(avg < 2.0 && max <= 32);
+}
+
+#[test]
+fn test_root_cache_probe_count_1024() {
+    let (avg, max, _) = lookup_probe_counts(1024);
+    assert!(avg < 2.0 && max <= 32);
+}
+
+// ─── Compact nullifier shards ───
+
+use cipherpay_anchor::state::{NullifierSet, NullifierShard};
+
+fn nf(prefix: u8, tag: u8) -> [u8; 32] {
+    let mut n = [0u8; 32];
+    n[0] = prefix;
+    n[1] = tag;
+    n[31] = tag.wrapping_mul(37);
+    n
+}
+
+#[test]
+fn test_nullifier_shard_rejects_double_spend() {
+    let mut data = vec![0u8; NullifierShard::body_size(8)];
+    let mut set = NullifierSet::init(&mut data[..], 7, 255, 8).unwrap();
+    for tag in [5u8, 1, 9, 3] {
+        set.insert(&nf(7, tag)).unwrap();
+    }
+    for tag in [5u8, 1, 9, 3] {
+        assert!(set.contains(&nf(7, tag)));
+        assert!(set.insert(&nf(7, tag)).is_err(), "double spend of {} accepted", tag);
+    }
+    assert!(!set.contains(&nf(7, 2)));
+    assert_eq!(set.header().count, 4);
+    // Wrong shard for this prefix
+    assert!(set.insert(&nf(8, 2)).is_err());
+}
+
+#[test]
+fn test_nullifier_shard_grows_when_full() {
+    let mut data = vec![0u8; NullifierShard::body_size(4)];
+    {
+        let mut set = NullifierSet::init(&mut data[..], 0, 1, 2).unwrap();
+        set.insert(&nf(0, 20)).unwrap();
+        set.insert(&nf(0, 10)).unwrap();
+        assert!(set.is_full());
+        assert!(set.insert(&nf(0, 30)).is_err());
+        set.set_capacity(4).unwrap();
+        set.insert(&nf(0, 30)).unwrap();
+        set.insert(&nf(0, 15)).unwrap();
+        assert!(set.set_capacity(8).is_err()); // data not grown
+    }
+    let set = NullifierSet::new(&data[..]).unwrap();
+    for tag in [10u8, 15, 20, 30] {
+        assert!(set.contains(&nf(0, tag)));
+    }
+}
+
+// ─── Batched deposit circuit ids ───
+
+use cipherpay_anchor::zk_verifier::solana_verifier::{
+    circuit_id, deposit_batch_size, n_public_for, DEPOSIT_N_PUBLIC, MAX_DEPOSIT_BATCH,
+};
+
+#[test]
+fn test_deposit_batch_circuit_ids() {
+    for k in 1..=MAX_DEPOSIT_BATCH as u8 {
+        let c = circuit_id::deposit_batch(k);
+        assert_eq!(deposit_batch_size(c), Some(k as usize));
+        assert_eq!(n_public_for(c), Some(DEPOSIT_N_PUBLIC * k as usize));
+    }
+    // Single-deposit and join-split ids are not batches
+    assert_eq!(deposit_batch_size(circuit_id::DEPOSIT), None);
+    assert_eq!(deposit_batch_size(circuit_id::joinsplit(2, 2)), None);
+}
+
+#[test]
+fn test_audit_circuit_id() {
+    use cipherpay_anchor::zk_verifier::solana_verifier::{audit_idx, embedded_vk, AUDIT_N_PUBLIC};
+    assert_eq!(n_public_for(circuit_id::AUDIT), Some(AUDIT_N_PUBLIC));
+    assert_eq!(audit_idx::PROVER_HI, AUDIT_N_PUBLIC - 1);
+    // The submitter is bound by its own limbs, separate from the auditor's
+    assert_eq!(audit_idx::PROVER_LO + 1, audit_idx::PROVER_HI);
+    assert_ne!(audit_idx::PROVER_LO, audit_idx::AUDITOR_LO);
+    // Registry VK only; the id is outside the join-split / batch ranges
+    assert!(embedded_vk(circuit_id::AUDIT).is_none());
+    assert_eq!(deposit_batch_size(circuit_id::AUDIT), None);
+}
+
+#[test]
+fn test_verify_with_vk_dispatches_circuit_arities() {
+    use cipherpay_anchor::zk_verifier::solana_verifier::{
+        has_verifier, verify_with_vk, BYTES_F, BYTES_PROOF, MAX_IC,
+    };
+    let proof = [0u8; BYTES_PROOF];
+    // An empty VK fails parsing, but only after an arm for the arity was picked
+    let dispatches = |n: usize| verify_with_vk(&[], &proof, &vec![0u8; n * BYTES_F])
+        != Err("unsupported public input count");
+    for n in 0..MAX_IC {
+        assert_eq!(dispatches(n), has_verifier(n), "arity {n}");
+    }
+
+    // Every circuit id the registry accepts has a verifier
+    for c in 0..=u8::MAX {
+        if let Some(n) = n_public_for(c) {
+            assert!(has_verifier(n), "circuit {c:#x} has no verifier for {n} publics");
+        }
+    }
+    // Batches past the cap are not circuits at all
+    assert_eq!(deposit_batch_size(circuit_id::deposit_batch(MAX_DEPOSIT_BATCH as u8 + 1)), None);
+    assert!(n_public_for(circuit_id::batch_insert(16)).is_some_and(has_verifier));
+}
+
+// ─── Join-split shapes ───
+
+use cipherpay_anchor::zk_verifier::solana_verifier::{
+    joinsplit_idx, joinsplit_shape, MAX_JOINSPLIT_INPUTS, MAX_JOINSPLIT_OUTPUTS,
+};
+
+#[test]
+fn test_joinsplit_shape_boundary_arities() {
+    use cipherpay_anchor::zk_verifier::solana_verifier::has_verifier;
+    let (max_n, max_m) = (MAX_JOINSPLIT_INPUTS, MAX_JOINSPLIT_OUTPUTS);
+    for (n, m) in [(1, 1), (1, max_m), (max_n, 1), (max_n, max_m)] {
+        let c = circuit_id::joinsplit(n as u8, m as u8);
+        assert_eq!(joinsplit_shape(c), Some((n, m)), "circuit {c:#x}");
+        let n_public = joinsplit_idx::n_public(n, m);
+        assert_eq!(n_public_for(c), Some(n_public));
+        assert!(has_verifier(n_public), "no verifier for ({n}, {m})");
+
+        // Every signal has exactly one index, and they fill 0..n_public
+        let mut idx: Vec<usize> = (0..m).map(|j| joinsplit_idx::out_commitment(n, m, j)).collect();
+        idx.extend((0..n).map(|i| joinsplit_idx::nullifier(n, m, i)));
+        idx.push(joinsplit_idx::merkle_root(n, m));
+        idx.extend((0..m).map(|j| joinsplit_idx::enc_note_hash(n, m, j)));
+        idx.extend([joinsplit_idx::fee(n, m), joinsplit_idx::relayer_lo(n, m), joinsplit_idx::relayer_hi(n, m)]);
+        idx.sort_unstable();
+        assert_eq!(idx, (0..n_public).collect::<Vec<_>>(), "({n}, {m})");
+    }
+
+    // One output past the cap (the id bits allow up to 4), and ids outside the range
+    assert_eq!(joinsplit_shape(circuit_id::joinsplit(1, max_m as u8 + 1)), None);
+    assert_eq!(joinsplit_shape(circuit_id::joinsplit(max_n as u8, 4)), None);
+    assert_eq!(n_public_for(circuit_id::joinsplit(1, max_m as u8 + 1)), None);
+    assert_eq!(joinsplit_shape(circuit_id::JOINSPLIT_BASE - 1), None);
+    assert_eq!(joinsplit_shape(circuit_id::JOINSPLIT_BASE + 0x10), None);
+}
+
+#[test]
+fn test_joinsplit_nullifier_accounts_layout() {
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::utils::joinsplit_nullifier_accounts;
+    let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    let owner = Pubkey::default();
+    let mut lamports = [0u64; 4];
+    let mut data = vec![vec![0u8; 0]; 4];
+    let infos: Vec<AccountInfo> = keys
+        .iter()
+        .zip(lamports.iter_mut())
+        .zip(data.iter_mut())
+        .map(|((k, l), d)| AccountInfo::new(k, false, true, l, &mut d[..], &owner, false, 0))
+        .collect();
+    let split = |set: u8, len: usize| {
+        joinsplit_nullifier_accounts(set, &infos[..len], 2).map(|v| {
+            v.iter().map(|(a, legacy)| (*a.key, legacy.map(|l| *l.key))).collect::<Vec<_>>()
+        })
+    };
+
+    // PDA mode: one record per input
+    let pda = ProgramConfig::NULLIFIER_SET_PDA;
+    assert_eq!(split(pda, 2).unwrap(), vec![(keys[0], None), (keys[1], None)]);
+    // Sharded: the N shards, then each input's legacy record in the same order
+    let sharded = ProgramConfig::NULLIFIER_SET_SHARDED;
+    assert_eq!(
+        split(sharded, 4).unwrap(),
+        vec![(keys[0], Some(keys[2])), (keys[1], Some(keys[3]))]
+    );
+    // Any other count is a layout for the wrong mode
+    for (set, len) in [(pda, 1), (pda, 4), (sharded, 2), (sharded, 3)] {
+        assert_eq!(split(set, len).unwrap_err(), CipherPayError::NullifierSetMismatch.into());
+    }
+}
+
+// ─── Token ids ───
+
+#[test]
+fn test_token_id_is_canonical_field_element() {
+    use cipherpay_anchor::utils::token_id_for;
+    let a = Pubkey::new_unique();
+    let b = Pubkey::new_unique();
+    assert_eq!(token_id_for(&a), token_id_for(&a));
+    assert_ne!(token_id_for(&a), token_id_for(&b));
+    // Top 3 bits cleared: below 2^253 < r, so no reduction is needed in-circuit
+    for m in [a, b, Pubkey::default(), Pubkey::new_from_array([0xff; 32])] {
+        assert!(token_id_for(&m)[31] < 0x20);
+    }
+}
+
+#[test]
+fn test_withdraw_destination_binding() {
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::utils::{assert_withdraw_destination, recipient_wallet_field, token_id_for};
+    let mint = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let ata = Pubkey::new_unique();
+    let (tid, wallet) = (token_id_for(&mint), recipient_wallet_field(&ata));
+    assert!(assert_withdraw_destination(&tid, &wallet, &mint, &ata).is_ok());
+
+    // Proof for another mint's pool
+    let other = token_id_for(&Pubkey::new_unique());
+    let err = assert_withdraw_destination(&other, &wallet, &mint, &ata).unwrap_err();
+    assert_eq!(err, CipherPayError::TokenIdMismatch.into());
+
+    // Proof paying another token account (or naming the owner wallet instead of its ATA)
+    for wrong in [recipient_wallet_field(&Pubkey::new_unique()), recipient_wallet_field(&owner), tid] {
+        let err = assert_withdraw_destination(&tid, &wrong, &mint, &ata).unwrap_err();
+        assert_eq!(err, CipherPayError::RecipientWalletMismatch.into());
+    }
+}
+
+// ─── Deposit funding transfers ───
+
+#[test]
+fn test_each_deposit_needs_its_own_transfer() {
+    #[allow(deprecated)] // re-exports of solana-instruction / solana-instructions-sysvar
+    use anchor_lang::solana_program::sysvar::instructions::{
+        self, construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction,
+    };
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::utils::assert_transfer_checked_in_same_tx;
+
+    let (src, mint, vault, authority) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (token_program, cipherpay) = (anchor_spl::token::ID, Pubkey::new_unique());
+    let meta = |pubkey| BorrowedAccountMeta { pubkey, is_signer: false, is_writable: false };
+    let mut checked = vec![12u8];
+    checked.extend_from_slice(&500u64.to_le_bytes());
+    checked.push(6);
+    let transfer = || BorrowedInstruction {
+        program_id: &token_program,
+        accounts: vec![meta(&src), meta(&mint), meta(&vault), meta(&authority)],
+        data: &checked,
+    };
+    let program = || BorrowedInstruction { program_id: &cipherpay, accounts: vec![], data: &[] };
+
+    // Sysvar holding `ixs`, executing the last one (the deposit)
+    let sysvar = |ixs: &[BorrowedInstruction]| {
+        let mut data = construct_instructions_data(ixs);
+        store_current_index(&mut data, (ixs.len() - 1) as u16);
+        data
+    };
+    let (sysvar_id, system) = (instructions::ID, Pubkey::default());
+    let (mut lamports, mut mint_lamports, mut mint_data) = (0u64, 0u64, [0u8; 0]);
+
+    // Batch of two 500-unit deposits against a single 500-unit transfer
+    let mut data = sysvar(&[transfer(), program()]);
+    let instr_ai = AccountInfo::new(&sysvar_id, false, false, &mut lamports, &mut data, &system, false, 0);
+    let mint_ai = AccountInfo::new(&mint, false, false, &mut mint_lamports, &mut mint_data, &token_program, false, 0);
+    let mut used = Vec::new();
+    assert_transfer_checked_in_same_tx(&instr_ai, &vault, 500, &mint_ai, &mut used).unwrap();
+    let err = assert_transfer_checked_in_same_tx(&instr_ai, &vault, 500, &mint_ai, &mut used).unwrap_err();
+    assert_eq!(err, CipherPayError::RequiredSplTransferMissing.into());
+    // ...nor can the wildcard of stub builds reuse it
+    assert!(assert_transfer_checked_in_same_tx(&instr_ai, &vault, 0, &mint_ai, &mut used).is_err());
+    drop(instr_ai);
+
+    // Two transfers fund the two deposits
+    let mut lamports = 0u64;
+    let mut data = sysvar(&[transfer(), transfer(), program()]);
+    let instr_ai = AccountInfo::new(&sysvar_id, false, false, &mut lamports, &mut data, &system, false, 0);
+    let mut used = Vec::new();
+    for _ in 0..2 {
+        assert_transfer_checked_in_same_tx(&instr_ai, &vault, 500, &mint_ai, &mut used).unwrap();
+    }
+    assert_eq!(used, vec![0, 1]);
+}
+
+/// Token-2022 mint carrying the transfer-fee extension (same fee in every epoch).
+fn fee_mint_data(basis_points: u16, maximum_fee: u64) -> Vec<u8> {
+    use anchor_spl::token_2022::spl_token_2022::extension::transfer_fee::{TransferFee, TransferFeeConfig};
+    use anchor_spl::token_2022::spl_token_2022::extension::{
+        BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    };
+    use anchor_spl::token_2022::spl_token_2022::state::Mint;
+    let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig]).unwrap();
+    let mut data = vec![0u8; len];
+    let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+    let fee = TransferFee {
+        epoch: 0.into(),
+        maximum_fee: maximum_fee.into(),
+        transfer_fee_basis_points: basis_points.into(),
+    };
+    let cfg = state.init_extension::<TransferFeeConfig>(true).unwrap();
+    cfg.older_transfer_fee = fee;
+    cfg.newer_transfer_fee = fee;
+    state.base = Mint { decimals: 6, is_initialized: true, ..Default::default() };
+    state.pack_base();
+    state.init_account_type().unwrap();
+    data
+}
+
+/// `transfer_fee_for` reads the epoch from the Clock sysvar; give host tests one.
+fn install_clock_stub() {
+    use anchor_lang::solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+    struct ClockStub;
+    impl SyscallStubs for ClockStub {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Clock) = Clock::default() };
+            0
+        }
+    }
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        set_syscall_stubs(Box::new(ClockStub));
+    });
+}
+
+#[test]
+fn test_transfer_fee_for_fee_bearing_mint() {
+    use cipherpay_anchor::utils::transfer_fee_for;
+    install_clock_stub();
+    let mint = Pubkey::new_unique();
+    let (token_2022, token) = (anchor_spl::token_2022::ID, anchor_spl::token::ID);
+    let mut lamports = 0u64;
+    let mut data = fee_mint_data(100, 50); // 1%, at most 50
+    let mint_ai = AccountInfo::new(&mint, false, false, &mut lamports, &mut data, &token_2022, false, 0);
+
+    assert_eq!(transfer_fee_for(&mint_ai, 0).unwrap(), 0);
+    assert_eq!(transfer_fee_for(&mint_ai, 1_000).unwrap(), 10);
+    // Rounded up, like Token-2022 withholds it
+    assert_eq!(transfer_fee_for(&mint_ai, 1_001).unwrap(), 11);
+    assert_eq!(transfer_fee_for(&mint_ai, 1).unwrap(), 1);
+    // Capped at the maximum fee
+    assert_eq!(transfer_fee_for(&mint_ai, 4_999).unwrap(), 50);
+    assert_eq!(transfer_fee_for(&mint_ai, 1_000_000).unwrap(), 50);
+    drop(mint_ai);
+
+    // The extension only counts on Token-2022 mints
+    let mut data = fee_mint_data(100, 50);
+    let classic_ai = AccountInfo::new(&mint, false, false, &mut lamports, &mut data, &token, false, 0);
+    assert_eq!(transfer_fee_for(&classic_ai, 1_000).unwrap(), 0);
+    drop(classic_ai);
+    let mut data = fee_mint_data(0, 50);
+    let free_ai = AccountInfo::new(&mint, false, false, &mut lamports, &mut data, &token_2022, false, 0);
+    assert_eq!(transfer_fee_for(&free_ai, 1_000).unwrap(), 0);
+}
+
+#[test]
+fn test_transfer_with_fee_must_land_expected_amount() {
+    #[allow(deprecated)] // re-exports of solana-instruction / solana-instructions-sysvar
+    use anchor_lang::solana_program::sysvar::instructions::{
+        self, construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction,
+    };
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::utils::assert_transfer_checked_in_same_tx;
+    install_clock_stub();
+
+    let (src, mint, vault, authority) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let token_2022 = anchor_spl::token_2022::ID;
+    let meta = |pubkey| BorrowedAccountMeta { pubkey, is_signer: false, is_writable: false };
+    // TransferCheckedWithFee (tag 26/1): amount, decimals, fee
+    let with_fee = |amount: u64, fee: u64| {
+        let mut d = vec![26u8, 1];
+        d.extend_from_slice(&amount.to_le_bytes());
+        d.push(6);
+        d.extend_from_slice(&fee.to_le_bytes());
+        d
+    };
+    let mut checked = vec![12u8];
+    checked.extend_from_slice(&1_000u64.to_le_bytes());
+    checked.push(6);
+
+    let (mut lamports, mut mint_lamports) = (0u64, 0u64);
+    let mut mint_data = fee_mint_data(100, 50);
+    let mint_ai = AccountInfo::new(&mint, false, false, &mut mint_lamports, &mut mint_data, &token_2022, false, 0);
+    let (sysvar_id, system) = (instructions::ID, Pubkey::default());
+    let mut check = |data: &[u8], expected: u64| {
+        let ix = BorrowedInstruction {
+            program_id: &token_2022,
+            accounts: vec![meta(&src), meta(&mint), meta(&vault), meta(&authority)],
+            data,
+        };
+        let mut sysvar = construct_instructions_data(&[ix]);
+        store_current_index(&mut sysvar, 0);
+        let instr_ai = AccountInfo::new(&sysvar_id, false, false, &mut lamports, &mut sysvar, &system, false, 0);
+        assert_transfer_checked_in_same_tx(&instr_ai, &vault, expected, &mint_ai, &mut Vec::new())
+    };
+    let missing = |r: Result<()>| r.unwrap_err() == CipherPayError::RequiredSplTransferMissing.into();
+
+    // The explicit fee is what the vault doesn't receive
+    assert!(check(&with_fee(1_000, 10), 990).is_ok());
+    assert!(missing(check(&with_fee(1_000, 10), 1_000)));
+    // A deposit expecting another fee (here: half of it) is not funded by it
+    assert!(missing(check(&with_fee(1_000, 10), 995)));
+    // Capped fee on a large transfer
+    assert!(check(&with_fee(100_000, 50), 99_950).is_ok());
+    // Plain TransferChecked on a fee-bearing mint: the fee comes from the mint
+    assert!(check(&checked, 990).is_ok());
+    assert!(missing(check(&checked, 1_000)));
+    // Malformed with-fee data (other sub-instruction, truncated) is not a transfer
+    let mut other = with_fee(1_000, 10);
+    other[1] = 2;
+    assert!(missing(check(&other, 990)));
+    assert!(missing(check(&with_fee(1_000, 10)[..18], 990)));
+}
+
+// ─── Two-phase deposits ───
+
+#[test]
+fn test_pending_deposit_refund_timeout() {
+    use cipherpay_anchor::constants::PENDING_DEPOSIT_TIMEOUT_SLOTS;
+    use cipherpay_anchor::state::PendingDeposit;
+    let mut p = PendingDeposit {
+        depositor: Pubkey::default(),
+        mint: Pubkey::default(),
+        amount: 5,
+        created_slot: 100,
+        bump: 255,
+    };
+    assert_eq!(p.refundable_at(), 100 + PENDING_DEPOSIT_TIMEOUT_SLOTS);
+    p.created_slot = u64::MAX - 1;
+    assert_eq!(p.refundable_at(), u64::MAX);
+}
+
+/// Anchor's generated entry has a single lifetime for every account; program-test
+/// hands out shorter-lived slices.
+fn cipherpay_processor(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    cipherpay_anchor::entry(program_id, accounts, data)
+}
+
+/// Rent-exempt account holding `data`.
+fn rent_exempt_account(data: Vec<u8>, owner: Pubkey) -> solana_sdk::account::Account {
+    solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// Serialized Anchor account (discriminator + data) owned by the program.
+fn program_account<T: AccountSerialize>(state: &T, space: usize) -> solana_sdk::account::Account {
+    let mut data = Vec::with_capacity(space);
+    state.try_serialize(&mut data).unwrap();
+    data.resize(space, 0);
+    rent_exempt_account(data, cipherpay_anchor::ID)
+}
+
+/// Root cache account of `capacity` roots holding `genesis_root` (cached at slot 0).
+fn root_cache_account(capacity: u32, mint: Pubkey, genesis_root: [u8; 32]) -> solana_sdk::account::Account {
+    let mut data = vec![0u8; MerkleRootCache::space(capacity)];
+    data[..8].copy_from_slice(MerkleRootCache::DISCRIMINATOR);
+    RootRing::init(&mut data[8..], capacity, 0, mint).unwrap().insert(genesis_root, 0);
+    rent_exempt_account(data, cipherpay_anchor::ID)
+}
+
+#[tokio::test]
+async fn test_close_deposit_marker_after_root_ages_out() {
+    use anchor_lang::{InstructionData, ToAccountMetas};
+    use cipherpay_anchor::constants::{CONFIG_SEED, DEPOSIT_MARKER_SEED, PROCESSED_DEPOSITS_SEED, ROOT_CACHE_SEED};
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::state::{DepositMarker, NullifierSet, NullifierShard, ProgramConfig};
+    use solana_program_test::{processor, ProgramTest};
+    use solana_sdk::instruction::{Instruction, InstructionError};
+    use solana_sdk::signature::Signer as _;
+    use solana_sdk::transaction::{Transaction, TransactionError};
+
+    let program_id = cipherpay_anchor::ID;
+    let mut pt = ProgramTest::new("cipherpay_anchor", program_id, processor!(cipherpay_processor));
+    let (mint, deposit_hash, deposit_root) = (Pubkey::new_unique(), leaf(7), root(1));
+
+    let (config_pda, config_bump) = Pubkey::find_program_address(&[CONFIG_SEED], &program_id);
+    let mut config = program_config();
+    config.bump = config_bump;
+    config.root_max_age_slots = 10;
+    pt.add_account(config_pda, program_account(&config, 8 + ProgramConfig::SIZE));
+
+    // Deposit root cached at slot 0: live until slot 10
+    let (cache_pda, _) = Pubkey::find_program_address(&[ROOT_CACHE_SEED, mint.as_ref(), &0u32.to_le_bytes()], &program_id);
+    pt.add_account(cache_pda, root_cache_account(8, mint, deposit_root));
+
+    let (marker_pda, marker_bump) = Pubkey::find_program_address(&[DEPOSIT_MARKER_SEED, deposit_hash.as_ref()], &program_id);
+    let (shard_pda, _) = Pubkey::find_program_address(
+        &[PROCESSED_DEPOSITS_SEED, &[NullifierShard::prefix_of(&deposit_hash)]],
+        &program_id,
+    );
+
+    let mut ctx = pt.start_with_context().await;
+    let payer = ctx.payer.insecure_clone();
+    let marker = DepositMarker {
+        processed: true,
+        bump: marker_bump,
+        payer: payer.pubkey(),
+        created_slot: 0,
+        mint,
+        tree_id: 0,
+        merkle_root: deposit_root,
+        leaf_index: 0,
+        root_pending: false,
+    };
+    let marker_account = program_account(&marker, DepositMarker::SPACE);
+    let marker_rent = marker_account.lamports;
+    ctx.set_account(&marker_pda, &marker_account.into());
+
+    let close_ix = Instruction {
+        program_id,
+        accounts: cipherpay_anchor::accounts::CloseDepositMarker {
+            payer: payer.pubkey(),
+            config: config_pda,
+            deposit_marker: marker_pda,
+            root_cache: cache_pda,
+            processed_deposits: shard_pda,
+            system_program: anchor_lang::system_program::ID,
+        }
+        .to_account_metas(None),
+        data: cipherpay_anchor::instruction::CloseDepositMarker { deposit_hash }.data(),
+    };
+
+    // Too early: the root still proves the deposit's note
+    let mut clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+    clock.slot = 5;
+    ctx.set_sysvar(&clock);
+    let blockhash = ctx.banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(std::slice::from_ref(&close_ix), Some(&payer.pubkey()), &[&payer], blockhash);
+    let err = ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap();
+    let code = u32::from(CipherPayError::DepositRootStillLive);
+    assert_eq!(err, TransactionError::InstructionError(0, InstructionError::Custom(code)));
+    assert!(ctx.banks_client.get_account(marker_pda).await.unwrap().is_some());
+
+    // Aged out: the marker's rent goes back to its payer, minus the shard's rent and the fee
+    clock.slot = 50;
+    ctx.set_sysvar(&clock);
+    let before = ctx.banks_client.get_balance(payer.pubkey()).await.unwrap();
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(&[close_ix], Some(&payer.pubkey()), &[&payer], blockhash);
+    let fee = ctx.banks_client.get_fee_for_message(tx.message.clone()).await.unwrap().unwrap();
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert!(ctx.banks_client.get_account(marker_pda).await.unwrap().is_none());
+    let shard = ctx.banks_client.get_account(shard_pda).await.unwrap().unwrap();
+    assert!(NullifierSet::new(&shard.data[8..]).unwrap().contains(&deposit_hash));
+    let after = ctx.banks_client.get_balance(payer.pubkey()).await.unwrap();
+    assert_eq!(after, before + marker_rent - fee - shard.lamports);
+}
+
+// ─── Pool limits ───
+
+fn pool_limits(min_deposit: u64, max_deposit: u64, max_withdraw: u64, cap: u64) -> cipherpay_anchor::state::PoolConfig {
+    cipherpay_anchor::state::PoolConfig {
+        mint: Pubkey::default(),
+        min_deposit,
+        max_deposit,
+        max_withdraw,
+        epoch_outflow_cap: cap,
+        outflow_epoch: 0,
+        epoch_outflow: 0,
+        large_withdraw_threshold: 0,
+        withdraw_delay_slots: 0,
+        guardian: Pubkey::default(),
+        bump: 255,
+        _reserved: [0u8; 16],
+    }
+}
+
+#[test]
+fn test_pool_deposit_limits() {
+    use cipherpay_anchor::error::CipherPayError;
+    let l = pool_limits(10, 100, 0, 0);
+    assert!(l.check_deposit(10).is_ok());
+    assert!(l.check_deposit(100).is_ok());
+    assert_eq!(l.check_deposit(9).unwrap_err(), CipherPayError::DepositBelowMinimum.into());
+    assert_eq!(l.check_deposit(101).unwrap_err(), CipherPayError::DepositAboveMaximum.into());
+    // 0 = no maximum
+    assert!(pool_limits(0, 0, 0, 0).check_deposit(u64::MAX).is_ok());
+}
+
+#[test]
+fn test_pool_withdraw_epoch_cap() {
+    use cipherpay_anchor::error::CipherPayError;
+    let mut l = pool_limits(0, 0, 40, 100);
+    assert_eq!(l.record_withdraw(7, 41).unwrap_err(), CipherPayError::InvalidWithdrawAmount.into());
+    assert!(!l.record_withdraw(7, 40).unwrap());
+    // 100 - 80 = 20 left: a max-size withdrawal no longer fits → cap reached (once)
+    assert!(l.record_withdraw(7, 40).unwrap());
+    assert!(!l.record_withdraw(7, 15).unwrap());
+    assert_eq!(l.epoch_outflow, 95);
+    assert_eq!(l.record_withdraw(7, 6).unwrap_err(), CipherPayError::EpochOutflowCapExceeded.into());
+    // Next epoch starts a fresh window
+    assert!(!l.record_withdraw(8, 40).unwrap());
+    assert_eq!((l.outflow_epoch, l.epoch_outflow), (8, 40));
+
+    // Without max_withdraw the cap is reached when it is used up exactly
+    let mut l = pool_limits(0, 0, 0, 10);
+    assert!(!l.record_withdraw(1, 9).unwrap());
+    assert!(l.record_withdraw(1, 1).unwrap());
+    assert!(l.record_withdraw(1, 1).is_err());
+}
+
+#[test]
+fn test_large_withdraw_timelock_and_release() {
+    let mut l = pool_limits(0, 0, 0, 100);
+    assert!(!l.is_timelocked(u64::MAX)); // threshold 0 = off
+    l.large_withdraw_threshold = 50;
+    assert!(!l.is_timelocked(50));
+    assert!(l.is_timelocked(51));
+
+    // A cancelled withdrawal gives its outflow back, but only within its epoch
+    l.record_withdraw(3, 60).unwrap();
+    l.release_withdraw(3, 60);
+    assert_eq!(l.epoch_outflow, 0);
+    l.record_withdraw(4, 30).unwrap();
+    l.release_withdraw(3, 30);
+    assert_eq!(l.epoch_outflow, 30);
+}
+
+#[test]
+fn test_cancel_refunds_only_the_proven_commitment() {
+    use anchor_lang::InstructionData;
+    use cipherpay_anchor::state::PendingWithdrawal;
+    use cipherpay_anchor::zk_verifier::solana_verifier::{withdraw_idx, withdraw_layout};
+
+    // Only the refundable layouts carry a refund note, always as the last signal
+    assert_eq!(withdraw_layout(10), Some((circuit_id::WITHDRAW, false, false)));
+    assert_eq!(withdraw_layout(12), Some((circuit_id::WITHDRAW_CHANGE, true, false)));
+    for (n, change) in [(11, false), (13, true)] {
+        let (c, with_change, refundable) = withdraw_layout(n).unwrap();
+        assert!(refundable && with_change == change);
+        assert_eq!(n_public_for(c), Some(n));
+        assert_eq!(withdraw_idx::refund_commitment(change), n - 1);
+    }
+    assert_eq!(withdraw_layout(9), None);
+
+    // The refund note is stored with the pending withdrawal at queue time...
+    let pending = PendingWithdrawal {
+        mint: Pubkey::new_unique(),
+        recipient_token_account: Pubkey::new_unique(),
+        amount: 90,
+        relayer_token_account: Pubkey::default(),
+        fee: 10,
+        payer: Pubkey::new_unique(),
+        epoch: 1,
+        claimable_at: 100,
+        refund_commitment: leaf(7),
+        bump: 255,
+    };
+    assert_eq!(pending.try_to_vec().unwrap().len(), PendingWithdrawal::SIZE);
+
+    // ...and the guardian's cancel carries nothing but the nullifier to redirect it with
+    let ix = cipherpay_anchor::instruction::CancelPendingWithdrawal { nullifier: [1u8; 32] };
+    assert_eq!(ix.data().len(), 8 + 32);
+}
+
+#[tokio::test]
+async fn test_cancel_appends_exactly_the_refund_commitment() {
+    use anchor_lang::{InstructionData, ToAccountMetas};
+    use anchor_spl::token::spl_token::{self, solana_program::program_pack::Pack};
+    use cipherpay_anchor::constants::{CONFIG_SEED, PENDING_WITHDRAWAL_SEED, POOL_CONFIG_SEED, ROOT_CACHE_SEED, TREE_SEED};
+    use cipherpay_anchor::state::{PendingWithdrawal, PoolConfig};
+    use solana_program_test::{processor, ProgramTest};
+    use solana_sdk::instruction::Instruction;
+    use solana_sdk::signature::{Keypair, Signer as _};
+    use solana_sdk::transaction::Transaction;
+
+    let program_id = cipherpay_anchor::ID;
+    let mut pt = ProgramTest::new("cipherpay_anchor", program_id, processor!(cipherpay_processor));
+    let (mint, guardian, payer, nullifier) = (Pubkey::new_unique(), Keypair::new(), Pubkey::new_unique(), [3u8; 32]);
+    let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &program_id);
+
+    let mut mint_data = vec![0u8; spl_token::state::Mint::LEN];
+    let mint_state = spl_token::state::Mint { decimals: 6, is_initialized: true, ..Default::default() };
+    spl_token::state::Mint::pack(mint_state, &mut mint_data).unwrap();
+    pt.add_account(mint, rent_exempt_account(mint_data, spl_token::ID));
+
+    let (config_pda, config_bump) = pda(&[CONFIG_SEED]);
+    let mut config = program_config();
+    config.bump = config_bump;
+    pt.add_account(config_pda, program_account(&config, 8 + ProgramConfig::SIZE));
+
+    // A time-locked withdrawal of 90 + 10 fee counted against epoch 1
+    let (pool_pda, pool_bump) = pda(&[POOL_CONFIG_SEED, mint.as_ref()]);
+    let mut pool = pool_limits(0, 0, 0, 1_000);
+    (pool.mint, pool.guardian, pool.bump) = (mint, guardian.pubkey(), pool_bump);
+    (pool.outflow_epoch, pool.epoch_outflow) = (1, 150);
+    pt.add_account(pool_pda, program_account(&pool, PoolConfig::SPACE));
+
+    let (pending_pda, pending_bump) = pda(&[PENDING_WITHDRAWAL_SEED, nullifier.as_ref()]);
+    let pending = PendingWithdrawal {
+        mint,
+        recipient_token_account: Pubkey::new_unique(),
+        amount: 90,
+        relayer_token_account: Pubkey::new_unique(),
+        fee: 10,
+        payer,
+        epoch: 1,
+        claimable_at: 1_000,
+        refund_commitment: leaf(7),
+        bump: pending_bump,
+    };
+    let pending_account = program_account(&pending, PendingWithdrawal::SPACE);
+    let pending_rent = pending_account.lamports;
+    pt.add_account(pending_pda, pending_account);
+
+    // Tree with two notes already in it
+    let mut tree = empty_tree(4);
+    tree.mint = mint;
+    append_leaf(&mut tree, &leaf(1)).unwrap();
+    append_leaf(&mut tree, &leaf(2)).unwrap();
+    let (tree_pda, _) = pda(&[TREE_SEED, mint.as_ref(), &0u32.to_le_bytes()]);
+    pt.add_account(tree_pda, program_account(&tree, TreeState::space(4)));
+    let (cache_pda, _) = pda(&[ROOT_CACHE_SEED, mint.as_ref(), &0u32.to_le_bytes()]);
+    pt.add_account(cache_pda, root_cache_account(8, mint, tree.current_root));
+
+    let mut ctx = pt.start_with_context().await;
+    let ix = Instruction {
+        program_id,
+        accounts: cipherpay_anchor::accounts::CancelPendingWithdrawal {
+            guardian: guardian.pubkey(),
+            config: config_pda,
+            pool_config: pool_pda,
+            pending_withdrawal: pending_pda,
+            payer,
+            tree: tree_pda,
+            root_cache: cache_pda,
+            commitment_queue: None,
+            token_mint: mint,
+        }
+        .to_account_metas(None),
+        data: cipherpay_anchor::instruction::CancelPendingWithdrawal { nullifier }.data(),
+    };
+    let blockhash = ctx.banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&ctx.payer.pubkey()), &[&ctx.payer, &guardian], blockhash);
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // The tree grew by exactly one leaf, the refund note, and only its root was cached
+    let mut expected = tree.clone();
+    let refund_root = append_leaf(&mut expected, &leaf(7)).unwrap();
+    let account = ctx.banks_client.get_account(tree_pda).await.unwrap().unwrap();
+    let after = TreeState::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(after.next_index, 3);
+    assert_eq!(after.current_root, refund_root);
+    assert_eq!(after.filled_subtrees, expected.filled_subtrees);
+
+    let account = ctx.banks_client.get_account(cache_pda).await.unwrap().unwrap();
+    let ring = RootRing::new(&account.data[8..]).unwrap();
+    assert_eq!(ring.latest(), Some(refund_root));
+    assert!(ring.contains(&tree.current_root));
+    assert_eq!(ring.header().count, 2);
+
+    // The outflow is released, the pending withdrawal closed and its rent back with its payer
+    let account = ctx.banks_client.get_account(pool_pda).await.unwrap().unwrap();
+    let pool = PoolConfig::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(pool.epoch_outflow, 50);
+    assert!(ctx.banks_client.get_account(pending_pda).await.unwrap().is_none());
+    assert_eq!(ctx.banks_client.get_balance(payer).await.unwrap(), pending_rent);
+}
+
+// ─── Program config ───
+
+use cipherpay_anchor::state::{ProgramConfig, VkMinVersion};
+
+fn program_config() -> ProgramConfig {
+    ProgramConfig {
+        admin: Pubkey::new_unique(),
+        pending_admin: None,
+        bump: 255,
+        pause_flags: 0,
+        root_max_age_slots: 0,
+        root_min_age_slots: 0,
+        nullifier_set: ProgramConfig::NULLIFIER_SET_PDA,
+        vk_min_versions: [VkMinVersion::default(); ProgramConfig::MAX_VK_MIN_VERSIONS],
+        _reserved: [0u8; 14],
+    }
+}
+
+/// Program-test context with `config` at its PDA (bump filled in).
+async fn start_with_config(mut config: ProgramConfig) -> (solana_program_test::ProgramTestContext, Pubkey) {
+    use cipherpay_anchor::constants::CONFIG_SEED;
+    use solana_program_test::{processor, ProgramTest};
+    let mut pt = ProgramTest::new("cipherpay_anchor", cipherpay_anchor::ID, processor!(cipherpay_processor));
+    let (config_pda, bump) = Pubkey::find_program_address(&[CONFIG_SEED], &cipherpay_anchor::ID);
+    config.bump = bump;
+    pt.add_account(config_pda, program_account(&config, ProgramConfig::SPACE));
+    (pt.start_with_context().await, config_pda)
+}
+
+/// Send `data` with `accounts` to the program, fee paid by the context's payer.
+async fn send_ix(
+    ctx: &mut solana_program_test::ProgramTestContext,
+    accounts: impl anchor_lang::ToAccountMetas,
+    data: impl anchor_lang::InstructionData,
+    signers: &[&solana_sdk::signature::Keypair],
+) -> std::result::Result<(), solana_sdk::transaction::TransactionError> {
+    use solana_sdk::signature::Signer as _;
+    let ix = solana_sdk::instruction::Instruction {
+        program_id: cipherpay_anchor::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    };
+    // A fresh blockhash each time, so repeating an instruction isn't a duplicate transaction
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let payer = ctx.payer.insecure_clone();
+    let mut all: Vec<&solana_sdk::signature::Keypair> = vec![&payer];
+    all.extend_from_slice(signers);
+    let tx = solana_sdk::transaction::Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &all, blockhash);
+    ctx.banks_client.process_transaction(tx).await.map_err(|e| e.unwrap())
+}
+
+async fn fetch_config(ctx: &mut solana_program_test::ProgramTestContext, config_pda: Pubkey) -> ProgramConfig {
+    let account = ctx.banks_client.get_account(config_pda).await.unwrap().unwrap();
+    ProgramConfig::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+/// Transaction error of a `CipherPayError` raised by the first instruction.
+fn program_error(e: cipherpay_anchor::error::CipherPayError) -> solana_sdk::transaction::TransactionError {
+    use solana_sdk::instruction::InstructionError;
+    solana_sdk::transaction::TransactionError::InstructionError(0, InstructionError::Custom(e.into()))
+}
+
+#[test]
+fn test_program_config_size() {
+    let mut cfg = program_config();
+    cfg.pending_admin = Some(Pubkey::new_unique());
+    assert_eq!(cfg.try_to_vec().unwrap().len(), ProgramConfig::SIZE);
+}
+
+#[test]
+fn test_min_vk_version_table() {
+    let mut cfg = program_config();
+    assert_eq!(cfg.min_vk_version(circuit_id::DEPOSIT), 0);
+
+    assert_eq!(cfg.set_min_vk_version(circuit_id::DEPOSIT, 2).unwrap(), 0);
+    assert_eq!(cfg.set_min_vk_version(circuit_id::DEPOSIT, 3).unwrap(), 2);
+    assert_eq!(cfg.min_vk_version(circuit_id::DEPOSIT), 3);
+    // Other circuits are unaffected
+    assert_eq!(cfg.min_vk_version(circuit_id::TRANSFER), 0);
+
+    // Clearing frees the slot
+    assert_eq!(cfg.set_min_vk_version(circuit_id::DEPOSIT, 0).unwrap(), 3);
+    assert_eq!(cfg.min_vk_version(circuit_id::DEPOSIT), 0);
+    assert_eq!(cfg.set_min_vk_version(circuit_id::TRANSFER, 0).unwrap(), 0);
+
+    // The table holds MAX_VK_MIN_VERSIONS circuits
+    for i in 0..ProgramConfig::MAX_VK_MIN_VERSIONS as u8 {
+        cfg.set_min_vk_version(circuit_id::deposit_batch(1) + i, 1).unwrap();
+    }
+    assert!(cfg.set_min_vk_version(circuit_id::WITHDRAW, 1).is_err());
+    cfg.set_min_vk_version(circuit_id::deposit_batch(1), 0).unwrap();
+    assert!(cfg.set_min_vk_version(circuit_id::WITHDRAW, 1).is_ok());
+}
+
+#[tokio::test]
+async fn test_admin_rotation_takes_two_steps() {
+    use cipherpay_anchor::accounts::{AcceptAdmin, ProposeAdmin};
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::instruction;
+    use solana_sdk::signature::{Keypair, Signer as _};
+
+    let (admin, next, stranger) = (Keypair::new(), Keypair::new(), Keypair::new());
+    let mut config = program_config();
+    config.admin = admin.pubkey();
+    let (mut ctx, config_pda) = start_with_config(config).await;
+    let propose = |admin: &Keypair| ProposeAdmin { config: config_pda, admin: admin.pubkey() };
+    let accept = |new_admin: &Keypair| AcceptAdmin { config: config_pda, new_admin: new_admin.pubkey() };
+    let unauthorized = program_error(CipherPayError::Unauthorized);
+
+    // Only the admin nominates, and nothing to accept before it does
+    let r = send_ix(&mut ctx, propose(&stranger), instruction::ProposeAdmin { new_admin: stranger.pubkey() }, &[&stranger]).await;
+    assert_eq!(r.unwrap_err(), unauthorized);
+    assert_eq!(send_ix(&mut ctx, accept(&next), instruction::AcceptAdmin {}, &[&next]).await.unwrap_err(), unauthorized);
+
+    send_ix(&mut ctx, propose(&admin), instruction::ProposeAdmin { new_admin: next.pubkey() }, &[&admin]).await.unwrap();
+    let cfg = fetch_config(&mut ctx, config_pda).await;
+    assert_eq!((cfg.admin, cfg.pending_admin), (admin.pubkey(), Some(next.pubkey())));
+
+    // Proposing doesn't hand anything over: only the nominee can accept
+    for other in [&stranger, &admin] {
+        let r = send_ix(&mut ctx, accept(other), instruction::AcceptAdmin {}, &[other]).await;
+        assert_eq!(r.unwrap_err(), unauthorized);
+    }
+    send_ix(&mut ctx, accept(&next), instruction::AcceptAdmin {}, &[&next]).await.unwrap();
+    let cfg = fetch_config(&mut ctx, config_pda).await;
+    assert_eq!((cfg.admin, cfg.pending_admin), (next.pubkey(), None));
+
+    // The old admin is out, and the nomination can't be accepted twice
+    let r = send_ix(&mut ctx, propose(&admin), instruction::ProposeAdmin { new_admin: admin.pubkey() }, &[&admin]).await;
+    assert_eq!(r.unwrap_err(), unauthorized);
+    assert_eq!(send_ix(&mut ctx, accept(&next), instruction::AcceptAdmin {}, &[&next]).await.unwrap_err(), unauthorized);
+
+    // A nomination can be replaced before it is accepted
+    send_ix(&mut ctx, propose(&next), instruction::ProposeAdmin { new_admin: stranger.pubkey() }, &[&next]).await.unwrap();
+    send_ix(&mut ctx, propose(&next), instruction::ProposeAdmin { new_admin: admin.pubkey() }, &[&next]).await.unwrap();
+    let r = send_ix(&mut ctx, accept(&stranger), instruction::AcceptAdmin {}, &[&stranger]).await;
+    assert_eq!(r.unwrap_err(), unauthorized);
+    send_ix(&mut ctx, accept(&admin), instruction::AcceptAdmin {}, &[&admin]).await.unwrap();
+    assert_eq!(fetch_config(&mut ctx, config_pda).await.admin, admin.pubkey());
+}
+
+#[tokio::test]
+async fn test_pause_flags_are_per_operation() {
+    use cipherpay_anchor::accounts::SetPauseState;
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::instruction;
+    use solana_sdk::signature::{Keypair, Signer as _};
+
+    let (admin, stranger) = (Keypair::new(), Keypair::new());
+    let mut config = program_config();
+    config.admin = admin.pubkey();
+    let (mut ctx, config_pda) = start_with_config(config).await;
+    let pause = |deposit_paused, transfer_paused, withdraw_paused| instruction::SetPauseState {
+        deposit_paused,
+        transfer_paused,
+        withdraw_paused,
+    };
+
+    let r = send_ix(&mut ctx, SetPauseState { config: config_pda, admin: stranger.pubkey() }, pause(true, true, true), &[&stranger]).await;
+    assert_eq!(r.unwrap_err(), program_error(CipherPayError::Unauthorized));
+    assert_eq!(fetch_config(&mut ctx, config_pda).await.pause_flags, 0);
+
+    let (d, t, w) = (ProgramConfig::PAUSE_DEPOSIT, ProgramConfig::PAUSE_TRANSFER, ProgramConfig::PAUSE_WITHDRAW);
+    assert_eq!(d | t | w, 0b111); // distinct bits
+    for (deposit, transfer, withdraw) in [(true, false, false), (false, true, false), (false, false, true), (true, false, true)] {
+        let accounts = SetPauseState { config: config_pda, admin: admin.pubkey() };
+        send_ix(&mut ctx, accounts, pause(deposit, transfer, withdraw), &[&admin]).await.unwrap();
+        let cfg = fetch_config(&mut ctx, config_pda).await;
+        // Each flag stops its own operation only; the state is replaced, not merged
+        assert_eq!(cfg.is_paused(d), deposit);
+        assert_eq!(cfg.is_paused(t), transfer);
+        assert_eq!(cfg.is_paused(w), withdraw);
+        // Batch inserts add deposit and transfer notes: either flag stops them
+        assert_eq!(cfg.is_paused(d | t), deposit || transfer);
+    }
+
+    send_ix(&mut ctx, SetPauseState { config: config_pda, admin: admin.pubkey() }, pause(false, false, false), &[&admin]).await.unwrap();
+    let cfg = fetch_config(&mut ctx, config_pda).await;
+    assert_eq!(cfg.pause_flags, 0);
+    assert!(!cfg.is_paused(d | t | w));
+}
+
+// ─── mock-verifier builds ───
+
+#[cfg(all(feature = "mock-verifier", not(feature = "real-crypto")))]
+#[test]
+fn test_mock_verifier_skips_pairing_but_checks_shape() {
+    use cipherpay_anchor::utils::verify_circuit_proof;
+    let cfg = program_config();
+    let dep = circuit_id::DEPOSIT;
+    let n = DEPOSIT_N_PUBLIC;
+    assert!(verify_circuit_proof(&cfg, dep, None, &[7u8; 256], &vec![1u8; n * 32]).is_ok());
+    assert!(verify_circuit_proof(&cfg, dep, None, &[7u8; 255], &vec![1u8; n * 32]).is_err());
+    assert!(verify_circuit_proof(&cfg, dep, None, &[7u8; 256], &vec![1u8; (n + 1) * 32]).is_err());
+    assert!(verify_circuit_proof(&cfg, 0x3F, None, &[7u8; 256], &vec![1u8; n * 32]).is_err());
+}
+
+#[cfg(all(feature = "mock-verifier", not(feature = "real-crypto")))]
+#[test]
+fn test_min_vk_version_retires_old_keys() {
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::state::VerifyingKeyAccount;
+    use cipherpay_anchor::utils::verify_circuit_proof;
+    let dep = circuit_id::DEPOSIT;
+    let proof = [7u8; 256];
+    let public = vec![1u8; DEPOSIT_N_PUBLIC * 32];
+    let vk = |version: u16| VerifyingKeyAccount {
+        circuit: dep,
+        version,
+        finalized: true,
+        vk_hash: [0u8; 32],
+        bump: 255,
+        data: vec![],
+    };
+    let retired = |r: Result<()>| r.unwrap_err() == CipherPayError::VerifyingKeyRetired.into();
+
+    let mut cfg = program_config();
+    cfg.set_min_vk_version(dep, 2).unwrap();
+    // The embedded VK (version 0) and older registry versions are refused...
+    assert!(retired(verify_circuit_proof(&cfg, dep, None, &proof, &public)));
+    assert!(retired(verify_circuit_proof(&cfg, dep, Some(&vk(1)), &proof, &public)));
+    // ...the minimum and newer ones still verify
+    assert!(verify_circuit_proof(&cfg, dep, Some(&vk(2)), &proof, &public).is_ok());
+    assert!(verify_circuit_proof(&cfg, dep, Some(&vk(3)), &proof, &public).is_ok());
+    // Other circuits keep their embedded VK
+    let transfer_public = vec![1u8; n_public_for(circuit_id::TRANSFER).unwrap() * 32];
+    assert!(verify_circuit_proof(&cfg, circuit_id::TRANSFER, None, &proof, &transfer_public).is_ok());
+
+    // Clearing the minimum re-allows the embedded VK
+    cfg.set_min_vk_version(dep, 0).unwrap();
+    assert!(verify_circuit_proof(&cfg, dep, None, &proof, &public).is_ok());
+}
+
+#[cfg(all(feature = "mock-verifier", not(feature = "real-crypto")))]
+#[test]
+fn test_joinsplit_rejects_mismatched_public_count() {
+    use cipherpay_anchor::error::CipherPayError;
+    use cipherpay_anchor::state::VerifyingKeyAccount;
+    use cipherpay_anchor::utils::verify_circuit_proof;
+    let cfg = program_config();
+    let (n, m) = (MAX_JOINSPLIT_INPUTS, MAX_JOINSPLIT_OUTPUTS);
+    let c = circuit_id::joinsplit(n as u8, m as u8);
+    let vk = |circuit: u8| VerifyingKeyAccount {
+        circuit,
+        version: 1,
+        finalized: true,
+        vk_hash: [0u8; 32],
+        bump: 255,
+        data: vec![],
+    };
+    let proof = [7u8; 256];
+    let n_public = joinsplit_idx::n_public(n, m);
+    let verify = |circuit: u8, count: usize| {
+        verify_circuit_proof(&cfg, circuit, Some(&vk(circuit)), &proof, &vec![1u8; count * 32])
+    };
+
+    assert!(verify(c, n_public).is_ok());
+    for count in [n_public - 1, n_public + 1, joinsplit_idx::n_public(n, m - 1)] {
+        assert_eq!(verify(c, count).unwrap_err(), CipherPayError::PublicInputCountMismatch.into());
+    }
+    // A VK for another shape can't stand in for this one
+    let other = circuit_id::joinsplit(n as u8, m as u8 - 1);
+    let err = verify_circuit_proof(&cfg, c, Some(&vk(other)), &proof, &vec![1u8; n_public * 32]);
+    assert_eq!(err.unwrap_err(), CipherPayError::VerifyingKeyCircuitMismatch.into());
+}
+
 // All other tests are commented out because the referenced modules don't exist yet
 // Uncomment these when the corresponding modules are implemented:
 
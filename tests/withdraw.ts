@@ -11,11 +11,11 @@ import {
 import { assert } from "chai";
 import fs from "fs";
 import path from "path";
+import { createHash } from "crypto";
 import {
   getAssociatedTokenAddressSync,
   ASSOCIATED_TOKEN_PROGRAM_ID,
   TOKEN_PROGRAM_ID,
-  createMint,
   getAccount,
   getOrCreateAssociatedTokenAccount,
   mintTo,
@@ -62,15 +62,18 @@ const WITHDRAW_VARIANT = (process.env.WITHDRAW_VARIANT || "withdraw").trim();
 const PROOF_PATH = path.resolve(__dirname, `../proofs/${WITHDRAW_VARIANT}_proof.bin`);
 const PUBSIG_PATH = path.resolve(__dirname, `../proofs/${WITHDRAW_VARIANT}_public_signals.bin`);
 
-// UPDATED public signals layout (7 × 32):
+// UPDATED public signals layout (10 × 32):
 // [0] nullifier,
 // [1] merkleRoot,
 // [2] recipientOwner_lo,
 // [3] recipientOwner_hi,
 // [4] recipientWalletPubKey,
 // [5] amount,
-// [6] tokenId
-const PUBSIG_COUNT = 7;
+// [6] tokenId,
+// [7] fee,
+// [8] relayer_lo,
+// [9] relayer_hi
+const PUBSIG_COUNT = 10;
 const FIELD_BYTES = 32;
 const PROOF_BYTES = 256;
 const PUBSIG_BYTES = PUBSIG_COUNT * FIELD_BYTES;
@@ -96,6 +99,12 @@ function bigIntToNumberSafe(x: bigint): number {
   }
   return Number(x);
 }
+// Canonical field element of a pubkey (LE), mirrors `utils::pubkey_to_field`
+function pubkeyToField(domain: string, key: PublicKey): Buffer {
+  const fe = createHash("sha256").update(domain).update(key.toBuffer()).digest();
+  fe[31] &= 0x1f;
+  return fe;
+}
 async function airdropIfNeeded(connection: Connection, pubkey: PublicKey, minLamports = 2 * LAMPORTS_PER_SOL) {
   const bal = await connection.getBalance(pubkey, "confirmed");
   if (bal < minLamports) {
@@ -123,6 +132,7 @@ function loadWithdrawProofAndSignals() {
   const s4_recipientWalletPubKey = split32(publicSignals, 4);
   const s5_amount = split32(publicSignals, 5);
   const s6_tokenId = split32(publicSignals, 6);
+  const s7_fee = split32(publicSignals, 7);
 
   return {
     proof,
@@ -135,6 +145,7 @@ function loadWithdrawProofAndSignals() {
       recipientWalletPubKey: s4_recipientWalletPubKey,
       amount: s5_amount,
       tokenId: s6_tokenId,
+      fee: s7_fee,
     },
   };
 }
@@ -157,6 +168,7 @@ describe("Shielded Withdraw - Real Program Integration", () => {
   // Shared state
   let tokenMint: PublicKey;
   let vaultPda: PublicKey;
+  let poolConfigPda: PublicKey;
   let vaultTokenAccount: PublicKey;
   let recipientOwner: PublicKey;
   let recipientTokenAccount: PublicKey;
@@ -169,18 +181,20 @@ describe("Shielded Withdraw - Real Program Integration", () => {
     console.log("📁 using proof files:", { PROOF_PATH, PUBSIG_PATH });
     await airdropIfNeeded(connection, provider.wallet.publicKey);
 
-    // 1) Fresh test mint (0 decimals)
-    tokenMint = await createMint(
-      provider.connection,
-      (provider.wallet as any).payer,
-      provider.wallet.publicKey,
-      null,
-      0
-    );
+    // 1) Pool mint (per-mint pools; initialized by `anchor run init`)
+    const poolMint = process.env.CP_TOKEN_MINT;
+    if (!poolMint) throw new Error("CP_TOKEN_MINT not set. Run `anchor run init` and export it.");
+    tokenMint = new PublicKey(poolMint);
 
-    // 2) Vault PDA (seed "vault" per IDL)
+    // 2) Vault PDA (seeds ["vault", mint] per IDL)
     [vaultPda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("vault")],
+      [Buffer.from("vault"), tokenMint.toBuffer()],
+      program.programId
+    );
+
+    // Pool limits PDA (seeds ["pool_config", mint]); may not exist (no limits)
+    [poolConfigPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_config"), tokenMint.toBuffer()],
       program.programId
     );
 
@@ -232,27 +246,14 @@ describe("Shielded Withdraw - Real Program Integration", () => {
 
     withdrawAmount = bigIntToNumberSafe(toBigIntLE(fields.amount));
 
-    // 6) Initialize root cache (seed "root_cache" per IDL)
+    // 6) Root cache of the tree holding the spent root (seeds ["root_cache", mint, tree_id_le])
+    const treeId = Buffer.alloc(4);
+    treeId.writeUInt32LE(Number(process.env.CP_TREE_ID ?? 0));
     [rootCachePda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("root_cache")],
+      [Buffer.from("root_cache"), tokenMint.toBuffer(), treeId],
       program.programId
     );
 
-    if ((program.methods as any).initializeRootCache) {
-      try {
-        await (program.methods as any)
-          .initializeRootCache()
-          .accounts({
-            rootCache: rootCachePda,
-            authority: provider.wallet.publicKey,
-            systemProgram: SystemProgram.programId,
-          })
-          .rpc();
-      } catch (_) {
-        // likely already initialized — ignore
-      }
-    }
-
     // 7) Seed vault with enough tokens
     const preVaultAcc = await getAccount(provider.connection, vaultTokenAccount).catch(() => null);
     const needsMint = preVaultAcc === null || Number(preVaultAcc.amount) < withdrawAmount;
@@ -271,7 +272,21 @@ describe("Shielded Withdraw - Real Program Integration", () => {
   it("Validates withdraw circuit outputs & sizes", async () => {
     const { proof, publicSignals } = loadWithdrawProofAndSignals();
     assert.equal(proof.length, PROOF_BYTES, "Groth16 proof should be 256 bytes");
-    assert.equal(publicSignals.length, PUBSIG_BYTES, "Withdraw public signals should be 224 bytes (7 × 32)");
+    assert.equal(publicSignals.length, PUBSIG_BYTES, "Withdraw public signals should be 320 bytes (10 × 32)");
+  });
+
+  it("Binds tokenId to the pool mint and recipientWalletPubKey to the recipient ATA", async () => {
+    const { fields } = loadWithdrawProofAndSignals();
+    assert.deepEqual(
+      Buffer.from(fields.tokenId),
+      pubkeyToField("cipherpay:token_id", tokenMint),
+      "proof tokenId must be token_id_for(mint)"
+    );
+    assert.deepEqual(
+      Buffer.from(fields.recipientWalletPubKey),
+      pubkeyToField("cipherpay:recipient_ata", recipientTokenAccount),
+      "proof recipientWalletPubKey must be recipient_wallet_field(recipient ATA)"
+    );
   });
 
   it("Executes shielded withdraw with ZK proof verification", async () => {
@@ -294,12 +309,20 @@ describe("Shielded Withdraw - Real Program Integration", () => {
         .shieldedWithdraw(Buffer.from(fields.nullifier), proof, publicSignals)
         .accounts({
           payer: provider.wallet.publicKey,
+          tree: null, // full withdraw: no change note appended
           rootCache: rootCachePda,
+          treeRootCache: null,
+          commitmentQueue: null, // tree not in sequencer mode
           nullifierRecord,
+          nullifierShard: null, // legacy per-nullifier PDA mode
+          legacyNullifierRecord: null,
+          poolConfig: poolConfigPda,
           vaultPda,
           vaultTokenAccount,
           recipientOwner,
           recipientTokenAccount,
+          relayerTokenAccount: null, // self-submitted (fee must be 0)
+          pendingWithdrawal: null, // below the pool's large-withdrawal threshold
           tokenMint,
           systemProgram: SystemProgram.programId,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -292,3 +292,32 @@ fn verify_withdraw_fails_if_proof_byte_flipped() {
     assert!(res.is_err(), "withdraw verification unexpectedly succeeded with corrupted proof");
     println!("verify_withdraw (corrupted proof): {:?}", res.err());
 }
+
+// ======================= VK REGISTRY (verify against supplied VK) =======================
+
+#[test]
+fn validate_vk_bytes_matches_circuit_shape() {
+    solana_verifier::validate_vk_bytes(DEPOSIT_VK_BE, solana_verifier::DEPOSIT_N_PUBLIC)
+        .expect("deposit vk should validate");
+    solana_verifier::validate_vk_bytes(TRANSFER_VK_BE, solana_verifier::TRANSFER_N_PUBLIC)
+        .expect("transfer vk should validate");
+    solana_verifier::validate_vk_bytes(WITHDRAW_VK_BE, solana_verifier::WITHDRAW_N_PUBLIC)
+        .expect("withdraw vk should validate");
+
+    // Wrong circuit shape and truncated blobs are rejected (including pre-tokenId deposit VKs)
+    assert!(solana_verifier::validate_vk_bytes(DEPOSIT_VK_BE, solana_verifier::DEPOSIT_N_PUBLIC - 1).is_err());
+    assert!(solana_verifier::validate_vk_bytes(DEPOSIT_VK_BE, solana_verifier::TRANSFER_N_PUBLIC).is_err());
+    assert!(solana_verifier::validate_vk_bytes(&DEPOSIT_VK_BE[..DEPOSIT_VK_BE.len() - 1], solana_verifier::DEPOSIT_N_PUBLIC).is_err());
+}
+
+#[test]
+fn verify_with_vk_matches_embedded_verifiers() {
+    solana_verifier::verify_with_vk(DEPOSIT_VK_BE, DEPOSIT_PROOF_LE, DEPOSIT_PUBLICS_LE)
+        .expect("verify_with_vk(deposit) failed");
+    solana_verifier::verify_with_vk(TRANSFER_VK_BE, TRANSFER_PROOF_LE, TRANSFER_PUBLICS_LE)
+        .expect("verify_with_vk(transfer) failed");
+
+    // A VK of another circuit must not verify the proof
+    let res = solana_verifier::verify_with_vk(WITHDRAW_VK_BE, DEPOSIT_PROOF_LE, DEPOSIT_PUBLICS_LE);
+    assert!(res.is_err(), "deposit proof unexpectedly verified under the withdraw vk");
+}